@@ -1,4 +1,8 @@
-use kvs::{KvStore, SledKvsEngine};
+// Not every benchmark uses all of the helpers
+#![allow(dead_code)]
+
+use kvs::engines::KvStoreConfig;
+use kvs::{KvStore, KvsEngine, SledKvsEngine};
 use rand::{distributions::Alphanumeric, prelude::*};
 use tempfile::TempDir;
 
@@ -8,6 +12,23 @@ pub fn prep_kv_store() -> (KvStore, TempDir) {
     (engine, tmpdir)
 }
 
+/// Opens a key-value store that is pre-populated with the given pairs, the store is reopened after
+/// being populated so that every value is read from a sealed log
+pub fn prep_sealed_kv_store(
+    config: KvStoreConfig,
+    kv_pairs: &[(String, String)],
+) -> (KvStore, TempDir) {
+    let tmpdir = TempDir::new().unwrap();
+    let engine = KvStore::open_with_config(tmpdir.path(), config.clone()).unwrap();
+    kv_pairs
+        .iter()
+        .cloned()
+        .for_each(|(k, v)| engine.set(k, v).unwrap());
+    drop(engine);
+    let engine = KvStore::open_with_config(tmpdir.path(), config).unwrap();
+    (engine, tmpdir)
+}
+
 pub fn prep_sled() -> (SledKvsEngine, TempDir) {
     let tmpdir = TempDir::new().unwrap();
     let db = sled::Config::default().path(tmpdir.path()).open().unwrap();
@@ -25,7 +46,6 @@ where
     R: Rng,
 {
     (0..size)
-        .into_iter()
         .map(|_| rand_key_value(rng, key_size, val_size))
         .collect()
 }
@@ -5,7 +5,12 @@ use criterion::{
     black_box, criterion_group, criterion_main, BatchSize, Bencher, BenchmarkId, Criterion,
     Throughput,
 };
-use kvs::{engines::Engine, KvsEngine};
+use crossbeam_utils::sync::WaitGroup;
+use kvs::engines::{Engine, KvStoreConfig};
+use kvs::thread_pool::{
+    RayonThreadPool, SharedQueueThreadPool, ThreadPool, ThreadPoolKind, WorkStealingThreadPool,
+};
+use kvs::{KvStore, KvsEngine};
 use rand::prelude::*;
 use rayon::ThreadPoolBuilder;
 use tempfile::TempDir;
@@ -19,7 +24,7 @@ pub fn concurrent_write_bulk(c: &mut Criterion) {
     g.throughput(Throughput::Bytes((ITER * (KEY_SIZE + VAL_SIZE)) as u64));
 
     let phys_cpus = num_cpus::get_physical();
-    (2..=phys_cpus*2).into_iter().step_by(2).for_each(|nthreads| {
+    (2..=phys_cpus * 2).step_by(2).for_each(|nthreads| {
         g.bench_with_input(
             BenchmarkId::new("kvs", nthreads),
             &(Engine::Kvs, nthreads),
@@ -70,8 +75,9 @@ fn concurrent_write_bulk_bench(b: &mut Bencher, (engine, nthreads): &(Engine, us
     }
 }
 
-fn concurrent_write_bulk_bench_iter<E>((engine, kv_pairs, _tmpdir): (E, Vec<(String, String)>, TempDir))
-where
+fn concurrent_write_bulk_bench_iter<E>(
+    (engine, kv_pairs, _tmpdir): (E, Vec<(String, String)>, TempDir),
+) where
     E: KvsEngine,
 {
     rayon::scope(move |s| {
@@ -87,22 +93,27 @@ pub fn concurrent_read_bulk(c: &mut Criterion) {
     g.throughput(Throughput::Bytes((ITER * (KEY_SIZE)) as u64));
 
     let phys_cpus = num_cpus::get_physical();
-    (2..=phys_cpus*2).into_iter().step_by(2).for_each(|nthreads| {
+    (2..=phys_cpus * 2).step_by(2).for_each(|nthreads| {
         g.bench_with_input(
             BenchmarkId::new("kvs", nthreads),
-            &(Engine::Kvs, nthreads),
+            &(Engine::Kvs, false, nthreads),
+            concurrent_read_bulk_bench,
+        );
+        g.bench_with_input(
+            BenchmarkId::new("kvs-mmap", nthreads),
+            &(Engine::Kvs, true, nthreads),
             concurrent_read_bulk_bench,
         );
         g.bench_with_input(
             BenchmarkId::new("sled", nthreads),
-            &(Engine::Sled, nthreads),
+            &(Engine::Sled, false, nthreads),
             concurrent_read_bulk_bench,
         );
     });
     g.finish();
 }
 
-fn concurrent_read_bulk_bench(b: &mut Bencher, (engine, nthreads): &(Engine, usize)) {
+fn concurrent_read_bulk_bench(b: &mut Bencher, (engine, mmap, nthreads): &(Engine, bool, usize)) {
     let mut rng = StdRng::from_seed([0u8; 32]);
     let kv_pairs = prebuilt_kv_pairs(&mut rng, ITER, KEY_SIZE, VAL_SIZE);
     let pool = ThreadPoolBuilder::new()
@@ -112,11 +123,8 @@ fn concurrent_read_bulk_bench(b: &mut Bencher, (engine, nthreads): &(Engine, usi
 
     match *engine {
         Engine::Kvs => {
-            let (engine, _tmpdir) = prep_kv_store();
-            kv_pairs
-                .iter()
-                .cloned()
-                .for_each(|(k, v)| engine.set(k, v).unwrap());
+            let config = KvStoreConfig::default().mmap(*mmap);
+            let (engine, _tmpdir) = prep_sealed_kv_store(config, &kv_pairs);
 
             pool.install(move || {
                 b.iter_batched(
@@ -164,5 +172,77 @@ where
     })
 }
 
+/// Many short reads spawned one by one from outside the pool, like the requests that a server
+/// hands to its pool, so the cost of handing jobs over dominates
+pub fn concurrent_pool_read(c: &mut Criterion) {
+    let mut g = c.benchmark_group("concurrent_pool_read");
+    g.throughput(Throughput::Elements(ITER as u64));
+
+    let phys_cpus = num_cpus::get_physical();
+    (2..=phys_cpus * 2).step_by(2).for_each(|nthreads| {
+        for kind in [
+            ThreadPoolKind::SharedQueue,
+            ThreadPoolKind::Rayon,
+            ThreadPoolKind::WorkStealing,
+        ] {
+            g.bench_with_input(
+                BenchmarkId::new(kind.as_str(), nthreads),
+                &(kind, nthreads as u32),
+                concurrent_pool_read_bench,
+            );
+        }
+    });
+    g.finish();
+}
+
+fn concurrent_pool_read_bench(b: &mut Bencher, (kind, nthreads): &(ThreadPoolKind, u32)) {
+    let mut rng = StdRng::from_seed([0u8; 32]);
+    let kv_pairs = prebuilt_kv_pairs(&mut rng, ITER, 16, 16);
+    let (engine, _tmpdir) = prep_sealed_kv_store(KvStoreConfig::default(), &kv_pairs);
+
+    match *kind {
+        ThreadPoolKind::SharedQueue => {
+            let pool = SharedQueueThreadPool::new(*nthreads).unwrap();
+            concurrent_pool_read_bench_iter(b, &pool, &engine, &kv_pairs);
+        }
+        ThreadPoolKind::Rayon => {
+            let pool = RayonThreadPool::new(*nthreads).unwrap();
+            concurrent_pool_read_bench_iter(b, &pool, &engine, &kv_pairs);
+        }
+        ThreadPoolKind::WorkStealing => {
+            let pool = WorkStealingThreadPool::new(*nthreads).unwrap();
+            concurrent_pool_read_bench_iter(b, &pool, &engine, &kv_pairs);
+        }
+        ThreadPoolKind::Naive => unreachable!("a thread per job is not compared"),
+    }
+}
+
+fn concurrent_pool_read_bench_iter<P>(
+    b: &mut Bencher,
+    pool: &P,
+    engine: &KvStore,
+    kv_pairs: &[(String, String)],
+) where
+    P: ThreadPool,
+{
+    b.iter(|| {
+        let wg = WaitGroup::new();
+        for (k, v) in kv_pairs.iter().cloned() {
+            let engine = engine.clone();
+            let wg = wg.clone();
+            pool.spawn(move || {
+                assert_eq!(Some(v), engine.get(black_box(k)).unwrap());
+                drop(wg);
+            });
+        }
+        wg.wait();
+    })
+}
+
 criterion_main!(benches);
-criterion_group!(benches, concurrent_write_bulk, concurrent_read_bulk,);
+criterion_group!(
+    benches,
+    concurrent_write_bulk,
+    concurrent_read_bulk,
+    concurrent_pool_read,
+);
@@ -50,8 +50,9 @@ fn sequential_write_bulk_bench(b: &mut Bencher, engine: &Engine) {
     }
 }
 
-fn sequential_write_bulk_bench_iter<E>((engine, kv_pairs, _tmpdir): (E, Vec<(String, String)>, TempDir))
-where
+fn sequential_write_bulk_bench_iter<E>(
+    (engine, kv_pairs, _tmpdir): (E, Vec<(String, String)>, TempDir),
+) where
     E: KvsEngine,
 {
     kv_pairs
@@ -1,39 +1,524 @@
-use kvs::networking::JsonKvsClient;
-use kvs::KvsClient;
-use std::net::SocketAddr;
+use kvs::engines::WriteOp;
+use kvs::networking::{
+    Addr, AdminCommand, AdminReply, ClusterConfig, FailoverConfig, FailoverKvsClient,
+    JsonKvsClient, ShardedKvsClient,
+};
+use kvs::replication::Role;
+use kvs::{Error, ErrorKind, KvsClient};
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use structopt::clap::AppSettings;
+use structopt::clap::ErrorKind::{HelpDisplayed, VersionDisplayed};
 use structopt::StructOpt;
 
+/// File in the home directory that keeps the history of the shell
+const HISTORY_FILENAME: &str = ".kvs_history";
+
+/// Exit code of failures that have no dedicated code
+const EXIT_FAILURE: i32 = 1;
+/// Exit code when the key does not exist
+const EXIT_KEY_NOT_FOUND: i32 = 2;
+/// Exit code when the stored value can not be used for the operation
+const EXIT_INVALID_VALUE: i32 = 3;
+/// Exit code when the server could not be reached
+const EXIT_UNAVAILABLE: i32 = 4;
+/// Exit code when reading or writing data failed, either on the connection or in the server's
+/// storage
+const EXIT_IO: i32 = 5;
+/// Exit code when the client and the server could not understand each other
+const EXIT_PROTOCOL: i32 = 6;
+/// Exit code when the server refused the request because it reached one of its limits
+const EXIT_BUSY: i32 = 7;
+/// Exit code when a write was sent to a replica
+const EXIT_READ_ONLY: i32 = 8;
+/// Exit code when the token was wrong or its user can not access the keys
+const EXIT_DENIED: i32 = 9;
+
 fn main() {
-    if let Err(err) = run() {
+    let opt = ClientCliOpt::from_args();
+    if opt.cluster.is_none() && matches!(opt.sub_cmd, ClientCliSubCommand::Rebalance { .. }) {
+        let err = Error::new(
+            ErrorKind::InvalidValue,
+            "Rebalancing needs the config of the cluster, given with --cluster",
+        );
+        eprintln!("{}", err);
+        std::process::exit(exit_code(&err));
+    }
+
+    if let ClientCliSubCommand::Watch { prefix, addr } = opt.sub_cmd {
+        let result = match opt.cluster {
+            Some(_) => Err(Error::new(
+                ErrorKind::Unsupported,
+                "Watching takes the address of a server instead of a cluster",
+            )),
+            None => watch(prefix, &addr, opt.token.as_deref()),
+        };
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            std::process::exit(exit_code(&err));
+        }
+        return;
+    }
+
+    let result = Client::connect(
+        opt.cluster.as_deref(),
+        opt.sub_cmd.addrs(),
+        opt.token.clone(),
+    )
+    .and_then(|mut kvs_client| match opt.sub_cmd {
+        ClientCliSubCommand::Shell { .. } => shell(&mut kvs_client),
+        ClientCliSubCommand::Exec { file, .. } => exec(&mut kvs_client, file.as_deref()),
+        sub_cmd => kvs_client.run(sub_cmd, false),
+    });
+    if let Err(err) = result {
         eprintln!("{}", err);
-        std::process::exit(1);
+        std::process::exit(exit_code(&err));
     }
 }
 
-fn run() -> kvs::Result<()> {
-    let opt = ClientCliOpt::from_args();
-    match opt.sub_cmd {
-        ClientCliSubCommand::Set { key, val, addr } => {
-            let mut kvs_client = JsonKvsClient::connect(addr)?;
-            kvs_client.set(key, val)?;
+fn exit_code(err: &kvs::Error) -> i32 {
+    match err.kind() {
+        ErrorKind::KeyNotFound => EXIT_KEY_NOT_FOUND,
+        ErrorKind::InvalidValue => EXIT_INVALID_VALUE,
+        ErrorKind::Io | ErrorKind::CorruptedLog | ErrorKind::CorruptedIndex => EXIT_IO,
+        ErrorKind::InvalidNetworkMessage | ErrorKind::Serialization => EXIT_PROTOCOL,
+        ErrorKind::ServerBusy => EXIT_BUSY,
+        ErrorKind::ReadOnly => EXIT_READ_ONLY,
+        ErrorKind::Unauthenticated | ErrorKind::PermissionDenied => EXIT_DENIED,
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// Either a client of one server, which fails over between its addresses, or of a cluster
+enum Client {
+    Server(FailoverKvsClient),
+    Cluster(ShardedKvsClient),
+}
+
+impl Client {
+    /// Connects to the cluster if its config is given, otherwise to the servers, or exits if
+    /// they could not be reached
+    fn connect(cluster: Option<&Path>, addrs: &[Addr], token: Option<String>) -> kvs::Result<Self> {
+        let failover = FailoverConfig::default().token(token);
+        let result = match cluster {
+            Some(path) => {
+                let config = ClusterConfig::from_file(path)?.failover(failover);
+                ShardedKvsClient::connect_with_config(config).map(Self::Cluster)
+            }
+            None => {
+                FailoverKvsClient::connect_with_config(addrs.to_vec(), failover).map(Self::Server)
+            }
+        };
+        match result {
+            Ok(kvs_client) => Ok(kvs_client),
+            Err(err) if err.kind() == ErrorKind::Unauthenticated => Err(err),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(EXIT_UNAVAILABLE);
+            }
         }
-        ClientCliSubCommand::Get { key, addr } => {
-            let mut kvs_client = JsonKvsClient::connect(addr)?;
-            match kvs_client.get(key)? {
-                Some(val) => println!("{}", val),
-                None => println!("Key not found"),
+    }
+
+    /// Runs one command. In a session, commands that print nothing on success print `OK`, so
+    /// that every command of a script has a result.
+    fn run(&mut self, sub_cmd: ClientCliSubCommand, session: bool) -> kvs::Result<()> {
+        let silent = sub_cmd.is_silent();
+        match (self, sub_cmd) {
+            (_, ClientCliSubCommand::Shell { .. }) | (_, ClientCliSubCommand::Exec { .. }) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Sessions can not be started from a session",
+                ));
+            }
+            (_, ClientCliSubCommand::Watch { .. }) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Watching keeps the connection to itself and can not be done in a session",
+                ));
+            }
+            (Self::Server(_), ClientCliSubCommand::Rebalance { .. }) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidValue,
+                    "Rebalancing needs the config of the cluster, given with --cluster",
+                ));
             }
+            (Self::Server(kvs_client), ClientCliSubCommand::Promote { .. }) => {
+                kvs_client.promote()?;
+            }
+            (Self::Server(kvs_client), ClientCliSubCommand::ReplicationStatus { .. }) => {
+                let status = kvs_client.replication_status()?;
+                match status.role {
+                    Role::Primary => println!("role: primary"),
+                    Role::Replica => println!("role: replica"),
+                }
+                if let Some(primary) = status.primary {
+                    println!("primary: {}", primary);
+                    println!("connected: {}", status.connected);
+                }
+                if let Some(position) = status.position {
+                    println!("position: {}:{}", position.epoch, position.seq);
+                }
+                if let Some(lag) = status.lag {
+                    println!("lag: {}", lag);
+                }
+            }
+            (
+                Self::Server(kvs_client),
+                ClientCliSubCommand::Admin {
+                    command,
+                    arg,
+                    admin_token,
+                    ..
+                },
+            ) => {
+                let command = admin_command(&command, arg)?;
+                match kvs_client.admin(command, admin_token.as_deref())? {
+                    AdminReply::Done => {}
+                    AdminReply::Stats(stats) => {
+                        for (name, value) in stats {
+                            println!("{}\t{}", name, value);
+                        }
+                    }
+                    AdminReply::Clients(clients) => {
+                        for client in clients {
+                            let peer_addr = client.peer_addr.as_deref().unwrap_or("-");
+                            println!("{}\t{}\t{}", client.id, peer_addr, client.connected_secs);
+                        }
+                    }
+                }
+            }
+            (Self::Server(kvs_client), sub_cmd) => run_keys(kvs_client, sub_cmd, session)?,
+            (Self::Cluster(_), ClientCliSubCommand::Promote { .. })
+            | (Self::Cluster(_), ClientCliSubCommand::ReplicationStatus { .. }) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Replication commands take the address of a server instead of a cluster",
+                ));
+            }
+            (Self::Cluster(_), ClientCliSubCommand::Admin { .. }) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Admin commands take the address of a server instead of a cluster",
+                ));
+            }
+            (Self::Cluster(kvs_client), ClientCliSubCommand::Rebalance { from }) => {
+                let previous = match from {
+                    Some(path) => ClusterConfig::from_file(path)?.nodes().to_vec(),
+                    None => Vec::new(),
+                };
+                println!("{}", kvs_client.rebalance(&previous)?);
+            }
+            (Self::Cluster(kvs_client), sub_cmd) => run_keys(kvs_client, sub_cmd, session)?,
+        }
+        if session && silent {
+            println!("OK");
+        }
+        Ok(())
+    }
+
+    /// Parses and runs one line of a session
+    fn run_line(&mut self, line: &str) -> kvs::Result<()> {
+        let words = shell_words::split(line)
+            .map_err(|err| Error::new(ErrorKind::InvalidValue, err.to_string()))?;
+        let args = std::iter::once("kvs-client".to_owned()).chain(words);
+        let sub_cmd = match ClientCliSubCommand::from_iter_safe(args) {
+            Ok(sub_cmd) => sub_cmd,
+            Err(err) if matches!(err.kind, HelpDisplayed | VersionDisplayed) => {
+                println!("{}", err.message);
+                return Ok(());
+            }
+            Err(err) => {
+                // the usage that follows the error is the usage of the whole program
+                let message = err.message.lines().next().unwrap_or_default();
+                let message = message.trim_start_matches("error: ");
+                return Err(Error::new(ErrorKind::InvalidValue, message));
+            }
+        };
+        self.run(sub_cmd, true)
+    }
+}
+
+/// Reads commands from the terminal until `quit` or the end of input, the history is kept in
+/// `~/.kvs_history`
+fn shell(kvs_client: &mut Client) -> kvs::Result<()> {
+    let mut editor = Editor::<()>::new();
+    let history = env::var_os("HOME").map(|home| Path::new(&home).join(HISTORY_FILENAME));
+    if let Some(history) = &history {
+        // there is no history the first time
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let line = match editor.readline("kvs> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(Error::new(ErrorKind::Io, err.to_string())),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line);
+        if line == "quit" || line == "exit" {
+            break;
+        }
+        if let Err(err) = kvs_client.run_line(line) {
+            eprintln!("{}", err);
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(err) = editor.save_history(history) {
+            eprintln!("Could not save the history: {}", err);
         }
-        ClientCliSubCommand::Rm { key, addr } => {
-            let mut kvs_client = JsonKvsClient::connect(addr)?;
+    }
+    Ok(())
+}
+
+/// Runs the commands of a script, one per line, or of stdin if no script is given. Every line is
+/// run, failed lines are reported with their line number and the first failure decides the exit
+/// code.
+fn exec(kvs_client: &mut Client, file: Option<&Path>) -> kvs::Result<()> {
+    let reader: Box<dyn BufRead> = match file {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+
+    let mut first_err = None;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Err(err) = kvs_client.run_line(line) {
+            eprintln!("line {}: {}", i + 1, err);
+            first_err.get_or_insert(err);
+        }
+    }
+    match first_err {
+        Some(err) => std::process::exit(exit_code(&err)),
+        None => Ok(()),
+    }
+}
+
+/// Prints the operations on the keys that start with `prefix` as the server commits them, one
+/// per line, until the server closes the connection. The first server of `addrs` that can be
+/// reached is watched.
+fn watch(prefix: String, addrs: &[Addr], token: Option<&str>) -> kvs::Result<()> {
+    let mut result = Err(Error::new(ErrorKind::InvalidValue, "No address was given"));
+    for addr in addrs {
+        result = JsonKvsClient::connect(addr.clone());
+        if result.is_ok() {
+            break;
+        }
+    }
+    let mut kvs_client = match result {
+        Ok(kvs_client) => kvs_client,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(EXIT_UNAVAILABLE);
+        }
+    };
+    if let Some(token) = token {
+        kvs_client.authenticate(token)?;
+    }
+
+    for op in kvs_client.watch(prefix)? {
+        match op? {
+            WriteOp::Set { key, value } => println!("set\t{}\t{}", key, value),
+            WriteOp::Remove { key } => println!("rm\t{}", key),
+        }
+    }
+    Ok(())
+}
+
+/// Runs the commands that read and write keys, which work the same on a single server and on a
+/// cluster. In a session, the commands on many keys take their keys from the command line only.
+fn run_keys<C>(kvs_client: &mut C, sub_cmd: ClientCliSubCommand, session: bool) -> kvs::Result<()>
+where
+    C: KvsClient,
+{
+    match sub_cmd {
+        ClientCliSubCommand::Set { key, val, .. } => {
+            kvs_client.set(key, val)?;
+        }
+        ClientCliSubCommand::Get { key, .. } => match kvs_client.get(key)? {
+            Some(val) => println!("{}", val),
+            None => println!("Key not found"),
+        },
+        ClientCliSubCommand::Rm { key, .. } => {
             kvs_client.remove(key)?;
         }
+        ClientCliSubCommand::Incr { key, delta, .. } => {
+            println!("{}", kvs_client.increment(key, delta)?);
+        }
+        ClientCliSubCommand::Append { key, suffix, .. } => {
+            kvs_client.append(key, suffix)?;
+        }
+        ClientCliSubCommand::Mget {
+            keys, batch_size, ..
+        } => {
+            for_each_batch(batch_size, input(keys, session)?, |keys| {
+                for val in kvs_client.multi_get(keys)? {
+                    match val {
+                        Some(val) => println!("{}", val),
+                        None => println!("Key not found"),
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        ClientCliSubCommand::Mset {
+            pairs, batch_size, ..
+        } => {
+            let pairs: Box<dyn Iterator<Item = kvs::Result<(String, String)>>> = if pairs.is_empty()
+            {
+                Box::new(input(pairs, session)?.map(|line| {
+                    let line = line?;
+                    match line.split_once('\t') {
+                        Some((key, val)) => Ok((key.to_owned(), val.to_owned())),
+                        None => Err(Error::new(
+                            ErrorKind::InvalidValue,
+                            format!("Line '{}' is not a tab-separated key and value", line),
+                        )),
+                    }
+                }))
+            } else if pairs.len() % 2 == 0 {
+                let mut pairs = pairs.into_iter();
+                Box::new(std::iter::from_fn(move || {
+                    Some(Ok((pairs.next()?, pairs.next()?)))
+                }))
+            } else {
+                return Err(Error::new(
+                    ErrorKind::InvalidValue,
+                    "Every key needs a value",
+                ));
+            };
+            for_each_batch(batch_size, pairs, |pairs| kvs_client.multi_set(pairs))?;
+        }
+        ClientCliSubCommand::Mdel {
+            keys, batch_size, ..
+        } => {
+            let mut removed = 0;
+            for_each_batch(batch_size, input(keys, session)?, |keys| {
+                removed += kvs_client.multi_remove(keys)?;
+                Ok(())
+            })?;
+            println!("{}", removed);
+        }
+        ClientCliSubCommand::Promote { .. }
+        | ClientCliSubCommand::ReplicationStatus { .. }
+        | ClientCliSubCommand::Admin { .. }
+        | ClientCliSubCommand::Rebalance { .. }
+        | ClientCliSubCommand::Shell { .. }
+        | ClientCliSubCommand::Exec { .. }
+        | ClientCliSubCommand::Watch { .. } => unreachable!("not a key command"),
+    }
+    Ok(())
+}
+
+/// Returns the admin command that the name and the argument of `kvs-client admin` stand for
+fn admin_command(name: &str, arg: Option<String>) -> kvs::Result<AdminCommand> {
+    let missing = |what: &str| {
+        Error::new(
+            ErrorKind::InvalidValue,
+            format!("Admin command {} needs {}", name, what),
+        )
+    };
+    let command = match name {
+        "compact" => AdminCommand::Compact,
+        "flush" => AdminCommand::Flush,
+        "checkpoint" => {
+            let path = arg.ok_or_else(|| missing("the path of the checkpoint"))?;
+            return Ok(AdminCommand::Checkpoint { path });
+        }
+        "stats" => AdminCommand::Stats,
+        "log-level" => {
+            let level = arg.ok_or_else(|| missing("a log level"))?;
+            return Ok(AdminCommand::SetLogLevel { level });
+        }
+        "clients" => AdminCommand::Clients,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidValue,
+                format!("Could not found admin command named '{}'", name),
+            ))
+        }
+    };
+    match arg {
+        Some(arg) => Err(Error::new(
+            ErrorKind::InvalidValue,
+            format!("Admin command {} takes no argument, got '{}'", name, arg),
+        )),
+        None => Ok(command),
+    }
+}
+
+/// Returns the arguments of a command on many keys, or the non-empty lines of stdin if there are
+/// none
+fn input(
+    args: Vec<String>,
+    session: bool,
+) -> kvs::Result<Box<dyn Iterator<Item = kvs::Result<String>>>> {
+    if !args.is_empty() {
+        return Ok(Box::new(args.into_iter().map(Ok)));
+    }
+    if session {
+        // stdin is where the session reads its commands from
+        return Err(Error::new(
+            ErrorKind::InvalidValue,
+            "Keys must be given as arguments in a session",
+        ));
+    }
+    let lines = io::stdin().lock().lines();
+    Ok(Box::new(lines.filter_map(|line| match line {
+        Ok(line) if line.is_empty() => None,
+        line => Some(line.map_err(Error::from)),
+    })))
+}
+
+/// Passes the items on in batches of at most `batch_size` items
+fn for_each_batch<T, I, F>(batch_size: usize, items: I, mut f: F) -> kvs::Result<()>
+where
+    I: Iterator<Item = kvs::Result<T>>,
+    F: FnMut(Vec<T>) -> kvs::Result<()>,
+{
+    let mut batch = Vec::with_capacity(batch_size);
+    for item in items {
+        batch.push(item?);
+        if batch.len() >= batch_size {
+            f(std::mem::replace(
+                &mut batch,
+                Vec::with_capacity(batch_size),
+            ))?;
+        }
+    }
+    if !batch.is_empty() {
+        f(batch)?;
     }
     Ok(())
 }
 
 #[derive(StructOpt)]
 struct ClientCliOpt {
+    #[structopt(
+        long = "cluster",
+        about = "TOML file with the nodes of a cluster, keys are spread over the nodes instead of sent to --addr",
+        global = true
+    )]
+    cluster: Option<PathBuf>,
+    #[structopt(
+        long = "token",
+        about = "Token that the connections authenticate with, for servers that require authentication",
+        global = true
+    )]
+    token: Option<String>,
     #[structopt(subcommand)]
     sub_cmd: ClientCliSubCommand,
 }
@@ -48,10 +533,11 @@ enum ClientCliSubCommand {
         val: String,
         #[structopt(
             long = "addr",
-            about = "IP address of the key-value store",
-            default_value = "127.0.0.1:4000"
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
         )]
-        addr: SocketAddr,
+        addr: Vec<Addr>,
     },
 
     #[structopt(about = "Get a value from a key in the key-value store")]
@@ -60,10 +546,11 @@ enum ClientCliSubCommand {
         key: String,
         #[structopt(
             long = "addr",
-            about = "IP address of the key-value store",
-            default_value = "127.0.0.1:4000"
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
         )]
-        addr: SocketAddr,
+        addr: Vec<Addr>,
     },
 
     #[structopt(about = "Remove a key from the key-value store")]
@@ -72,9 +559,253 @@ enum ClientCliSubCommand {
         key: String,
         #[structopt(
             long = "addr",
-            about = "IP address of the key-value store",
-            default_value = "127.0.0.1:4000"
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
         )]
-        addr: SocketAddr,
+        addr: Vec<Addr>,
     },
+
+    #[structopt(
+        about = "Add to the integer value of a key in the key-value store",
+        setting = AppSettings::AllowNegativeNumbers
+    )]
+    Incr {
+        #[structopt(name = "KEY")]
+        key: String,
+        #[structopt(name = "DELTA", default_value = "1")]
+        delta: i64,
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(about = "Append to the value of a key in the key-value store")]
+    Append {
+        #[structopt(name = "KEY")]
+        key: String,
+        #[structopt(name = "SUFFIX")]
+        suffix: String,
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(
+        about = "Get the values of the keys, which are read from stdin one key per line if none are given"
+    )]
+    Mget {
+        #[structopt(name = "KEY")]
+        keys: Vec<String>,
+        #[structopt(
+            long = "batch-size",
+            about = "Number of keys that are sent in one request",
+            default_value = "1000"
+        )]
+        batch_size: usize,
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(
+        about = "Set the keys to the values, which are read from stdin one tab-separated pair per line if none are given"
+    )]
+    Mset {
+        #[structopt(name = "KEY VALUE")]
+        pairs: Vec<String>,
+        #[structopt(
+            long = "batch-size",
+            about = "Number of pairs that are sent in one request, each request is atomic",
+            default_value = "1000"
+        )]
+        batch_size: usize,
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(
+        about = "Remove the keys, which are read from stdin one key per line if none are given"
+    )]
+    Mdel {
+        #[structopt(name = "KEY")]
+        keys: Vec<String>,
+        #[structopt(
+            long = "batch-size",
+            about = "Number of keys that are sent in one request",
+            default_value = "1000"
+        )]
+        batch_size: usize,
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(about = "Make a replica stop following its primary and accept writes")]
+    Promote {
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(about = "Show the role of a server and how far a replica is behind its primary")]
+    ReplicationStatus {
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(
+        about = "Run a maintenance command on a server that accepts admin requests: compact, flush, checkpoint PATH, stats, log-level LEVEL or clients"
+    )]
+    Admin {
+        #[structopt(
+            name = "COMMAND",
+            possible_values = &["compact", "flush", "checkpoint", "stats", "log-level", "clients"]
+        )]
+        command: String,
+        #[structopt(
+            name = "ARG",
+            about = "Directory on the server's host that the checkpoint is written to, or the new log level"
+        )]
+        arg: Option<String>,
+        #[structopt(
+            long = "admin-token",
+            about = "Admin token of the server, if it has one"
+        )]
+        admin_token: Option<String>,
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(
+        about = "Move the keys of a cluster to the nodes that own them, prints the number of moved keys"
+    )]
+    Rebalance {
+        #[structopt(
+            long = "from",
+            about = "TOML file with the nodes of the cluster before it changed, its nodes that were removed are emptied"
+        )]
+        from: Option<PathBuf>,
+    },
+
+    #[structopt(
+        about = "Read commands from the terminal and run them on one connection, until quit or Ctrl-D"
+    )]
+    Shell {
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(
+        about = "Run the commands of a script on one connection, one command per line, lines that start with # are skipped"
+    )]
+    Exec {
+        #[structopt(
+            short = "f",
+            long = "file",
+            about = "Script that is run, stdin is read if it is not given"
+        )]
+        file: Option<PathBuf>,
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fail over between servers",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+
+    #[structopt(
+        about = "Print the changes of the keys that start with the prefix as they are committed, one change per line"
+    )]
+    Watch {
+        #[structopt(
+            name = "PREFIX",
+            about = "Prefix of the watched keys, all keys are watched if it is not given",
+            default_value = ""
+        )]
+        prefix: String,
+        #[structopt(
+            long = "addr",
+            about = "IP address or unix:PATH of the key-value store, can be given more than once to fall back to the next server if one can not be reached",
+            default_value = "127.0.0.1:4000",
+            number_of_values = 1
+        )]
+        addr: Vec<Addr>,
+    },
+}
+
+impl ClientCliSubCommand {
+    fn addrs(&self) -> &[Addr] {
+        match self {
+            Self::Set { addr, .. }
+            | Self::Get { addr, .. }
+            | Self::Rm { addr, .. }
+            | Self::Incr { addr, .. }
+            | Self::Append { addr, .. }
+            | Self::Mget { addr, .. }
+            | Self::Mset { addr, .. }
+            | Self::Mdel { addr, .. }
+            | Self::Promote { addr }
+            | Self::ReplicationStatus { addr }
+            | Self::Admin { addr, .. }
+            | Self::Shell { addr }
+            | Self::Exec { addr, .. }
+            | Self::Watch { addr, .. } => addr,
+            Self::Rebalance { .. } => &[],
+        }
+    }
+
+    /// Returns `true` if the command prints nothing when it succeeds
+    fn is_silent(&self) -> bool {
+        match self {
+            Self::Set { .. }
+            | Self::Rm { .. }
+            | Self::Append { .. }
+            | Self::Mset { .. }
+            | Self::Promote { .. } => true,
+            Self::Admin { command, .. } => command != "stats" && command != "clients",
+            _ => false,
+        }
+    }
 }
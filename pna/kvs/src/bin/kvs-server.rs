@@ -1,37 +1,68 @@
 #[macro_use]
 extern crate slog;
 
-use kvs::engines::Engine;
-use kvs::networking::JsonKvsServer;
-use kvs::thread_pool::{NaiveThreadPool, ThreadPool};
-use kvs::{KvStore, KvsEngine, KvsServer, Result, SledKvsEngine};
+use kvs::engines::{Engine, KvStoreConfig, SledKvsEngineConfig};
+use kvs::networking::{
+    Addr, AdminConfig, AuthConfig, AutoKvsServer, BinaryKvsServer, HttpKvsServer, JsonKvsServer,
+    LogFormat, LogLevel, Metrics, MetricsServer, Protocol, RequestLog, RespKvsServer, ServerConfig,
+};
+use kvs::replication::ReplicatedKvsEngine;
+use kvs::thread_pool::{
+    NaiveThreadPool, RayonThreadPool, SharedQueueThreadPool, ThreadPool, ThreadPoolKind,
+    WorkStealingThreadPool,
+};
+use kvs::{Error, ErrorKind, KvStore, KvsEngine, KvsServer, Result, SledKvsEngine};
+use serde::Deserialize;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use slog::Drain;
 use std::env;
-use std::fs;
+use std::fs::{self, OpenOptions};
+use std::io;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use structopt::StructOpt;
 
 const KVS_ENGINE_FILENAME: &str = "KVS_ENGINE";
 
+const DEFAULT_ADDR: &str = "127.0.0.1:4000";
+
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+const DEFAULT_MAX_REQUEST_SIZE: usize = 64 * 1024 * 1024;
+
 fn main() {
+    let options = match ServerOptions::resolve(ServerCliOpt::from_args()) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
     let decorator = slog_term::TermDecorator::new().stderr().build();
     let drain = slog_term::FullFormat::new(decorator).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
+    // admin requests can change the level while the server runs
+    let drain = options
+        .log_level
+        .filter(slog_async::Async::new(drain).build())
+        .fuse();
     let logger = slog::Logger::root(drain, o!("version" => env!("CARGO_PKG_VERSION")));
 
-    if let Err(err) = run(logger) {
+    if let Err(err) = run(&options, logger) {
         eprintln!("{}", err);
         std::process::exit(1);
     }
 }
 
-fn run(logger: slog::Logger) -> Result<()> {
-    let cli_options = ServerCliOpt::from_args();
+fn run(options: &ServerOptions, logger: slog::Logger) -> Result<()> {
     let current_dir = env::current_dir()?;
 
     let current_engine = current_directory_engine(&current_dir)?;
-    let engine = match cli_options.engine {
+    let engine = match options.engine {
         None => current_engine.unwrap_or(Engine::Kvs),
         Some(selected_engine) => match current_engine {
             None => selected_engine,
@@ -53,24 +84,167 @@ fn run(logger: slog::Logger) -> Result<()> {
     let engine_path = current_dir.join(KVS_ENGINE_FILENAME);
     fs::write(engine_path, engine.as_str())?;
 
-    let pool = NaiveThreadPool::new(4)?;
-    let logger = logger.new(o!( "engine" => engine.as_str()));
+    let logger = logger.new(o!(
+        "engine" => engine.as_str(),
+        "protocol" => options.protocol.as_str(),
+        "thread_pool" => options.thread_pool.as_str()
+    ));
+    let threads = options.threads;
+    match options.thread_pool {
+        ThreadPoolKind::Naive => {
+            let pool = NaiveThreadPool::new(threads)?;
+            open_engine(options, engine, &current_dir, pool, logger)
+        }
+        ThreadPoolKind::SharedQueue => {
+            let pool = SharedQueueThreadPool::new(threads)?;
+            open_engine(options, engine, &current_dir, pool, logger)
+        }
+        ThreadPoolKind::Rayon => {
+            let pool = RayonThreadPool::new(threads)?;
+            open_engine(options, engine, &current_dir, pool, logger)
+        }
+        ThreadPoolKind::WorkStealing => {
+            let pool = WorkStealingThreadPool::new(threads)?;
+            open_engine(options, engine, &current_dir, pool, logger)
+        }
+    }
+}
+
+fn open_engine<P>(
+    options: &ServerOptions,
+    engine: Engine,
+    path: &Path,
+    pool: P,
+    logger: slog::Logger,
+) -> Result<()>
+where
+    P: ThreadPool + Send + Sync + 'static,
+{
+    let metrics = options.metrics_addr.map(|_| Arc::new(Metrics::new()));
     match engine {
-        Engine::Kvs => run_with(cli_options.addr, KvStore::open(&current_dir)?, pool, logger),
+        Engine::Kvs => {
+            let engine = KvStore::open_with_config(path, options.kvs.clone())?;
+            if let Some(metrics) = &metrics {
+                metrics.observe_kv_store(engine.clone());
+            }
+            run_with(options, metrics, engine, pool, logger)
+        }
         Engine::Sled => {
-            let db = sled::Config::default().path(current_dir).open()?;
-            run_with(cli_options.addr, SledKvsEngine::new(db), pool, logger)
+            let engine = SledKvsEngine::open_with_config(path, options.sled.clone())?;
+            run_with(options, metrics, engine, pool, logger)
+        }
+    }
+}
+
+/// Wraps the engine in a `ReplicatedKvsEngine` if the server follows a primary or records its
+/// changes, the others do not pay for the change feed
+fn run_with<E, P>(
+    options: &ServerOptions,
+    metrics: Option<Arc<Metrics>>,
+    engine: E,
+    pool: P,
+    logger: slog::Logger,
+) -> Result<()>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + Sync + 'static,
+{
+    match options.replica_of {
+        Some(primary) => {
+            let engine = ReplicatedKvsEngine::replica_of_with_token(
+                engine,
+                primary,
+                options.replica_token.clone(),
+                Some(logger.clone()),
+            );
+            serve(options, metrics, engine, pool, logger)
         }
+        None if options.replication => {
+            let engine = ReplicatedKvsEngine::primary(engine);
+            serve(options, metrics, engine, pool, logger)
+        }
+        None => serve(options, metrics, engine, pool, logger),
     }
 }
 
-fn run_with<E, P>(addr: SocketAddr, engine: E, pool: P, logger: slog::Logger) -> Result<()>
+/// Serves clients until the process receives SIGTERM or SIGINT, then shuts the server down
+fn serve<E, P>(
+    options: &ServerOptions,
+    metrics: Option<Arc<Metrics>>,
+    engine: E,
+    pool: P,
+    logger: slog::Logger,
+) -> Result<()>
 where
     E: KvsEngine,
-    P: ThreadPool,
+    P: ThreadPool + Send + Sync + 'static,
 {
-    let kvs_server = JsonKvsServer::new(engine, pool, Some(logger));
-    kvs_server.serve(addr)
+    let addr = options.addr.clone();
+    let mut config = options.server_config.clone();
+
+    // registered before serving so that no signal is missed
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    // the HTTP listener shares the engine and the threads with the other protocol
+    let pool = Arc::new(pool);
+    let metrics_handle = match (options.metrics_addr, metrics) {
+        (Some(metrics_addr), Some(metrics)) => {
+            metrics.observe_pool(Arc::clone(&pool));
+            config = config.metrics(Arc::clone(&metrics));
+            Some(MetricsServer::new(metrics, Some(logger.clone())).serve(metrics_addr)?)
+        }
+        _ => None,
+    };
+    let http_handle = match options.http_addr {
+        Some(http_addr) => {
+            let server = HttpKvsServer::with_config(
+                engine.clone(),
+                Arc::clone(&pool),
+                config.clone(),
+                Some(logger.clone()),
+            );
+            Some(server.serve(http_addr)?)
+        }
+        None => None,
+    };
+    let server_logger = Some(logger.clone());
+    let handle = match options.protocol {
+        Protocol::Auto => {
+            AutoKvsServer::with_config(engine, pool, config, server_logger).serve(addr)?
+        }
+        Protocol::Json => {
+            JsonKvsServer::with_config(engine, pool, config, server_logger).serve(addr)?
+        }
+        Protocol::Binary => {
+            BinaryKvsServer::with_config(engine, pool, config, server_logger).serve(addr)?
+        }
+        Protocol::Resp => {
+            RespKvsServer::with_config(engine, pool, config, server_logger).serve(addr)?
+        }
+    };
+
+    if let Some(signal) = signals.forever().next() {
+        info!(logger, "Received signal"; "signal" => signal);
+    }
+    let deadline = options.shutdown_deadline;
+    if let Some(http_handle) = http_handle {
+        http_handle.shutdown_within(deadline)?;
+    }
+    handle.shutdown_within(deadline)?;
+    // scrapes keep working until the last request is served
+    match metrics_handle {
+        Some(metrics_handle) => metrics_handle.shutdown_within(deadline),
+        None => Ok(()),
+    }
+}
+
+/// Resolves a flag that is turned on with `--<name>` and off with `--no-<name>`, `None` if the
+/// command line has neither so that the config file decides
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
 }
 
 fn current_directory_engine<P>(path: P) -> Result<Option<Engine>>
@@ -88,18 +262,508 @@ where
     }
 }
 
+/// Settings of the server, from the command line, the config file or the defaults, in that order
+struct ServerOptions {
+    addr: Addr,
+    engine: Option<Engine>,
+    protocol: Protocol,
+    http_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    replica_of: Option<SocketAddr>,
+    replica_token: Option<String>,
+    replication: bool,
+    thread_pool: ThreadPoolKind,
+    threads: u32,
+    log_level: LogLevel,
+    shutdown_deadline: Duration,
+    server_config: ServerConfig,
+    kvs: KvStoreConfig,
+    sled: SledKvsEngineConfig,
+}
+
+impl ServerOptions {
+    fn resolve(cli: ServerCliOpt) -> Result<Self> {
+        let file = match &cli.config {
+            Some(path) => ConfigFile::read(path)?,
+            None => ConfigFile::default(),
+        };
+
+        let engine = match cli.engine {
+            Some(engine) => Some(engine),
+            None => file.engine.as_deref().map(str::parse).transpose()?,
+        };
+        let protocol = match cli.protocol {
+            Some(protocol) => protocol,
+            None => file.protocol.as_deref().unwrap_or("auto").parse()?,
+        };
+        let thread_pool = match cli.thread_pool {
+            Some(thread_pool) => thread_pool,
+            None => file.thread_pool.as_deref().unwrap_or("naive").parse()?,
+        };
+        let threads = match cli.threads.or(file.threads) {
+            Some(threads) => threads,
+            None => thread::available_parallelism().map_or(4, |n| n.get() as u32),
+        };
+        if threads == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidValue,
+                "A thread pool needs at least one thread",
+            ));
+        }
+        let log_level = LogLevel::new(match cli.log_level {
+            Some(level) => level,
+            None => parse_level(file.log_level.as_deref().unwrap_or("info"))?,
+        });
+
+        // zero disables a timeout or removes the connection limit
+        let secs = |cli: Option<u64>, file: Option<u64>, default| {
+            Some(Duration::from_secs(cli.or(file).unwrap_or(default)))
+        };
+        let http_addr = cli.http_addr.or(file.http_addr);
+        // binary connections to an auto server are refused instead
+        let auth_protocol = matches!(protocol, Protocol::Auto | Protocol::Json);
+        if file.auth.is_some() && (http_addr.is_some() || !auth_protocol) {
+            return Err(Error::new(
+                ErrorKind::InvalidValue,
+                "Authentication needs the json or auto protocol and no HTTP listener",
+            ));
+        }
+        let max_connections = cli
+            .max_connections
+            .or(file.max_connections)
+            .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        let mut server_config = ServerConfig::default()
+            .idle_timeout(secs(cli.idle_timeout_secs, file.idle_timeout_secs, 300))
+            .read_timeout(secs(cli.read_timeout_secs, file.read_timeout_secs, 30))
+            .write_timeout(secs(cli.write_timeout_secs, file.write_timeout_secs, 30))
+            .max_connections(Some(max_connections).filter(|&max| max > 0))
+            .max_request_size(
+                cli.max_request_size
+                    .or(file.max_request_size)
+                    .unwrap_or(DEFAULT_MAX_REQUEST_SIZE),
+            );
+        if let Some(auth) = file.auth {
+            server_config = server_config.auth(auth);
+        }
+        let admin_token = cli.admin_token.or(file.admin_token);
+        let admin = flag(cli.admin, cli.no_admin)
+            .or(file.admin)
+            .unwrap_or(admin_token.is_some());
+        if admin {
+            if !auth_protocol {
+                return Err(Error::new(
+                    ErrorKind::InvalidValue,
+                    "Admin requests need the json or auto protocol",
+                ));
+            }
+            let mut admin = AdminConfig::default().log_level(log_level.clone());
+            if let Some(token) = admin_token {
+                admin = admin.token(token);
+            }
+            server_config = server_config.admin(admin);
+        }
+        let access_log_sample_rate = cli.access_log_sample_rate.or(file.access_log_sample_rate);
+        let slow_log_threshold_ms = cli.slow_log_threshold_ms.or(file.slow_log_threshold_ms);
+        if access_log_sample_rate.is_some() || slow_log_threshold_ms.is_some() {
+            let format = match cli.request_log_format {
+                Some(format) => format,
+                None => file
+                    .request_log_format
+                    .as_deref()
+                    .unwrap_or("terminal")
+                    .parse()?,
+            };
+            let logger = match cli.request_log_file.or(file.request_log_file) {
+                Some(path) => {
+                    let file = OpenOptions::new().create(true).append(true).open(path)?;
+                    format.logger(file)
+                }
+                None => format.logger(io::stderr()),
+            };
+            let mut log = RequestLog::default();
+            if let Some(rate) = access_log_sample_rate {
+                if !(0.0..=1.0).contains(&rate) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidValue,
+                        "The sample rate of the access log must be between 0 and 1",
+                    ));
+                }
+                log = log.access(logger.new(o!("log" => "access")), rate);
+            }
+            if let Some(ms) = slow_log_threshold_ms {
+                let threshold = Duration::from_millis(ms);
+                log = log.slow(logger.new(o!("log" => "slow")), threshold);
+            }
+            server_config = server_config.request_log(log);
+        }
+
+        let mut kvs = KvStoreConfig::default()
+            .mmap(file.kvs.mmap.unwrap_or(false))
+            .compression(
+                file.kvs
+                    .compression
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()?,
+            )
+            .recompress_on_merge(file.kvs.recompress_on_merge.unwrap_or(false));
+        if let Some(threshold) = file.kvs.compression_threshold {
+            kvs = kvs.compression_threshold(threshold);
+        }
+
+        let mut sled = SledKvsEngineConfig::default()
+            .compression(
+                flag(cli.sled_compression, cli.no_sled_compression)
+                    .or(file.sled.compression)
+                    .unwrap_or(false),
+            )
+            .sync_writes(
+                flag(cli.sled_sync_writes, cli.no_sled_sync_writes)
+                    .or(file.sled.sync_writes)
+                    .unwrap_or(false),
+            );
+        if let Some(cache_capacity) = cli.sled_cache_capacity.or(file.sled.cache_capacity) {
+            sled = sled.cache_capacity(cache_capacity);
+        }
+        if let Some(flush_every_ms) = cli.sled_flush_every_ms.or(file.sled.flush_every_ms) {
+            // zero disables background flushes
+            sled = sled.flush_every_ms(Some(flush_every_ms).filter(|&ms| ms > 0));
+        }
+
+        Ok(Self {
+            addr: cli
+                .addr
+                .or(file.addr)
+                .unwrap_or_else(|| DEFAULT_ADDR.parse().unwrap()),
+            engine,
+            protocol,
+            http_addr,
+            metrics_addr: cli.metrics_addr.or(file.metrics_addr),
+            replica_of: cli.replica_of.or(file.replica_of),
+            replica_token: cli.replica_token.or(file.replica_token),
+            replication: flag(cli.replication, cli.no_replication)
+                .or(file.replication)
+                .unwrap_or(false),
+            thread_pool,
+            threads,
+            log_level,
+            shutdown_deadline: Duration::from_secs(
+                cli.shutdown_deadline_secs
+                    .or(file.shutdown_deadline_secs)
+                    .unwrap_or(10),
+            ),
+            server_config,
+            kvs,
+            sled,
+        })
+    }
+}
+
+/// Settings that are read from the file given to `--config`, they use the names of the command
+/// line options with underscores:
+///
+/// ```toml
+/// addr = "127.0.0.1:4000"
+/// engine = "kvs"
+/// thread_pool = "shared-queue"
+/// threads = 8
+/// log_level = "debug"
+/// idle_timeout_secs = 60
+/// access_log_sample_rate = 0.01
+/// slow_log_threshold_ms = 100
+/// request_log_format = "json"
+/// admin_token = "admin-secret"
+///
+/// [kvs]
+/// mmap = true
+/// compression = "snappy"
+///
+/// [sled]
+/// cache_capacity = 1073741824
+///
+/// [auth]
+/// method = "hmac"
+///
+/// [[auth.users]]
+/// name = "app"
+/// token = "secret"
+/// read = ["app/"]
+/// write = ["app/"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    addr: Option<Addr>,
+    engine: Option<String>,
+    protocol: Option<String>,
+    http_addr: Option<SocketAddr>,
+    metrics_addr: Option<SocketAddr>,
+    replica_of: Option<SocketAddr>,
+    replica_token: Option<String>,
+    replication: Option<bool>,
+    thread_pool: Option<String>,
+    threads: Option<u32>,
+    log_level: Option<String>,
+    shutdown_deadline_secs: Option<u64>,
+    idle_timeout_secs: Option<u64>,
+    read_timeout_secs: Option<u64>,
+    write_timeout_secs: Option<u64>,
+    max_connections: Option<usize>,
+    max_request_size: Option<usize>,
+    access_log_sample_rate: Option<f64>,
+    slow_log_threshold_ms: Option<u64>,
+    request_log_format: Option<String>,
+    request_log_file: Option<PathBuf>,
+    admin: Option<bool>,
+    admin_token: Option<String>,
+    #[serde(default)]
+    kvs: KvsConfigFile,
+    #[serde(default)]
+    sled: SledConfigFile,
+    auth: Option<AuthConfig>,
+}
+
+/// Options of `KvStore`, see `KvStoreConfig`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KvsConfigFile {
+    mmap: Option<bool>,
+    compression: Option<String>,
+    compression_threshold: Option<usize>,
+    recompress_on_merge: Option<bool>,
+}
+
+/// Options of `SledKvsEngine`, see `SledKvsEngineConfig`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SledConfigFile {
+    cache_capacity: Option<u64>,
+    compression: Option<bool>,
+    flush_every_ms: Option<u64>,
+    sync_writes: Option<bool>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidValue,
+                format!("Invalid config {}: {}", path.display(), err),
+            )
+        })
+    }
+}
+
+fn parse_level(s: &str) -> Result<slog::Level> {
+    s.parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidValue,
+            format!("Could not found log level named '{}'", s),
+        )
+    })
+}
+
 #[derive(StructOpt)]
 struct ServerCliOpt {
+    #[structopt(
+        long = "config",
+        about = "TOML file with the settings of the server, options on the command line take precedence"
+    )]
+    config: Option<PathBuf>,
+
     #[structopt(
         long = "addr",
-        about = "IP address of the key-value store",
-        default_value = "127.0.0.1:4000"
+        about = "IP address of the key-value store, 127.0.0.1:4000 by default, or unix:PATH for a Unix domain socket"
     )]
-    addr: SocketAddr,
+    addr: Option<Addr>,
 
     #[structopt(
         long = "engine",
         about = "Name of the engine that is used for the key-value store"
     )]
     engine: Option<Engine>,
+
+    #[structopt(
+        long = "protocol",
+        about = "Protocol that clients speak, one of auto (JSON and binary, the default), json, binary or resp"
+    )]
+    protocol: Option<Protocol>,
+
+    #[structopt(
+        long = "http-addr",
+        about = "IP address of an HTTP listener that serves the same engine with a REST interface"
+    )]
+    http_addr: Option<SocketAddr>,
+
+    #[structopt(
+        long = "metrics-addr",
+        about = "IP address of an HTTP listener that serves metrics in the Prometheus text format at /metrics"
+    )]
+    metrics_addr: Option<SocketAddr>,
+
+    #[structopt(
+        long = "replica-of",
+        about = "Address of a primary whose changes are applied, the server only serves reads until it is promoted"
+    )]
+    replica_of: Option<SocketAddr>,
+
+    #[structopt(
+        long = "replica-token",
+        about = "Token that a replica authenticates to its primary with, if the primary requires authentication"
+    )]
+    replica_token: Option<String>,
+
+    #[structopt(
+        long = "replication",
+        overrides_with = "no-replication",
+        about = "Record the recent changes in memory, so that replicas can follow this server and clients can watch keys"
+    )]
+    replication: bool,
+
+    #[structopt(
+        long = "no-replication",
+        overrides_with = "replication",
+        about = "Do not record the recent changes, overrides the config file"
+    )]
+    no_replication: bool,
+
+    #[structopt(
+        long = "thread-pool",
+        about = "Thread pool that serves the connections, one of naive (a thread per connection, the default), shared-queue, rayon or work-stealing"
+    )]
+    thread_pool: Option<ThreadPoolKind>,
+
+    #[structopt(
+        long = "threads",
+        about = "Number of threads of the shared-queue, rayon and work-stealing pools, the number of CPUs by default"
+    )]
+    threads: Option<u32>,
+
+    #[structopt(
+        long = "log-level",
+        about = "Least severe level that is logged, one of critical, error, warning, info (the default), debug or trace",
+        parse(try_from_str = parse_level)
+    )]
+    log_level: Option<slog::Level>,
+
+    #[structopt(
+        long = "shutdown-deadline-secs",
+        about = "Seconds that in-flight requests are given to finish when the server is stopped, 10 by default"
+    )]
+    shutdown_deadline_secs: Option<u64>,
+
+    #[structopt(
+        long = "idle-timeout-secs",
+        about = "Seconds that a connection can wait between requests before it is closed, 0 disables the timeout, 300 by default"
+    )]
+    idle_timeout_secs: Option<u64>,
+
+    #[structopt(
+        long = "read-timeout-secs",
+        about = "Seconds that the server waits for the rest of a request, 0 disables the timeout, 30 by default"
+    )]
+    read_timeout_secs: Option<u64>,
+
+    #[structopt(
+        long = "write-timeout-secs",
+        about = "Seconds that the server waits for a client to accept a response, 0 disables the timeout, 30 by default"
+    )]
+    write_timeout_secs: Option<u64>,
+
+    #[structopt(
+        long = "max-connections",
+        about = "Maximum number of clients that are served at the same time, 0 removes the limit, 1024 by default"
+    )]
+    max_connections: Option<usize>,
+
+    #[structopt(
+        long = "max-request-size",
+        about = "Maximum size in bytes of a request, 64 MiB by default"
+    )]
+    max_request_size: Option<usize>,
+
+    #[structopt(
+        long = "access-log-sample-rate",
+        about = "Fraction of the requests of JSON connections that are written to the access log, between 0 and 1"
+    )]
+    access_log_sample_rate: Option<f64>,
+
+    #[structopt(
+        long = "slow-log-threshold-ms",
+        about = "Milliseconds that a request of a JSON connection takes before it is written to the slow log"
+    )]
+    slow_log_threshold_ms: Option<u64>,
+
+    #[structopt(
+        long = "request-log-format",
+        about = "Format of the access log and the slow log, one of terminal (the default) or json"
+    )]
+    request_log_format: Option<LogFormat>,
+
+    #[structopt(
+        long = "request-log-file",
+        about = "File that the access log and the slow log are appended to, stderr by default"
+    )]
+    request_log_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "admin",
+        overrides_with = "no-admin",
+        about = "Accept admin requests on JSON connections, from users with the admin flag if the server requires authentication"
+    )]
+    admin: bool,
+
+    #[structopt(
+        long = "no-admin",
+        overrides_with = "admin",
+        about = "Refuse admin requests, overrides the config file and --admin-token"
+    )]
+    no_admin: bool,
+
+    #[structopt(
+        long = "admin-token",
+        about = "Token that admin requests must carry, implies --admin"
+    )]
+    admin_token: Option<String>,
+
+    #[structopt(
+        long = "sled-cache-capacity",
+        about = "Maximum size in bytes of sled's page cache"
+    )]
+    sled_cache_capacity: Option<u64>,
+
+    #[structopt(
+        long = "sled-compression",
+        overrides_with = "no-sled-compression",
+        about = "Compress sled's on-disk data, must match the setting the data was created with"
+    )]
+    sled_compression: bool,
+
+    #[structopt(
+        long = "no-sled-compression",
+        overrides_with = "sled-compression",
+        about = "Do not compress sled's on-disk data, overrides the config file"
+    )]
+    no_sled_compression: bool,
+
+    #[structopt(
+        long = "sled-flush-every-ms",
+        about = "Interval in milliseconds between sled's background flushes, 0 disables them"
+    )]
+    sled_flush_every_ms: Option<u64>,
+
+    #[structopt(
+        long = "sled-sync-writes",
+        overrides_with = "no-sled-sync-writes",
+        about = "Flush sled after every write so that writes are durable once acknowledged"
+    )]
+    sled_sync_writes: bool,
+
+    #[structopt(
+        long = "no-sled-sync-writes",
+        overrides_with = "sled-sync-writes",
+        about = "Do not flush sled after every write, overrides the config file"
+    )]
+    no_sled_sync_writes: bool,
 }
@@ -1,20 +1,25 @@
 //! An `KvsEngine` that uses log-structure file system.
 
+use crate::engines::{WriteBatch, WriteOp};
 use crate::{Error, ErrorKind, KvsEngine, Result};
 use dashmap::DashMap;
+use memmap2::Mmap;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 
 const GARBAGE_THRESHOLD: u64 = 4 * 1024 * 1024;
+const COMPRESSION_THRESHOLD: usize = 1024;
 
 /// A simple key-value that has supports for inserting, updating, accessing, and removing entries.
 /// This implementation holds that key-value inside the main memory that doesn't support data
@@ -88,6 +93,15 @@ impl Clone for KvStore {
 impl KvStore {
     /// Open the key-value store at the given path and return the store to the caller.
     pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::open_with_config(path, KvStoreConfig::default())
+    }
+
+    /// Open the key-value store at the given path using the given configuration and return the
+    /// store to the caller.
+    pub fn open_with_config<P>(path: P, config: KvStoreConfig) -> Result<Self>
     where
         P: AsRef<Path>,
     {
@@ -95,12 +109,12 @@ impl KvStore {
         let gen = prev_gens.last().map(|&e| e + 1).unwrap_or_default();
 
         // go through all log files, rebuild the index, and keep the handle to each log for later access
-        let mut garbage = 0;
+        let mut stats = KvStoreStats::default();
         let mut index = DashMap::new();
         let mut readers = BTreeMap::new();
         for prev_gen in prev_gens {
             let mut reader = open_log(&path, prev_gen)?;
-            garbage += build_index(&mut reader, &mut index, prev_gen)?;
+            build_index(&mut reader, &mut index, &mut stats, prev_gen)?;
             readers.insert(prev_gen, reader);
         }
         // create a new log file for this instance, taking a write handle and a read handle for it
@@ -110,20 +124,30 @@ impl KvStore {
         let path = Arc::new(path.as_ref().to_path_buf());
         let index = Arc::new(index);
 
+        let mmaps = if config.mmap {
+            Some(Arc::new(DashMap::new()))
+        } else {
+            None
+        };
+
         let r_context = ReadContext {
             path: Arc::clone(&path),
             index: Arc::clone(&index),
             merge_gen: Arc::new(AtomicU64::new(0)),
+            active_gen: Arc::new(AtomicU64::new(gen)),
             readers: RefCell::new(readers),
+            mmaps,
+            batch_lock: Arc::new(RwLock::new(())),
         };
 
         let w_context = WriteContext {
             path: Arc::clone(&path),
             index: Arc::clone(&index),
             r_context: r_context.clone(),
+            config,
             writer,
             gen,
-            garbage,
+            stats,
         };
 
         Ok(Self {
@@ -131,6 +155,218 @@ impl KvStore {
             r_context,
         })
     }
+
+    /// Returns statistics about the on-disk logs and the in-memory index.
+    pub fn stats(&self) -> KvStoreStats {
+        let w_context = self.w_context.lock().unwrap();
+        KvStoreStats {
+            keys: self.r_context.index.len() as u64,
+            ..w_context.stats.clone()
+        }
+    }
+}
+
+/// Options that are used when opening a `KvStore`
+///
+/// # Usages
+///
+/// ```
+/// use kvs::{KvsEngine, Result};
+/// use kvs::engines::{KvStore, KvStoreConfig};
+/// use tempfile::TempDir;
+///
+/// fn main() -> Result<()> {
+///     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+///     let config = KvStoreConfig::default().mmap(true);
+///     let kvs = KvStore::open_with_config(temp_dir.path(), config)?;
+///
+///     kvs.set("key".to_string(), "val".to_string())?;
+///     assert_eq!(kvs.get("key".to_string())?, Some("val".to_string()));
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct KvStoreConfig {
+    mmap: bool,
+    codec: Option<Codec>,
+    compression_threshold: usize,
+    recompress_on_merge: bool,
+}
+
+impl Default for KvStoreConfig {
+    fn default() -> Self {
+        Self {
+            mmap: false,
+            codec: None,
+            compression_threshold: COMPRESSION_THRESHOLD,
+            recompress_on_merge: false,
+        }
+    }
+}
+
+impl KvStoreConfig {
+    /// Read sealed log generations through memory maps that are shared between all clones of
+    /// the store, instead of opening a buffered file reader per generation for each clone. The
+    /// active generation is always read through a file reader, since it is still being appended.
+    pub fn mmap(mut self, enabled: bool) -> Self {
+        self.mmap = enabled;
+        self
+    }
+
+    /// Compress values with the given codec before they are written to the log, `None` stores
+    /// every value as is. Logs that contain records written with different codecs stay readable.
+    pub fn compression(mut self, codec: Option<Codec>) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Only compress values whose size in bytes is at least `threshold`, small values rarely
+    /// benefit from compression.
+    pub fn compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Rewrite every record with the configured compression settings when logs are compacted,
+    /// instead of copying records as they are.
+    pub fn recompress_on_merge(mut self, enabled: bool) -> Self {
+        self.recompress_on_merge = enabled;
+        self
+    }
+
+    /// Creates the log entry for a set operation, compressing the value if needed.
+    fn set_entry(&self, key: String, val: String) -> Result<LogEntry> {
+        let codec = match self.codec {
+            Some(codec) if val.len() >= self.compression_threshold => codec,
+            _ => return Ok(LogEntry::Set(key, val)),
+        };
+        let data = codec.compress(val.as_bytes())?;
+        if data.len() >= val.len() {
+            // not worth it, decompressing would only slow down reads
+            return Ok(LogEntry::Set(key, val));
+        }
+        let val = CompressedValue {
+            codec,
+            len: val.len() as u64,
+            data,
+        };
+        Ok(LogEntry::SetCompressed(key, val))
+    }
+}
+
+/// Statistics about a `KvStore`
+#[derive(Debug, Clone, Default)]
+pub struct KvStoreStats {
+    /// Number of keys in the index
+    pub keys: u64,
+    /// Number of bytes in the logs that are no longer referenced by the index
+    pub garbage_bytes: u64,
+    /// Number of compactions that were performed since the store was opened
+    pub merges: u64,
+    /// Total size of the compressed values in the index before compression, values that were
+    /// overwritten or removed no longer count
+    pub uncompressed_bytes: u64,
+    /// Total size of the compressed values in the index after compression, counting the same
+    /// values as `uncompressed_bytes`
+    pub compressed_bytes: u64,
+}
+
+impl KvStoreStats {
+    /// Returns the ratio between the size of the compressed values before and after compression,
+    /// or `1.0` if no value was compressed.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            return 1.0;
+        }
+        self.uncompressed_bytes as f64 / self.compressed_bytes as f64
+    }
+
+    /// Counts a record that the index now refers to
+    fn add(&mut self, index: &LogIndex) {
+        self.uncompressed_bytes += index.uncompressed_len;
+        self.compressed_bytes += index.compressed_len;
+    }
+
+    /// Counts a record that the index no longer refers to as garbage
+    fn remove(&mut self, index: &LogIndex) {
+        self.uncompressed_bytes -= index.uncompressed_len;
+        self.compressed_bytes -= index.compressed_len;
+        self.garbage_bytes += index.len;
+    }
+}
+
+/// Compression algorithms that can be applied to the values in the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    /// Snappy compression, fast with a moderate compression ratio
+    Snappy,
+    /// DEFLATE compression, slower with a better compression ratio
+    Deflate,
+}
+
+impl Codec {
+    /// Get the string representation of the codec
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Self::Snappy => "snappy",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            Self::Snappy => {
+                let compressed = snap::raw::Encoder::new()
+                    .compress_vec(bytes)
+                    .map_err(io::Error::from)?;
+                Ok(compressed)
+            }
+            Self::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        match *self {
+            Self::Snappy => {
+                let decompressed = snap::raw::Decoder::new()
+                    .decompress_vec(bytes)
+                    .map_err(|err| Error::new(ErrorKind::CorruptedLog, err))?;
+                Ok(decompressed)
+            }
+            Self::Deflate => {
+                let mut decompressed = Vec::new();
+                flate2::read::DeflateDecoder::new(bytes).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Codec> {
+        let name = s.to_lowercase();
+        match name.as_str() {
+            "snappy" => Ok(Self::Snappy),
+            "deflate" => Ok(Self::Deflate),
+            _ => Err(Error::new(
+                ErrorKind::UnsupportedCodec,
+                format!("Could not found codec named '{}'", name),
+            )),
+        }
+    }
 }
 
 impl KvsEngine for KvStore {
@@ -159,6 +395,85 @@ impl KvsEngine for KvStore {
     fn remove(&self, key: String) -> Result<()> {
         self.w_context.lock().unwrap().remove(key)
     }
+
+    /// # Error
+    ///
+    /// Error from I/O operations will be propagated. If the value is not an integer or the
+    /// result overflows returns an `InvalidValue` error.
+    fn increment(&self, key: String, delta: i64) -> Result<i64> {
+        self.w_context.lock().unwrap().increment(key, delta)
+    }
+
+    /// # Error
+    ///
+    /// Error from I/O operations and serialization/deserialization operations will be propagated.
+    fn append(&self, key: String, suffix: String) -> Result<()> {
+        self.w_context.lock().unwrap().append(key, suffix)
+    }
+
+    /// # Error
+    ///
+    /// Error from I/O operations and serialization/deserialization operations will be propagated.
+    /// If the batch removes a key that doesn't exist returns a `KeyNotFound` error, and no
+    /// operation in the batch is applied.
+    ///
+    /// The batch is written to the log as a single record, so that a crash in the middle of the
+    /// write loses the whole batch when the log is read again. The index is only updated once
+    /// the record is written, and readers never observe a partially updated index.
+    fn apply_batch(&self, batch: WriteBatch) -> Result<()> {
+        self.w_context.lock().unwrap().apply_batch(batch)
+    }
+
+    /// # Error
+    ///
+    /// Error from I/O operations will be propagated.
+    fn flush(&self) -> Result<()> {
+        self.w_context.lock().unwrap().flush()
+    }
+
+    /// The index is not ordered, every scan goes through all the keys in the index.
+    fn scan(&self, start: String, limit: usize) -> Result<Vec<String>> {
+        let _batch_guard = self.r_context.batch_lock.read().unwrap();
+        let mut keys: Vec<String> = self
+            .r_context
+            .index
+            .iter()
+            .filter(|entry| *entry.key() >= start)
+            .map(|entry| entry.key().clone())
+            .collect();
+        keys.sort_unstable();
+        keys.truncate(limit);
+        Ok(keys)
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.w_context.lock().unwrap().merge()
+    }
+
+    /// Copies the log files while writes and compactions wait, the copy is opened like any
+    /// other store.
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        let mut w_context = self.w_context.lock().unwrap();
+        w_context.flush()?;
+        fs::create_dir_all(path.parent().unwrap_or(path))?;
+        fs::create_dir(path)?;
+        for gen in previous_gens(w_context.path.as_ref())? {
+            let name = format!("gen-{}.log", gen);
+            fs::copy(w_context.path.join(&name), path.join(&name))?;
+        }
+        Ok(())
+    }
+
+    fn statistics(&self) -> Result<Vec<(String, u64)>> {
+        let stats = self.stats();
+        Ok(vec![
+            ("keys".to_owned(), stats.keys),
+            ("garbage_bytes".to_owned(), stats.garbage_bytes),
+            ("merges".to_owned(), stats.merges),
+            ("uncompressed_bytes".to_owned(), stats.uncompressed_bytes),
+            ("compressed_bytes".to_owned(), stats.compressed_bytes),
+        ])
+    }
 }
 
 /// A database's writer that updates on-disk files and maintains consistent index to those files
@@ -167,30 +482,22 @@ struct WriteContext {
     path: Arc<PathBuf>,
     index: Arc<DashMap<String, LogIndex>>,
     r_context: ReadContext,
+    config: KvStoreConfig,
     writer: BufSeekWriter<File>,
     gen: u64,
-    garbage: u64,
+    stats: KvStoreStats,
 }
 
 impl WriteContext {
     fn set(&mut self, key: String, val: String) -> Result<()> {
         let pos = self.writer.pos;
-        let log_entry = LogEntry::Set(key.clone(), val);
+        let log_entry = self.config.set_entry(key, val)?;
         bincode::serialize_into(&mut self.writer, &log_entry)?;
         self.writer.flush()?;
 
-        let log_index = LogIndex {
-            gen: self.gen,
-            pos,
-            len: self.writer.pos - pos,
-        };
-        if let Some(prev_index) = self.index.insert(key, log_index) {
-            self.garbage += prev_index.len;
-            if self.garbage > GARBAGE_THRESHOLD {
-                self.merge()?;
-            }
-        };
-        Ok(())
+        let log_index = LogIndex::new(self.gen, pos, self.writer.pos - pos, &log_entry);
+        apply_entry(&self.index, &mut self.stats, log_entry, log_index);
+        self.merge_if_needed()
     }
 
     fn remove(&mut self, key: String) -> Result<()> {
@@ -201,16 +508,89 @@ impl WriteContext {
             ));
         }
 
-        let log_entry = LogEntry::Rm(key.clone());
+        let pos = self.writer.pos;
+        let log_entry = LogEntry::Rm(key);
         bincode::serialize_into(&mut self.writer, &log_entry)?;
         self.writer.flush()?;
 
-        if let Some((_, prev_index)) = self.index.remove(&key) {
-            self.garbage += prev_index.len;
-            if self.garbage > GARBAGE_THRESHOLD {
-                self.merge()?;
+        let log_index = LogIndex::new(self.gen, pos, self.writer.pos - pos, &log_entry);
+        apply_entry(&self.index, &mut self.stats, log_entry, log_index);
+        self.merge_if_needed()
+    }
+
+    fn merge_if_needed(&mut self) -> Result<()> {
+        if self.stats.garbage_bytes > GARBAGE_THRESHOLD {
+            self.merge()?;
+        }
+        Ok(())
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        // no other writer can change the value while the write context is held
+        let val = self.r_context.get(key.clone())?;
+        let val = super::add_to_value(&key, val.as_deref(), delta)?;
+        self.set(key, val.to_string())?;
+        Ok(val)
+    }
+
+    fn append(&mut self, key: String, suffix: String) -> Result<()> {
+        let mut val = self.r_context.get(key.clone())?.unwrap_or_default();
+        val.push_str(&suffix);
+        self.set(key, val)
+    }
+
+    fn apply_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        // check that every removed key exists before writing anything, taking into account the
+        // operations that come before it in the batch
+        let mut exists = std::collections::HashMap::new();
+        for op in batch.ops() {
+            match op {
+                WriteOp::Set { key, .. } => {
+                    exists.insert(key.as_str(), true);
+                }
+                WriteOp::Remove { key } => {
+                    let key_exists = exists
+                        .get(key.as_str())
+                        .cloned()
+                        .unwrap_or_else(|| self.index.contains_key(key));
+                    if !key_exists {
+                        return Err(Error::new(
+                            ErrorKind::KeyNotFound,
+                            format!("Key '{}' does not exist", key),
+                        ));
+                    }
+                    exists.insert(key.as_str(), false);
+                }
             }
-        };
+        }
+
+        let entries = batch
+            .ops
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Set { key, value } => self.config.set_entry(key, value),
+                WriteOp::Remove { key } => Ok(LogEntry::Rm(key)),
+            })
+            .collect::<Result<_>>()?;
+
+        let pos = self.writer.pos;
+        let log_entry = LogEntry::Batch(entries);
+        bincode::serialize_into(&mut self.writer, &log_entry)?;
+        self.writer.flush()?;
+
+        // readers wait until every key of the batch points to its new entry
+        let entries = split_record(log_entry, self.gen, pos, self.writer.pos - pos)?;
+        let batch_guard = self.r_context.batch_lock.write().unwrap();
+        for (entry, log_index) in entries {
+            apply_entry(&self.index, &mut self.stats, entry, log_index);
+        }
+        drop(batch_guard);
+        self.merge_if_needed()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.writer.get_ref().sync_data()?;
         Ok(())
     }
 
@@ -221,7 +601,10 @@ impl WriteContext {
         let (mut merged_writer, merged_reader) = create_log(self.path.as_ref(), merge_gen)?;
         let (writer, reader) = create_log(self.path.as_ref(), new_gen)?;
 
-        // Copy data to the merge log and update the index
+        // Copy data to the merge log and update the index, the compression statistics are counted
+        // again from the records in the merge log
+        self.stats.uncompressed_bytes = 0;
+        self.stats.compressed_bytes = 0;
         let mut readers = self.r_context.readers.borrow_mut();
         for mut log_index in self.index.iter_mut() {
             let reader = readers
@@ -232,22 +615,57 @@ impl WriteContext {
             let mut entry_reader = reader.take(log_index.len);
 
             let merge_pos = merged_writer.pos;
-            io::copy(&mut entry_reader, &mut merged_writer)?;
+            let (uncompressed_len, compressed_len) = if self.config.recompress_on_merge {
+                let log_entry = match bincode::deserialize_from(&mut entry_reader)? {
+                    LogEntry::Set(key, val) => self.config.set_entry(key, val)?,
+                    LogEntry::SetCompressed(key, val) if Some(val.codec) == self.config.codec => {
+                        LogEntry::SetCompressed(key, val)
+                    }
+                    LogEntry::SetCompressed(key, val) => {
+                        self.config.set_entry(key, val.decompress()?)?
+                    }
+                    LogEntry::Rm(_) | LogEntry::Batch(_) => {
+                        return Err(Error::new(
+                            ErrorKind::CorruptedIndex,
+                            "Expecting a log entry for a set operation",
+                        ))
+                    }
+                };
+                bincode::serialize_into(&mut merged_writer, &log_entry)?;
+                log_entry.compressed_lens()
+            } else {
+                io::copy(&mut entry_reader, &mut merged_writer)?;
+                (log_index.uncompressed_len, log_index.compressed_len)
+            };
 
             *log_index = LogIndex {
                 gen: merge_gen,
                 pos: merge_pos,
-                len: log_index.len,
+                len: merged_writer.pos - merge_pos,
+                uncompressed_len,
+                compressed_len,
             };
+            self.stats.add(&log_index);
         }
         readers.insert(merge_gen, merged_reader);
         readers.insert(new_gen, reader);
         merged_writer.flush()?;
 
+        // the merge log is complete and will never be written to again, so readers can start
+        // mapping it into memory
+        self.r_context.active_gen.store(new_gen, Ordering::SeqCst);
+
         // set merge generation, `ReadContext` in all threads will observe the new value and drop
         // its the file handle
         self.r_context.merge_gen.store(merge_gen, Ordering::SeqCst);
 
+        // drop the shared memory maps of stale log files, readers that are still holding one of
+        // them can keep using it even after the file is removed. A reader that maps a stale log
+        // after this point sees the new merge generation and drops its map itself
+        if let Some(mmaps) = self.r_context.mmaps.as_ref() {
+            mmaps.retain(|&gen, _| gen >= merge_gen);
+        }
+
         // remove stale log files
         let prev_gens = previous_gens(self.path.as_ref())?;
         let stale_gens = prev_gens.iter().filter(|&&gen| gen < merge_gen);
@@ -259,7 +677,8 @@ impl WriteContext {
         // update writer and log generation
         self.writer = writer;
         self.gen = new_gen;
-        self.garbage = 0;
+        self.stats.garbage_bytes = 0;
+        self.stats.merges += 1;
         Ok(())
     }
 }
@@ -270,7 +689,12 @@ struct ReadContext {
     path: Arc<PathBuf>,
     index: Arc<DashMap<String, LogIndex>>,
     merge_gen: Arc<AtomicU64>,
+    active_gen: Arc<AtomicU64>,
     readers: RefCell<BTreeMap<u64, BufSeekReader<File>>>,
+    mmaps: Option<Arc<DashMap<u64, Arc<Mmap>>>>,
+    /// Held for writing while the index is updated for a batch, so that readers see the whole
+    /// batch or none of it
+    batch_lock: Arc<RwLock<()>>,
 }
 
 impl Clone for ReadContext {
@@ -281,29 +705,49 @@ impl Clone for ReadContext {
             path: Arc::clone(&self.path),
             index: Arc::clone(&self.index),
             merge_gen: Arc::clone(&self.merge_gen),
+            active_gen: Arc::clone(&self.active_gen),
             readers: RefCell::new(BTreeMap::new()),
+            mmaps: self.mmaps.clone(),
+            batch_lock: Arc::clone(&self.batch_lock),
         }
     }
 }
 
 impl ReadContext {
     fn get(&self, key: String) -> Result<Option<String>> {
-        match self.index.get(&key) {
+        let batch_guard = self.batch_lock.read().unwrap();
+        let index = self.index.get(&key);
+        drop(batch_guard);
+        match index {
             None => Ok(None),
             Some(index) => {
                 self.drop_stale_readers();
-                let log_entry = {
-                    let mut readers = self.readers.borrow_mut();
-                    let reader = readers
-                        .entry(index.gen)
-                        .or_insert(open_log(self.path.as_ref(), index.gen)?);
-
-                    reader.seek(SeekFrom::Start(index.pos))?;
-                    bincode::deserialize_from(reader)?
+                let log_entry = match self.sealed_log(index.gen)? {
+                    Some(mmap) => {
+                        let start = index.pos as usize;
+                        let end = start + index.len as usize;
+                        let bytes = mmap.get(start..end).ok_or_else(|| {
+                            Error::new(
+                                ErrorKind::CorruptedIndex,
+                                "Log index points past the end of the log file",
+                            )
+                        })?;
+                        bincode::deserialize(bytes)?
+                    }
+                    None => {
+                        let mut readers = self.readers.borrow_mut();
+                        let reader = readers
+                            .entry(index.gen)
+                            .or_insert(open_log(self.path.as_ref(), index.gen)?);
+
+                        reader.seek(SeekFrom::Start(index.pos))?;
+                        bincode::deserialize_from(reader)?
+                    }
                 };
 
                 match log_entry {
                     LogEntry::Set(_, value) => Ok(Some(value)),
+                    LogEntry::SetCompressed(_, value) => Ok(Some(value.decompress()?)),
                     _ => Err(Error::new(
                         ErrorKind::CorruptedLog,
                         "Expecting a log entry for a set operation",
@@ -313,6 +757,31 @@ impl ReadContext {
         }
     }
 
+    /// Returns the shared memory map of the log with the given generation, if memory-mapped reads
+    /// are enabled and the log has been sealed.
+    fn sealed_log(&self, gen: u64) -> Result<Option<Arc<Mmap>>> {
+        let mmaps = match self.mmaps.as_ref() {
+            Some(mmaps) => mmaps,
+            None => return Ok(None),
+        };
+        if gen >= self.active_gen.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        if let Some(mmap) = mmaps.get(&gen) {
+            return Ok(Some(Arc::clone(mmap.value())));
+        }
+
+        let mmap = Arc::new(map_log(self.path.as_ref(), gen)?);
+        let mmap = Arc::clone(mmaps.entry(gen).or_insert(mmap).value());
+
+        // a merge may have made the log stale and dropped the stale maps before this one was
+        // inserted, it must not stay around until the next merge
+        if gen < self.merge_gen.load(Ordering::SeqCst) {
+            mmaps.remove(&gen);
+        }
+        Ok(Some(mmap))
+    }
+
     fn drop_stale_readers(&self) {
         let merge_gen = self.merge_gen.load(Ordering::SeqCst);
         let mut readers = self.readers.borrow_mut();
@@ -331,6 +800,37 @@ impl ReadContext {
 enum LogEntry {
     Set(String, String),
     Rm(String),
+    // NOTE: New variants must be added at the end so that existing logs stay readable
+    SetCompressed(String, CompressedValue),
+    /// Entries of a `WriteBatch`, which are read back either all together or not at all
+    Batch(Vec<LogEntry>),
+}
+
+/// A compressed value, along with the codec that was used so that records written with different
+/// settings can be read back
+#[derive(Debug, Serialize, Deserialize)]
+struct CompressedValue {
+    codec: Codec,
+    len: u64,
+    data: Vec<u8>,
+}
+
+impl CompressedValue {
+    fn decompress(&self) -> Result<String> {
+        let bytes = self.codec.decompress(&self.data)?;
+        String::from_utf8(bytes).map_err(|err| Error::new(ErrorKind::CorruptedLog, err))
+    }
+}
+
+impl LogEntry {
+    /// Returns the size of the value before and after compression, or zeros if the value is not
+    /// compressed
+    fn compressed_lens(&self) -> (u64, u64) {
+        match self {
+            LogEntry::SetCompressed(_, val) => (val.len, val.data.len() as u64),
+            _ => (0, 0),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -338,32 +838,40 @@ struct LogIndex {
     gen: u64,
     pos: u64,
     len: u64,
+    /// Size of the value before compression, zero if the record is not compressed
+    uncompressed_len: u64,
+    /// Size of the value after compression, zero if the record is not compressed
+    compressed_len: u64,
+}
+
+impl LogIndex {
+    fn new(gen: u64, pos: u64, len: u64, entry: &LogEntry) -> Self {
+        let (uncompressed_len, compressed_len) = entry.compressed_lens();
+        Self {
+            gen,
+            pos,
+            len,
+            uncompressed_len,
+            compressed_len,
+        }
+    }
 }
 
 fn build_index(
     reader: &mut BufSeekReader<File>,
     index_map: &mut DashMap<String, LogIndex>,
+    stats: &mut KvStoreStats,
     gen: u64,
-) -> Result<u64> {
+) -> Result<()> {
     reader.seek(SeekFrom::Start(0))?;
-    let mut garbage = 0;
     loop {
         let pos = reader.pos;
         match bincode::deserialize_from(reader.by_ref()) {
-            Ok(e) => match e {
-                LogEntry::Set(key, _) => {
-                    let len = reader.pos - pos;
-                    let index = LogIndex { gen, pos, len };
-                    if let Some(prev_index) = index_map.insert(key, index) {
-                        garbage += prev_index.len;
-                    };
+            Ok(e) => {
+                for (entry, index) in split_record(e, gen, pos, reader.pos - pos)? {
+                    apply_entry(index_map, stats, entry, index);
                 }
-                LogEntry::Rm(key) => {
-                    if let Some((_, prev_index)) = index_map.remove(&key) {
-                        garbage += prev_index.len;
-                    };
-                }
-            },
+            }
             Err(err) => match err.as_ref() {
                 bincode::ErrorKind::Io(io_err) => match io_err.kind() {
                     // TODO: Note down why this is ok
@@ -374,7 +882,63 @@ fn build_index(
             },
         }
     }
-    Ok(garbage)
+    Ok(())
+}
+
+/// Points the index at an entry that was read from or written to the log
+fn apply_entry(
+    index_map: &DashMap<String, LogIndex>,
+    stats: &mut KvStoreStats,
+    entry: LogEntry,
+    index: LogIndex,
+) {
+    match entry {
+        LogEntry::Set(key, _) | LogEntry::SetCompressed(key, _) => {
+            stats.add(&index);
+            if let Some(prev_index) = index_map.insert(key, index) {
+                stats.remove(&prev_index);
+            };
+        }
+        LogEntry::Rm(key) => {
+            if let Some((_, prev_index)) = index_map.remove(&key) {
+                stats.remove(&prev_index);
+            };
+        }
+        LogEntry::Batch(_) => {}
+    }
+}
+
+/// Returns the entries of the record at `pos` along with their index, the entries of a batch are
+/// serialized one after the other so each of them can be read on its own
+fn split_record(
+    record: LogEntry,
+    gen: u64,
+    pos: u64,
+    len: u64,
+) -> Result<Vec<(LogEntry, LogIndex)>> {
+    let entries = match record {
+        LogEntry::Batch(entries) => entries,
+        entry => {
+            let index = LogIndex::new(gen, pos, len, &entry);
+            return Ok(vec![(entry, index)]);
+        }
+    };
+
+    let mut entry_pos = pos + bincode::serialized_size(&LogEntry::Batch(Vec::new()))?;
+    let mut split = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if let LogEntry::Batch(_) = entry {
+            return Err(Error::new(
+                ErrorKind::CorruptedLog,
+                "Expecting a log entry for a set or a remove operation in a batch",
+            ));
+        }
+        let entry_len = bincode::serialized_size(&entry)?;
+        let index = LogIndex::new(gen, entry_pos, entry_len, &entry);
+        entry_pos += entry_len;
+        split.push((entry, index));
+    }
+    Ok(split)
 }
 
 fn open_log<P>(path: P, gen: u64) -> Result<BufSeekReader<File>>
@@ -387,6 +951,18 @@ where
     Ok(reader)
 }
 
+fn map_log<P>(path: P, gen: u64) -> Result<Mmap>
+where
+    P: AsRef<Path>,
+{
+    let log_path = path.as_ref().join(format!("gen-{}.log", gen));
+    let readable_log = OpenOptions::new().read(true).open(&log_path)?;
+    // SAFETY: Only sealed logs are mapped, those are never written to again and they are only
+    // removed from the file system once no index entry points to them.
+    let mmap = unsafe { Mmap::map(&readable_log)? };
+    Ok(mmap)
+}
+
 fn create_log<P>(path: P, gen: u64) -> Result<(BufSeekWriter<File>, BufSeekReader<File>)>
 where
     P: AsRef<Path>,
@@ -442,7 +1018,7 @@ where
     where
         W: Write + Seek,
     {
-        let pos = w.seek(SeekFrom::Current(0))?;
+        let pos = w.stream_position()?;
         let writer = BufWriter::new(w);
         Ok(Self { pos, writer })
     }
@@ -453,9 +1029,8 @@ where
     W: Write,
 {
     fn write(&mut self, b: &[u8]) -> std::result::Result<usize, io::Error> {
-        self.writer.write(b).and_then(|bytes_written| {
+        self.writer.write(b).inspect(|&bytes_written| {
             self.pos += bytes_written as u64;
-            Ok(bytes_written)
         })
     }
 
@@ -478,7 +1053,7 @@ where
     R: Read + Seek,
 {
     fn new(mut r: R) -> Result<Self> {
-        let pos = r.seek(SeekFrom::Current(0))?;
+        let pos = r.stream_position()?;
         let reader = BufReader::new(r);
         Ok(Self { pos, reader })
     }
@@ -489,9 +1064,8 @@ where
     R: Read + Seek,
 {
     fn read(&mut self, b: &mut [u8]) -> std::result::Result<usize, io::Error> {
-        self.reader.read(b).and_then(|bytes_read| {
+        self.reader.read(b).inspect(|&bytes_read| {
             self.pos += bytes_read as u64;
-            Ok(bytes_read)
         })
     }
 }
@@ -500,9 +1074,8 @@ where
     R: Read + Seek,
 {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        self.reader.seek(pos).and_then(|posn| {
+        self.reader.seek(pos).inspect(|&posn| {
             self.pos = posn;
-            Ok(posn)
         })
     }
 }
@@ -1,11 +1,17 @@
 //! Different implementations of `KvsEngine`
 mod kvs;
+mod offload;
 mod sled;
 
-pub use self::kvs::KvStore;
-pub use self::sled::SledKvsEngine;
+pub use self::kvs::{Codec, KvStore, KvStoreConfig, KvStoreStats};
+pub use self::offload::OffloadKvsEngine;
+pub use self::sled::{SledKvsEngine, SledKvsEngineConfig};
 
+use crate::replication::Replication;
 use crate::{Error, ErrorKind, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
 use std::str::FromStr;
 
 /// Define the interface of a key-value store
@@ -18,6 +24,186 @@ pub trait KvsEngine: Clone + Send + 'static {
 
     /// Removes a key.
     fn remove(&self, key: String) -> Result<()>;
+
+    /// Atomically adds `delta` to the integer value of a key and returns the new value. A key
+    /// that does not exist is treated as having the value `0`.
+    fn increment(&self, key: String, delta: i64) -> Result<i64>;
+
+    /// Atomically appends `suffix` to the value of a key. A key that does not exist is treated
+    /// as having an empty value.
+    fn append(&self, key: String, suffix: String) -> Result<()>;
+
+    /// Applies all operations in the batch, either all of them take effect or none of them does.
+    /// Removing a key that does not exist fails the whole batch.
+    fn apply_batch(&self, batch: WriteBatch) -> Result<()>;
+
+    /// Makes sure every completed write is persisted to disk.
+    fn flush(&self) -> Result<()>;
+
+    /// Returns up to `limit` keys that are greater than or equal to `start`, in ascending order.
+    /// The keys after the last returned key `k` can be listed by scanning again from
+    /// `scan_successor(&k)`.
+    fn scan(&self, start: String, limit: usize) -> Result<Vec<String>>;
+
+    /// Reclaims the space of overwritten and removed values now, instead of waiting for the
+    /// engine to do it on its own.
+    fn compact(&self) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Engine does not support compaction on demand",
+        ))
+    }
+
+    /// Writes a copy of every completed write to a new directory at `path`, which can be opened
+    /// as an engine of the same kind. Writes wait until the copy is done, so it holds the data at
+    /// a single point in time. The directory must not exist yet, an existing one is an `Io`
+    /// error.
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        let _ = path;
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Engine does not support checkpoints",
+        ))
+    }
+
+    /// Returns named counters that describe the state of the engine.
+    fn statistics(&self) -> Result<Vec<(String, u64)>> {
+        Ok(Vec::new())
+    }
+
+    /// Returns the replication state of the engine. Engines that do not record their changes,
+    /// which is every engine that is not wrapped in a `ReplicatedKvsEngine`, return `None`.
+    fn replication(&self) -> Option<&Replication> {
+        None
+    }
+}
+
+/// Returns the smallest key that is greater than `key`, it is used to continue a scan
+pub fn scan_successor(key: &str) -> String {
+    let mut successor = String::with_capacity(key.len() + 1);
+    successor.push_str(key);
+    successor.push('\0');
+    successor
+}
+
+/// Define the interface of a key-value store whose operations complete asynchronously, a blocking
+/// `KvsEngine` can be turned into one with `OffloadKvsEngine`
+pub trait AsyncKvsEngine: Clone + Send + 'static {
+    /// Sets a value to a key.
+    fn set(&self, key: String, value: String) -> BoxFuture<'static, Result<()>>;
+
+    /// Returns the value of a key, if the key exists. Otherwise, returns `None`.
+    fn get(&self, key: String) -> BoxFuture<'static, Result<Option<String>>>;
+
+    /// Removes a key.
+    fn remove(&self, key: String) -> BoxFuture<'static, Result<()>>;
+
+    /// Atomically adds `delta` to the integer value of a key and returns the new value.
+    fn increment(&self, key: String, delta: i64) -> BoxFuture<'static, Result<i64>>;
+
+    /// Atomically appends `suffix` to the value of a key.
+    fn append(&self, key: String, suffix: String) -> BoxFuture<'static, Result<()>>;
+
+    /// Applies all operations in the batch, either all of them take effect or none of them does.
+    fn apply_batch(&self, batch: WriteBatch) -> BoxFuture<'static, Result<()>>;
+
+    /// Makes sure every completed write is persisted to disk.
+    fn flush(&self) -> BoxFuture<'static, Result<()>>;
+
+    /// Returns up to `limit` keys that are greater than or equal to `start`, in ascending order.
+    fn scan(&self, start: String, limit: usize) -> BoxFuture<'static, Result<Vec<String>>>;
+}
+
+/// A group of write operations that are applied atomically with `KvsEngine::apply_batch`
+///
+/// # Usages
+///
+/// ```
+/// use kvs::engines::WriteBatch;
+/// use kvs::{KvStore, KvsEngine, Result};
+/// use tempfile::TempDir;
+///
+/// fn main() -> Result<()> {
+///     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+///     let kvs = KvStore::open(temp_dir.path())?;
+///
+///     let mut batch = WriteBatch::default();
+///     batch.set("key1".to_string(), "val1".to_string());
+///     batch.set("key2".to_string(), "val2".to_string());
+///     batch.remove("key1".to_string());
+///     kvs.apply_batch(batch)?;
+///
+///     assert_eq!(kvs.get("key1".to_string())?, None);
+///     assert_eq!(kvs.get("key2".to_string())?, Some("val2".to_string()));
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<WriteOp>,
+}
+
+impl WriteBatch {
+    /// Queues setting a value to a key
+    pub fn set(&mut self, key: String, value: String) {
+        self.ops.push(WriteOp::Set { key, value });
+    }
+
+    /// Queues removing a key
+    pub fn remove(&mut self, key: String) {
+        self.ops.push(WriteOp::Remove { key });
+    }
+
+    /// Returns the queued operations in the order they were added
+    pub fn ops(&self) -> &[WriteOp] {
+        &self.ops
+    }
+
+    /// Returns the number of queued operations
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns `true` if no operation was queued
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// A write operation in a `WriteBatch`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteOp {
+    /// Sets a value to a key
+    Set {
+        /// Key to set
+        key: String,
+        /// Value to set
+        value: String,
+    },
+    /// Removes a key
+    Remove {
+        /// Key to remove
+        key: String,
+    },
+}
+
+/// Parses the value of a key as an integer and adds `delta` to it
+fn add_to_value(key: &str, val: Option<&str>, delta: i64) -> Result<i64> {
+    let current = match val {
+        None => 0,
+        Some(val) => val.parse::<i64>().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidValue,
+                format!("Value of key '{}' is not an integer", key),
+            )
+        })?,
+    };
+    current.checked_add(delta).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidValue,
+            format!("Value of key '{}' would overflow", key),
+        )
+    })
 }
 
 /// Different engines that can be used for the key-value store
@@ -0,0 +1,131 @@
+//! An `AsyncKvsEngine` that offloads method calls of a blocking `KvsEngine` to a thread pool.
+
+use crate::engines::{AsyncKvsEngine, WriteBatch};
+use crate::thread_pool::ThreadPool;
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, FutureExt};
+use std::sync::Arc;
+
+/// An adapter that runs every operation of the wrapped `KvsEngine` on a thread pool and
+/// completes the returned future once the operation is done, so that the executor polling the
+/// future is never blocked by the engine.
+///
+/// # Usages
+///
+/// ```
+/// use kvs::engines::{AsyncKvsEngine, OffloadKvsEngine};
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, Result};
+/// use tempfile::TempDir;
+///
+/// fn main() -> Result<()> {
+///     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+///     let pool = SharedQueueThreadPool::new(4)?;
+///     let kvs = OffloadKvsEngine::new(KvStore::open(temp_dir.path())?, pool);
+///
+///     futures::executor::block_on(async {
+///         kvs.set("key".to_string(), "val".to_string()).await?;
+///         let val = kvs.get("key".to_string()).await?;
+///         assert_eq!(val, Some("val".to_string()));
+///         Ok(())
+///     })
+/// }
+/// ```
+#[derive(Debug)]
+pub struct OffloadKvsEngine<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    engine: E,
+    pool: Arc<P>,
+}
+
+impl<E, P> Clone for OffloadKvsEngine<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    fn clone(&self) -> Self {
+        Self {
+            engine: self.engine.clone(),
+            pool: Arc::clone(&self.pool),
+        }
+    }
+}
+
+impl<E, P> OffloadKvsEngine<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + Sync + 'static,
+{
+    /// Creates a new adapter that runs the operations of `engine` on `pool`
+    pub fn new(engine: E, pool: P) -> Self {
+        Self {
+            engine,
+            pool: Arc::new(pool),
+        }
+    }
+
+    fn offload<T, F>(&self, f: F) -> BoxFuture<'static, Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(E) -> Result<T> + Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+        let engine = self.engine.clone();
+        self.pool.spawn(move || {
+            // the caller might have dropped the future, the result is not needed anymore
+            result_tx.send(f(engine)).ok();
+        });
+
+        async move {
+            result_rx.await.map_err(|_| {
+                Error::new(
+                    ErrorKind::Canceled,
+                    "Engine operation did not complete on the thread pool",
+                )
+            })?
+        }
+        .boxed()
+    }
+}
+
+impl<E, P> AsyncKvsEngine for OffloadKvsEngine<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + Sync + 'static,
+{
+    fn set(&self, key: String, value: String) -> BoxFuture<'static, Result<()>> {
+        self.offload(move |engine| engine.set(key, value))
+    }
+
+    fn get(&self, key: String) -> BoxFuture<'static, Result<Option<String>>> {
+        self.offload(move |engine| engine.get(key))
+    }
+
+    fn remove(&self, key: String) -> BoxFuture<'static, Result<()>> {
+        self.offload(move |engine| engine.remove(key))
+    }
+
+    fn increment(&self, key: String, delta: i64) -> BoxFuture<'static, Result<i64>> {
+        self.offload(move |engine| engine.increment(key, delta))
+    }
+
+    fn append(&self, key: String, suffix: String) -> BoxFuture<'static, Result<()>> {
+        self.offload(move |engine| engine.append(key, suffix))
+    }
+
+    fn apply_batch(&self, batch: WriteBatch) -> BoxFuture<'static, Result<()>> {
+        self.offload(move |engine| engine.apply_batch(batch))
+    }
+
+    fn flush(&self) -> BoxFuture<'static, Result<()>> {
+        self.offload(|engine| engine.flush())
+    }
+
+    fn scan(&self, start: String, limit: usize) -> BoxFuture<'static, Result<Vec<String>>> {
+        self.offload(move |engine| engine.scan(start, limit))
+    }
+}
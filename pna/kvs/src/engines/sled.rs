@@ -1,42 +1,276 @@
 //! An `KvsEngine` that proxies method calls to the underlying `sled` key-value store.
 
+use crate::engines::{WriteBatch, WriteOp};
 use crate::{Error, ErrorKind, KvsEngine, Result};
+use sled::transaction::{self, TransactionError};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
 
 /// A key-value store that uses sled as the underlying data storage engine
+///
+/// # Usages
+///
+/// ```
+/// use kvs::engines::{SledKvsEngine, SledKvsEngineConfig};
+/// use kvs::{KvsEngine, Result};
+/// use tempfile::TempDir;
+///
+/// fn main() -> Result<()> {
+///     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+///     let config = SledKvsEngineConfig::default()
+///         .cache_capacity(64 * 1024 * 1024)
+///         .sync_writes(true);
+///     let kvs = SledKvsEngine::open_with_config(temp_dir.path(), config)?;
+///
+///     kvs.set("key".to_string(), "val".to_string())?;
+///     assert_eq!(kvs.get("key".to_string())?, Some("val".to_string()));
+///     Ok(())
+/// }
+/// ```
 #[derive(Debug, Clone)]
 pub struct SledKvsEngine {
     db: sled::Db,
+    sync_writes: bool,
+    // writes hold it shared, a checkpoint holds it exclusively so that it copies a single point
+    // in time
+    checkpoint_lock: Arc<RwLock<()>>,
 }
 
 impl SledKvsEngine {
     /// Creates a new proxy that forwards method calls to the underlying key-value store
     pub fn new(db: sled::Db) -> Self {
-        Self { db }
+        Self {
+            db,
+            sync_writes: false,
+            checkpoint_lock: Arc::default(),
+        }
+    }
+
+    /// Open the sled database at the given path and return the engine to the caller.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::open_with_config(path, SledKvsEngineConfig::default())
+    }
+
+    /// Open the sled database at the given path using the given configuration and return the
+    /// engine to the caller.
+    pub fn open_with_config<P>(path: P, config: SledKvsEngineConfig) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut sled_config = sled::Config::default()
+            .path(path.as_ref())
+            .use_compression(config.compression)
+            .flush_every_ms(config.flush_every_ms);
+        if let Some(cache_capacity) = config.cache_capacity {
+            sled_config = sled_config.cache_capacity(cache_capacity);
+        }
+        let db = sled_config.open()?;
+        Ok(Self {
+            db,
+            sync_writes: config.sync_writes,
+            checkpoint_lock: Arc::default(),
+        })
+    }
+
+    /// Flushes the database if every write has to be durable once it returns
+    fn after_write(&self) -> Result<()> {
+        if self.sync_writes {
+            self.db.flush()?;
+        }
+        Ok(())
     }
 }
 
 impl KvsEngine for SledKvsEngine {
     fn set(&self, key: String, value: String) -> Result<()> {
+        let _guard = self.checkpoint_lock.read().unwrap();
         self.db.insert(key, value.as_bytes())?;
-        Ok(())
+        self.after_write()
     }
 
+    /// # Error
+    ///
+    /// If the stored value is not valid UTF-8 returns an `InvalidValue` error.
     fn get(&self, key: String) -> Result<Option<String>> {
         self.db
-            .get(key.as_bytes())
-            .map(|val| {
-                // NOTE: Since the value is inserted as a string, using unwrap is ok
-                val.map(|iv| iv.to_vec())
-                    .map(|v| String::from_utf8(v).unwrap())
-            })
-            .map_err(Error::from)
+            .get(key.as_bytes())?
+            .map(|val| into_string(&key, &val))
+            .transpose()
     }
 
     fn remove(&self, key: String) -> Result<()> {
-        self.db.remove(key.as_bytes())?.ok_or(Error::new(
-            ErrorKind::KeyNotFound,
-            format!("Key '{}' does not exist", key),
-        ))?;
+        let _guard = self.checkpoint_lock.read().unwrap();
+        self.db.remove(key.as_bytes())?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::KeyNotFound,
+                format!("Key '{}' does not exist", key),
+            )
+        })?;
+        self.after_write()
+    }
+
+    fn increment(&self, key: String, delta: i64) -> Result<i64> {
+        let _guard = self.checkpoint_lock.read().unwrap();
+        // the closure might be called multiple times, the result is kept from the latest call
+        let mut result = Ok(0);
+        self.db.update_and_fetch(key.as_bytes(), |old| {
+            result = old
+                .map(|v| into_string(&key, v))
+                .transpose()
+                .and_then(|val| super::add_to_value(&key, val.as_deref(), delta));
+            match result {
+                Ok(val) => Some(val.to_string().into_bytes()),
+                Err(_) => old.map(|v| v.to_vec()),
+            }
+        })?;
+        let val = result?;
+        self.after_write()?;
+        Ok(val)
+    }
+
+    fn append(&self, key: String, suffix: String) -> Result<()> {
+        let _guard = self.checkpoint_lock.read().unwrap();
+        self.db.update_and_fetch(key.as_bytes(), |old| {
+            let mut val = old.map(|v| v.to_vec()).unwrap_or_default();
+            val.extend_from_slice(suffix.as_bytes());
+            Some(val)
+        })?;
+        self.after_write()
+    }
+
+    /// Applies the batch in a sled transaction.
+    ///
+    /// # Error
+    ///
+    /// If the batch removes a key that doesn't exist returns a `KeyNotFound` error, and the
+    /// transaction is aborted.
+    fn apply_batch(&self, batch: WriteBatch) -> Result<()> {
+        let _guard = self.checkpoint_lock.read().unwrap();
+        self.db
+            .transaction(|tx| {
+                for op in batch.ops() {
+                    match op {
+                        WriteOp::Set { key, value } => {
+                            tx.insert(key.as_bytes(), value.as_bytes())?;
+                        }
+                        WriteOp::Remove { key } => {
+                            if tx.remove(key.as_bytes())?.is_none() {
+                                return transaction::abort(Error::new(
+                                    ErrorKind::KeyNotFound,
+                                    format!("Key '{}' does not exist", key),
+                                ));
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => Error::from(err),
+            })?;
+        self.after_write()
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.db.flush()?;
         Ok(())
     }
+
+    fn scan(&self, start: String, limit: usize) -> Result<Vec<String>> {
+        self.db
+            .range(start.as_bytes()..)
+            .keys()
+            .take(limit)
+            .map(|key| {
+                let key = key?;
+                String::from_utf8(key.to_vec()).map_err(|_| {
+                    Error::new(ErrorKind::InvalidValue, "Stored key is not valid UTF-8")
+                })
+            })
+            .collect()
+    }
+
+    /// Copies the entries into a new database while writes through this engine wait. Writes to
+    /// the same `sled::Db` that do not go through the engine may or may not be part of the copy.
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        let _guard = self.checkpoint_lock.write().unwrap();
+        fs::create_dir_all(path.parent().unwrap_or(path))?;
+        fs::create_dir(path)?;
+        let copy = sled::open(path)?;
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            copy.insert(key, value)?;
+        }
+        copy.flush()?;
+        Ok(())
+    }
+
+    fn statistics(&self) -> Result<Vec<(String, u64)>> {
+        Ok(vec![
+            ("keys".to_owned(), self.db.len() as u64),
+            ("size_on_disk".to_owned(), self.db.size_on_disk()?),
+        ])
+    }
+}
+
+/// Options that are used when opening a `SledKvsEngine`
+#[derive(Debug, Clone)]
+pub struct SledKvsEngineConfig {
+    cache_capacity: Option<u64>,
+    compression: bool,
+    flush_every_ms: Option<u64>,
+    sync_writes: bool,
+}
+
+impl Default for SledKvsEngineConfig {
+    fn default() -> Self {
+        Self {
+            cache_capacity: None,
+            compression: false,
+            flush_every_ms: Some(500),
+            sync_writes: false,
+        }
+    }
+}
+
+impl SledKvsEngineConfig {
+    /// Maximum size in bytes of the in-memory page cache, uses sled's default when not set
+    pub fn cache_capacity(mut self, bytes: u64) -> Self {
+        self.cache_capacity = Some(bytes);
+        self
+    }
+
+    /// Compress data with zstd before it is written to disk. A database must always be opened
+    /// with the same setting that it was created with.
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Interval in milliseconds between background flushes, `None` disables them so that data
+    /// is only persisted on explicit calls to `flush`
+    pub fn flush_every_ms(mut self, every_ms: Option<u64>) -> Self {
+        self.flush_every_ms = every_ms;
+        self
+    }
+
+    /// Flush after every write, so that a write is durable once it returns
+    pub fn sync_writes(mut self, enabled: bool) -> Self {
+        self.sync_writes = enabled;
+        self
+    }
+}
+
+fn into_string(key: &str, val: &[u8]) -> Result<String> {
+    String::from_utf8(val.to_vec()).map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidValue,
+            format!("Value of key '{}' is not valid UTF-8", key),
+        )
+    })
 }
@@ -19,8 +19,6 @@ enum Repr {
 
     /// I/O error
     Io(std::io::Error),
-    /// Sled error
-    Sled(sled::Error),
     /// Bincode error
     Bincode(bincode::Error),
     /// Serde JSON error
@@ -42,6 +40,33 @@ impl Error {
     }
 }
 
+impl Error {
+    /// Returns the message of this error without the description of its kind
+    pub(crate) fn message(&self) -> String {
+        match self.repr {
+            Repr::Simple(ref kind) => kind.as_str().to_owned(),
+            Repr::Custom(ref repr) => repr.error.to_string(),
+            Repr::Io(ref err) => err.to_string(),
+            Repr::Bincode(ref err) => err.to_string(),
+            Repr::SerdeJson(ref err) => err.to_string(),
+            Repr::RayonThreadPoolBuildError(ref err) => err.to_string(),
+        }
+    }
+
+    /// Returns the type of this error
+    pub fn kind(&self) -> ErrorKind {
+        match self.repr {
+            Repr::Simple(kind) => kind,
+            Repr::Custom(ref repr) => repr.kind,
+            Repr::Io(_) => ErrorKind::Io,
+            // the connection was closed or failed in the middle of a message
+            Repr::SerdeJson(ref err) if err.is_io() || err.is_eof() => ErrorKind::Io,
+            Repr::Bincode(_) | Repr::SerdeJson(_) => ErrorKind::Serialization,
+            Repr::RayonThreadPoolBuildError(_) => ErrorKind::Internal,
+        }
+    }
+}
+
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
         Self {
@@ -58,7 +83,6 @@ impl std::fmt::Display for Error {
             Repr::Simple(ref kind) => write!(f, "{}", kind.as_str()),
             Repr::Custom(ref repr) => write!(f, "{} ({})", repr.error, repr.kind.as_str()),
             Repr::Io(ref err) => write!(f, "{} (i/o error)", err),
-            Repr::Sled(ref err) => write!(f, "{} (sled error)", err),
             Repr::Bincode(ref err) => write!(f, "{} (bincode (de)serialization error)", err),
             Repr::SerdeJson(ref err) => write!(f, "{} (json (de)serialization error)", err),
             Repr::RayonThreadPoolBuildError(ref err) => {
@@ -78,9 +102,13 @@ impl From<std::io::Error> for Error {
 
 impl From<sled::Error> for Error {
     fn from(err: sled::Error) -> Self {
-        Self {
-            repr: Repr::Sled(err),
-        }
+        let kind = match err {
+            sled::Error::Io(err) => return Self::from(err),
+            sled::Error::Corruption { .. } => ErrorKind::CorruptedLog,
+            sled::Error::Unsupported(_) => ErrorKind::Unsupported,
+            _ => ErrorKind::Internal,
+        };
+        Self::new(kind, err)
     }
 }
 
@@ -115,7 +143,7 @@ struct CustomRepr {
 }
 
 /// Types of error
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum ErrorKind {
     /// Operation on a non-existent key
@@ -124,12 +152,34 @@ pub enum ErrorKind {
     CorruptedLog,
     /// Faulty in-memory index
     CorruptedIndex,
+    /// The value of a key can not be used for the requested operation
+    InvalidValue,
     /// An unexpected message from the network is received
     InvalidNetworkMessage,
     /// Wrong engine provided when constructing a key-value store
     UnsupportedKvsEngine,
+    /// Wrong compression codec provided when configuring a key-value store
+    UnsupportedCodec,
     /// Error that was originated from the remote server
     ServerError,
+    /// An operation was dropped before it could complete
+    Canceled,
+    /// Failed to read from or write to the file system or the network
+    Io,
+    /// Failed to serialize or deserialize data
+    Serialization,
+    /// The storage engine was used in an unsupported way
+    Unsupported,
+    /// An unexpected internal failure
+    Internal,
+    /// The server reached one of its limits and did not serve the request
+    ServerBusy,
+    /// A write was sent to a replica, which only serves reads
+    ReadOnly,
+    /// The client did not prove who it is, or its proof was wrong
+    Unauthenticated,
+    /// The user of the connection may not read or write the keys of the request
+    PermissionDenied,
 }
 
 impl ErrorKind {
@@ -138,9 +188,20 @@ impl ErrorKind {
             Self::KeyNotFound => "Key not found",
             Self::CorruptedLog => "Corrupted on-disk log",
             Self::CorruptedIndex => "Corrupted in-memory index",
+            Self::InvalidValue => "Invalid value for the operation",
             Self::InvalidNetworkMessage => "Received an invalid network message",
             Self::UnsupportedKvsEngine => "Unsupported key-value store engine",
+            Self::UnsupportedCodec => "Unsupported compression codec",
             Self::ServerError => "Remote server error",
+            Self::Canceled => "Operation canceled",
+            Self::Io => "I/O error",
+            Self::Serialization => "(De)serialization error",
+            Self::Unsupported => "Unsupported operation",
+            Self::Internal => "Internal error",
+            Self::ServerBusy => "Server is busy",
+            Self::ReadOnly => "Server is read-only",
+            Self::Unauthenticated => "Authentication failed",
+            Self::PermissionDenied => "Permission denied",
         }
     }
 }
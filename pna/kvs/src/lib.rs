@@ -9,8 +9,9 @@ extern crate slog;
 pub mod engines;
 pub mod error;
 pub mod networking;
+pub mod replication;
 pub mod thread_pool;
 
-pub use engines::{KvStore, KvsEngine, SledKvsEngine};
+pub use engines::{AsyncKvsEngine, KvStore, KvsEngine, SledKvsEngine};
 pub use error::{Error, ErrorKind, Result};
 pub use networking::{KvsClient, KvsServer};
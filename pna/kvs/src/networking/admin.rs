@@ -0,0 +1,211 @@
+use crate::networking::auth::constant_time_eq;
+use crate::networking::protocol::{self, AdminCommand, AdminReply, AdminResponse, RemoteError};
+use crate::networking::{Connection, Response};
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use slog::{Drain, Level, OwnedKVList, Record};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Lets clients run maintenance commands on a server: compaction, flushes, checkpoints,
+/// statistics, the log level and the list of connections. Only the JSON protocol accepts admin
+/// requests.
+///
+/// Requests must carry the admin token if one is set. On a server that authenticates its
+/// clients, the user of the connection must also be an admin, see `User::admin`.
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{AdminConfig, JsonKvsServer, LogLevel, ServerConfig};
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, KvsServer, Result};
+/// use slog::Drain;
+///
+/// fn main() -> Result<()> {
+///     let level = LogLevel::new(slog::Level::Info);
+///     let decorator = slog_term::TermDecorator::new().build();
+///     let drain = slog_term::FullFormat::new(decorator).build().fuse();
+///     let drain = slog_async::Async::new(level.filter(drain).fuse()).build().fuse();
+///     let logger = slog::Logger::root(drain, slog::o!());
+///
+///     let engine = KvStore::open(std::env::current_dir()?)?;
+///     let pool = SharedQueueThreadPool::new(4)?;
+///     let admin = AdminConfig::default().token("admin-secret").log_level(level);
+///     let config = ServerConfig::default().admin(admin);
+///     let server = JsonKvsServer::with_config(engine, pool, config, Some(logger));
+///     server.serve(([127, 0, 0, 1], 4000))?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct AdminConfig {
+    token: Option<String>,
+    log_level: Option<LogLevel>,
+}
+
+impl AdminConfig {
+    /// Requires admin requests to carry `token`
+    pub fn token<T>(mut self, token: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Level of the server's log that admin requests can change, servers without one refuse
+    /// to change their log level
+    pub fn log_level(mut self, level: LogLevel) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    fn check_token(&self, token: Option<&str>) -> Result<()> {
+        match (&self.token, token) {
+            (None, _) => Ok(()),
+            (Some(expected), Some(token))
+                if constant_time_eq(expected.as_bytes(), token.as_bytes()) =>
+            {
+                Ok(())
+            }
+            (Some(_), _) => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Admin request does not carry the admin token of the server",
+            )),
+        }
+    }
+
+    fn set_log_level(&self, level: &str) -> Result<()> {
+        let log_level = self.log_level.as_ref().ok_or_else(|| {
+            Error::new(
+                ErrorKind::Unsupported,
+                "Server does not change its log level at runtime",
+            )
+        })?;
+        let level = Level::from_str(level).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidValue,
+                format!("Could not found log level named '{}'", level),
+            )
+        })?;
+        log_level.set(level);
+        Ok(())
+    }
+}
+
+// the token is left out so that it does not end up in logs
+impl fmt::Debug for AdminConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminConfig")
+            .field("token", &self.token.as_ref().map(|_| "..."))
+            .field("log_level", &self.log_level)
+            .finish()
+    }
+}
+
+/// Least severe level of the records that a logger keeps, shared between the drain that filters
+/// the records and whoever changes it
+#[derive(Debug, Clone)]
+pub struct LogLevel(Arc<AtomicUsize>);
+
+impl LogLevel {
+    /// Creates a level that keeps the records of `level` and the more severe ones
+    pub fn new(level: Level) -> Self {
+        Self(Arc::new(AtomicUsize::new(level.as_usize())))
+    }
+
+    /// Returns the current level
+    pub fn get(&self) -> Level {
+        Level::from_usize(self.0.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+    }
+
+    /// Changes the level, the drains that filter with it see the change right away
+    pub fn set(&self, level: Level) {
+        self.0.store(level.as_usize(), Ordering::Relaxed);
+    }
+
+    /// Wraps `drain` so that it only gets the records that are at least as severe as the level
+    pub fn filter<D>(&self, drain: D) -> LogLevelFilter<D>
+    where
+        D: Drain,
+    {
+        LogLevelFilter {
+            drain,
+            level: self.clone(),
+        }
+    }
+}
+
+/// Drain that passes on the records of at least a `LogLevel`, like `slog::LevelFilter` but with a
+/// level that can change
+#[derive(Debug)]
+pub struct LogLevelFilter<D> {
+    drain: D,
+    level: LogLevel,
+}
+
+impl<D> Drain for LogLevelFilter<D>
+where
+    D: Drain,
+{
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(
+        &self,
+        record: &Record<'_>,
+        values: &OwnedKVList,
+    ) -> std::result::Result<Self::Ok, Self::Err> {
+        if record.level().is_at_least(self.level.get()) {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        level.is_at_least(self.level.get()) && self.drain.is_enabled(level)
+    }
+}
+
+/// Runs an admin command that passed the authentication of the connection, if the server
+/// accepts admin requests and the token matches
+pub(crate) fn execute<E>(
+    engine: &E,
+    admin: Option<&AdminConfig>,
+    connection: &Connection,
+    token: Option<&str>,
+    command: AdminCommand,
+) -> Response
+where
+    E: KvsEngine,
+{
+    let result = admin
+        .ok_or_else(protocol::no_admin)
+        .and_then(|admin| admin.check_token(token).map(|_| admin))
+        .and_then(|admin| {
+            info!(connection.logger, "Admin request"; "command" => format!("{:?}", command));
+            match command {
+                AdminCommand::Compact => engine.compact().map(|_| AdminReply::Done),
+                AdminCommand::Flush => engine.flush().map(|_| AdminReply::Done),
+                AdminCommand::Checkpoint { path } => engine
+                    .checkpoint(Path::new(&path))
+                    .map(|_| AdminReply::Done),
+                AdminCommand::Stats => engine.statistics().map(|mut stats| {
+                    stats.push(("connections".to_owned(), connection.clients().len() as u64));
+                    AdminReply::Stats(stats)
+                }),
+                AdminCommand::SetLogLevel { level } => {
+                    admin.set_log_level(&level).map(|_| AdminReply::Done)
+                }
+                AdminCommand::Clients => Ok(AdminReply::Clients(connection.clients())),
+            }
+        });
+    Response::Admin(match result {
+        Ok(reply) => AdminResponse::Ok(reply),
+        Err(err) => AdminResponse::Err(RemoteError::from(&err)),
+    })
+}
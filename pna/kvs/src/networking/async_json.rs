@@ -0,0 +1,600 @@
+use crate::engines::{AsyncKvsEngine, WriteBatch};
+use crate::networking::config::DEFAULT_MAX_REQUEST_SIZE;
+use crate::networking::protocol::{
+    self, AppendResponse, Envelope, GetResponse, Incoming, IncrementResponse, MultiGetResponse,
+    MultiRemoveResponse, MultiSetResponse, PromoteResponse, RemoteError, RemoveResponse,
+    ReplicationMessage, ReplicationStatusResponse, Request, Response, ScanResponse, SetResponse,
+    WatchMessage,
+};
+use crate::networking::transport::Listener;
+use crate::networking::Addr;
+use crate::{Error, ErrorKind, Result};
+use futures::{future, FutureExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Reading half of a connection over either transport
+type ReadHalf = Box<dyn AsyncRead + Unpin + Send>;
+
+/// Writing half of a connection over either transport
+type WriteHalf = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// Asynchronous network client for JSON message, it talks to both `JsonKvsServer` and
+/// `AsyncJsonKvsServer`
+#[allow(missing_debug_implementations)]
+pub struct AsyncJsonKvsClient {
+    rstream: JsonReader<ReadHalf>,
+    wstream: WriteHalf,
+}
+
+impl AsyncJsonKvsClient {
+    /// Connect to the remote server at `addr` and return the client to it
+    pub async fn connect<A>(addr: A) -> Result<Self>
+    where
+        A: Into<Addr>,
+    {
+        let (rstream, wstream) = match addr.into() {
+            Addr::Tcp(addr) => split(TcpStream::connect(addr).await?.into_split()),
+            #[cfg(unix)]
+            Addr::Unix(path) => split(UnixStream::connect(path).await?.into_split()),
+        };
+        Ok(Self {
+            rstream: JsonReader::new(rstream, DEFAULT_MAX_REQUEST_SIZE),
+            wstream,
+        })
+    }
+
+    /// Send set command
+    pub async fn set(&mut self, key: String, value: String) -> Result<()> {
+        match self.call(&Request::Set { key, value }).await? {
+            SetResponse::Ok => Ok(()),
+            SetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Send get command
+    pub async fn get(&mut self, key: String) -> Result<Option<String>> {
+        match self.call(&Request::Get { key }).await? {
+            GetResponse::Ok(val) => Ok(val),
+            GetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Send remove command
+    pub async fn remove(&mut self, key: String) -> Result<()> {
+        match self.call(&Request::Remove { key }).await? {
+            RemoveResponse::Ok => Ok(()),
+            RemoveResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Send increment command
+    pub async fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        match self.call(&Request::Increment { key, delta }).await? {
+            IncrementResponse::Ok(val) => Ok(val),
+            IncrementResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Send append command
+    pub async fn append(&mut self, key: String, suffix: String) -> Result<()> {
+        match self.call(&Request::Append { key, suffix }).await? {
+            AppendResponse::Ok => Ok(()),
+            AppendResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Send get command for many keys
+    pub async fn multi_get(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        match self.call(&Request::MultiGet { keys }).await? {
+            MultiGetResponse::Ok(vals) => Ok(vals),
+            MultiGetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Send set command for many keys
+    pub async fn multi_set(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        match self.call(&Request::MultiSet { pairs }).await? {
+            MultiSetResponse::Ok => Ok(()),
+            MultiSetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Send remove command for many keys
+    pub async fn multi_remove(&mut self, keys: Vec<String>) -> Result<u64> {
+        match self.call(&Request::MultiRemove { keys }).await? {
+            MultiRemoveResponse::Ok(removed) => Ok(removed),
+            MultiRemoveResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Send scan command
+    pub async fn scan(&mut self, start: String, limit: usize) -> Result<Vec<String>> {
+        match self.call(&Request::Scan { start, limit }).await? {
+            ScanResponse::Ok(keys) => Ok(keys),
+            ScanResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    async fn call<T>(&mut self, request: &Request) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        write_json(&mut self.wstream, request).await?;
+        self.rstream.read().await?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                "Connection was closed before a response was received",
+            )
+        })
+    }
+}
+
+/// Asynchronous network server for JSON message, it serves both `JsonKvsClient` and
+/// `AsyncJsonKvsClient`. Each connection is handled by a task on the executor that runs `serve`,
+/// instead of occupying a thread for the lifetime of the connection.
+#[derive(Debug)]
+pub struct AsyncJsonKvsServer<E>
+where
+    E: AsyncKvsEngine,
+{
+    engine: E,
+    logger: slog::Logger,
+    max_request_size: usize,
+}
+
+impl<E> AsyncJsonKvsServer<E>
+where
+    E: AsyncKvsEngine,
+{
+    /// Create a new asynchronous JSON server
+    pub fn new(engine: E, logger: Option<slog::Logger>) -> Self {
+        let logger = logger.unwrap_or_else(super::default_logger);
+        Self {
+            engine,
+            logger,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+        }
+    }
+
+    /// Close connections that send a request larger than `bytes`, after answering with an error
+    pub fn max_request_size(mut self, bytes: usize) -> Self {
+        self.max_request_size = bytes;
+        self
+    }
+
+    /// Start accepting requests on the given TCP address or Unix domain socket, the returned
+    /// future must be run on a Tokio runtime
+    pub fn serve<A>(&self, addr: A) -> impl Future<Output = Result<()>> + Send + 'static
+    where
+        A: Into<Addr>,
+    {
+        let addr = addr.into();
+        let engine = self.engine.clone();
+        let logger = self.logger.new(o!("addr" => addr.to_string()));
+        let max_request_size = self.max_request_size;
+
+        async move {
+            info!(logger, "Starting asynchronous key-value store server");
+            let listener = AsyncListener::bind(&addr)?;
+            loop {
+                let (stream, peer_addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        error!(logger, "Could not accept connection"; "error" => err);
+                        continue;
+                    }
+                };
+
+                let engine = engine.clone();
+                let logger = match peer_addr {
+                    Some(peer_addr) => logger.new(o!( "peer_addr" => peer_addr )),
+                    None => logger.clone(),
+                };
+                tokio::spawn(async move {
+                    if let Err(err) = Self::handle(engine, stream, max_request_size).await {
+                        error!(logger, "Could not handle client"; "error" => format!("{}", err));
+                    }
+                });
+            }
+        }
+    }
+
+    async fn handle(
+        engine: E,
+        (rstream, mut wstream): (ReadHalf, WriteHalf),
+        max_request_size: usize,
+    ) -> Result<()> {
+        let mut rstream = JsonReader::new(rstream, max_request_size);
+
+        loop {
+            let incoming = match rstream.read().await {
+                Ok(Some(incoming)) => incoming,
+                Ok(None) => break,
+                Err(err) if err.kind() == ErrorKind::InvalidNetworkMessage => {
+                    // the rest of the request can not be skipped reliably, so the connection is
+                    // closed
+                    let response = SetResponse::Err(RemoteError::from(&err));
+                    let _ = write_json(&mut wstream, &response).await;
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            };
+            match incoming {
+                Incoming::Tagged(Envelope { id, body }) => {
+                    let body = Self::execute(&engine, body).await;
+                    write_json(&mut wstream, &Envelope { id, body }).await?;
+                }
+                Incoming::Untagged(request) => {
+                    let response = Self::execute(&engine, request).await;
+                    write_json(&mut wstream, &response).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The engine's future is created before the returned future runs, so the returned future
+    /// does not borrow the engine
+    fn execute(engine: &E, request: Request) -> impl Future<Output = Response> + Send + 'static {
+        match request {
+            Request::Set { key, value } => engine
+                .set(key, value)
+                .map(|res| {
+                    Response::Set(match res {
+                        Ok(_) => SetResponse::Ok,
+                        Err(err) => SetResponse::Err(RemoteError::from(&err)),
+                    })
+                })
+                .boxed(),
+            Request::Get { key } => engine
+                .get(key)
+                .map(|res| {
+                    Response::Get(match res {
+                        Ok(v) => GetResponse::Ok(v),
+                        Err(err) => GetResponse::Err(RemoteError::from(&err)),
+                    })
+                })
+                .boxed(),
+            Request::Remove { key } => engine
+                .remove(key)
+                .map(|res| {
+                    Response::Remove(match res {
+                        Ok(_) => RemoveResponse::Ok,
+                        Err(err) => RemoveResponse::Err(RemoteError::from(&err)),
+                    })
+                })
+                .boxed(),
+            Request::Increment { key, delta } => engine
+                .increment(key, delta)
+                .map(|res| {
+                    Response::Increment(match res {
+                        Ok(v) => IncrementResponse::Ok(v),
+                        Err(err) => IncrementResponse::Err(RemoteError::from(&err)),
+                    })
+                })
+                .boxed(),
+            Request::Append { key, suffix } => engine
+                .append(key, suffix)
+                .map(|res| {
+                    Response::Append(match res {
+                        Ok(_) => AppendResponse::Ok,
+                        Err(err) => AppendResponse::Err(RemoteError::from(&err)),
+                    })
+                })
+                .boxed(),
+            Request::MultiGet { keys } => {
+                let gets: Vec<_> = keys.into_iter().map(|key| engine.get(key)).collect();
+                future::try_join_all(gets)
+                    .map(|res| {
+                        Response::MultiGet(match res {
+                            Ok(vals) => MultiGetResponse::Ok(vals),
+                            Err(err) => MultiGetResponse::Err(RemoteError::from(&err)),
+                        })
+                    })
+                    .boxed()
+            }
+            Request::MultiSet { pairs } => {
+                let mut batch = WriteBatch::default();
+                for (key, value) in pairs {
+                    batch.set(key, value);
+                }
+                engine
+                    .apply_batch(batch)
+                    .map(|res| {
+                        Response::MultiSet(match res {
+                            Ok(_) => MultiSetResponse::Ok,
+                            Err(err) => MultiSetResponse::Err(RemoteError::from(&err)),
+                        })
+                    })
+                    .boxed()
+            }
+            Request::MultiRemove { keys } => {
+                let removes: Vec<_> = keys.into_iter().map(|key| engine.remove(key)).collect();
+                future::join_all(removes)
+                    .map(|results| {
+                        let mut removed = 0;
+                        for res in results {
+                            match res {
+                                Ok(_) => removed += 1,
+                                Err(err) if err.kind() == ErrorKind::KeyNotFound => {}
+                                Err(err) => {
+                                    return Response::MultiRemove(MultiRemoveResponse::Err(
+                                        RemoteError::from(&err),
+                                    ))
+                                }
+                            }
+                        }
+                        Response::MultiRemove(MultiRemoveResponse::Ok(removed))
+                    })
+                    .boxed()
+            }
+            Request::Scan { start, limit } => engine
+                .scan(start, limit)
+                .map(|res| {
+                    Response::Scan(match res {
+                        Ok(keys) => ScanResponse::Ok(keys),
+                        Err(err) => ScanResponse::Err(RemoteError::from(&err)),
+                    })
+                })
+                .boxed(),
+            Request::Replicate { .. } => {
+                let err = RemoteError::from(&protocol::not_replicated());
+                future::ready(Response::Replicate(ReplicationMessage::Err(err))).boxed()
+            }
+            Request::Watch { .. } => {
+                let err = RemoteError::from(&protocol::not_replicated());
+                future::ready(Response::Watch(WatchMessage::Err(err))).boxed()
+            }
+            Request::Promote => {
+                let err = RemoteError::from(&protocol::not_replicated());
+                future::ready(Response::Promote(PromoteResponse::Err(err))).boxed()
+            }
+            Request::ReplicationStatus => {
+                let err = RemoteError::from(&protocol::not_replicated());
+                future::ready(Response::ReplicationStatus(ReplicationStatusResponse::Err(
+                    err,
+                )))
+                .boxed()
+            }
+            request @ Request::Challenge | request @ Request::Authenticate { .. } => {
+                future::ready(Response::err(&request, &protocol::no_auth())).boxed()
+            }
+            request @ Request::Admin { .. } => {
+                future::ready(Response::err(&request, &protocol::no_admin())).boxed()
+            }
+        }
+    }
+}
+
+/// Listener of `AsyncJsonKvsServer` on either transport
+enum AsyncListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl AsyncListener {
+    /// Binds like the blocking servers, which replace the stale socket file of a Unix domain
+    /// socket. Must be called on a Tokio runtime.
+    fn bind(addr: &Addr) -> io::Result<Self> {
+        match Listener::bind(addr)? {
+            Listener::Tcp(listener) => {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener).map(Self::Tcp)
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                listener.set_nonblocking(true)?;
+                UnixListener::from_std(listener).map(Self::Unix)
+            }
+        }
+    }
+
+    /// Returns the halves of the accepted connection and the address of the peer for logging
+    async fn accept(&self) -> io::Result<((ReadHalf, WriteHalf), Option<String>)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok((split(stream.into_split()), Some(peer_addr.to_string())))
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                let peer_addr = peer_addr
+                    .as_pathname()
+                    .map(|path| path.display().to_string());
+                Ok((split(stream.into_split()), peer_addr))
+            }
+        }
+    }
+}
+
+fn split<R, W>((rstream, wstream): (R, W)) -> (ReadHalf, WriteHalf)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    (Box::new(rstream), Box::new(wstream))
+}
+
+/// Reads consecutive JSON values from an asynchronous stream, the values are not delimited so
+/// bytes are buffered until a complete value has been received
+struct JsonReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    /// Start of the bytes in `buf` that were not returned as part of a value yet
+    start: usize,
+    scanner: ValueScanner,
+    max_size: usize,
+}
+
+impl<R> JsonReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn new(reader: R, max_size: usize) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            start: 0,
+            scanner: ValueScanner::default(),
+            max_size,
+        }
+    }
+
+    /// Returns the next value, or `None` if the stream was closed in between values
+    async fn read<T>(&mut self) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        loop {
+            // only the bytes that arrived since the last read are scanned, and the value is only
+            // parsed once it is complete
+            if let Some(len) = self.scanner.scan(&self.buf[self.start..]) {
+                return self.take(len).map(Some);
+            }
+            if self.buf.len() - self.start > self.max_size {
+                return Err(Error::new(
+                    ErrorKind::InvalidNetworkMessage,
+                    format!(
+                        "Message exceeds the maximum size of {} bytes",
+                        self.max_size
+                    ),
+                ));
+            }
+
+            // the bytes of the returned values are dropped once they make up most of the buffer,
+            // so that the remaining bytes are not moved after every value
+            if self.start > 0 && self.start >= self.buf.len() - self.start {
+                self.buf.drain(..self.start);
+                self.start = 0;
+            }
+
+            let len = self.buf.len();
+            self.buf.resize(len + READ_BUFFER_SIZE, 0);
+            let bytes_read = self.reader.read(&mut self.buf[len..]).await?;
+            self.buf.truncate(len + bytes_read);
+            if bytes_read == 0 {
+                return match self.scanner.state() {
+                    ScanState::Empty => Ok(None),
+                    // a number or a literal is only known to be complete at the end of the stream
+                    ScanState::Scalar => self.take(self.buf.len() - self.start).map(Some),
+                    ScanState::Incomplete => Err(Error::new(
+                        ErrorKind::InvalidNetworkMessage,
+                        "Connection was closed in the middle of a message",
+                    )),
+                };
+            }
+        }
+    }
+
+    /// Parses the value in the next `len` bytes
+    fn take<T>(&mut self, len: usize) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let end = self.start + len;
+        let value = serde_json::from_slice(&self.buf[self.start..end]);
+        self.start = end;
+        self.scanner = ValueScanner::default();
+        Ok(value?)
+    }
+}
+
+/// Finds where a JSON value ends without parsing it. The scan resumes where the previous one
+/// stopped, so every byte of a value is only looked at once however many reads it takes.
+#[derive(Debug, Default)]
+struct ValueScanner {
+    /// Number of bytes that were scanned
+    pos: usize,
+    /// Number of objects and arrays that are open
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    /// Whether a number or a literal is being scanned at the top level
+    in_scalar: bool,
+}
+
+enum ScanState {
+    /// Only whitespace was scanned
+    Empty,
+    /// A number or a literal was scanned, which ends at the end of the stream
+    Scalar,
+    /// Part of a value was scanned
+    Incomplete,
+}
+
+impl ValueScanner {
+    /// Returns the length of the value at the start of `buf`, including the whitespace before it,
+    /// or `None` if the value does not end in `buf`
+    fn scan(&mut self, buf: &[u8]) -> Option<usize> {
+        while let Some(&b) = buf.get(self.pos) {
+            if self.in_string {
+                self.pos += 1;
+                match b {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => {
+                        self.in_string = false;
+                        if self.depth == 0 {
+                            return Some(self.pos);
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            if self.in_scalar && (b.is_ascii_whitespace() || b"{}[]\",:".contains(&b)) {
+                return Some(self.pos);
+            }
+            self.pos += 1;
+            match b {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' => {
+                    // an unbalanced bracket ends the value, so that parsing reports it
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        return Some(self.pos);
+                    }
+                }
+                _ if b.is_ascii_whitespace() => {}
+                _ if self.depth == 0 => self.in_scalar = true,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    fn state(&self) -> ScanState {
+        if self.in_scalar {
+            ScanState::Scalar
+        } else if self.depth == 0 && !self.in_string {
+            ScanState::Empty
+        } else {
+            ScanState::Incomplete
+        }
+    }
+}
+
+async fn write_json<W, T>(writer: &mut W, value: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let bytes = serde_json::to_vec(value)?;
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,373 @@
+use crate::engines::scan_successor;
+use crate::networking::protocol::{
+    AuthenticateResponse, Challenge, ChallengeResponse, RemoteError, ScanResponse,
+};
+use crate::networking::{Request, Response};
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::de::{Deserializer, IoRead};
+use sha2::Sha256;
+use std::fmt;
+use std::io::{Read, Write};
+
+/// Number of random bytes in the nonce of an HMAC challenge
+const NONCE_SIZE: usize = 32;
+
+/// How clients prove that they know the token of a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    /// The client sends its token, which can be read by anyone who can see the traffic
+    Token,
+    /// The client sends an HMAC-SHA256 of a random nonce that is keyed with its token, the token
+    /// itself is never sent
+    Hmac,
+}
+
+/// A client of a server, identified by its token, with the prefixes of the keys that it can read
+/// and write. A key can be read or written if it starts with one of the prefixes, an empty prefix
+/// covers every key.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct User {
+    name: String,
+    token: String,
+    #[serde(default)]
+    read: Vec<String>,
+    #[serde(default)]
+    write: Vec<String>,
+    #[serde(default)]
+    admin: bool,
+}
+
+impl User {
+    /// Creates a user that can neither read nor write any key
+    pub fn new<N, T>(name: N, token: T) -> Self
+    where
+        N: Into<String>,
+        T: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            token: token.into(),
+            read: Vec::new(),
+            write: Vec::new(),
+            admin: false,
+        }
+    }
+
+    /// Lets the user read the keys that start with `prefix`
+    pub fn read<P>(mut self, prefix: P) -> Self
+    where
+        P: Into<String>,
+    {
+        self.read.push(prefix.into());
+        self
+    }
+
+    /// Lets the user write the keys that start with `prefix`
+    pub fn write<P>(mut self, prefix: P) -> Self
+    where
+        P: Into<String>,
+    {
+        self.write.push(prefix.into());
+        self
+    }
+
+    /// Lets the user run admin requests, on servers that accept them
+    pub fn admin(mut self) -> Self {
+        self.admin = true;
+        self
+    }
+
+    /// Returns the name of the user
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn can_read(&self, key: &str) -> bool {
+        self.read
+            .iter()
+            .any(|prefix| key.starts_with(prefix.as_str()))
+    }
+
+    fn can_write(&self, key: &str) -> bool {
+        self.write
+            .iter()
+            .any(|prefix| key.starts_with(prefix.as_str()))
+    }
+}
+
+// the token is left out so that it does not end up in logs
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("name", &self.name)
+            .field("read", &self.read)
+            .field("write", &self.write)
+            .field("admin", &self.admin)
+            .finish()
+    }
+}
+
+/// Users that can connect to a server and how they prove who they are. A server with an
+/// `AuthConfig` answers every request with an `Unauthenticated` error until the connection
+/// authenticated, and requests on keys that the user can not access with a `PermissionDenied`
+/// error. Only the JSON protocol supports authentication, the other protocols refuse their
+/// connections.
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{AuthConfig, AuthMethod, JsonKvsServer, ServerConfig, User};
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, KvsServer, Result};
+///
+/// fn main() -> Result<()> {
+///     let engine = KvStore::open(std::env::current_dir()?)?;
+///     let pool = SharedQueueThreadPool::new(4)?;
+///     let auth = AuthConfig::new(AuthMethod::Hmac)
+///         .user(User::new("admin", "admin-secret").read("").write(""))
+///         .user(User::new("app", "app-secret").read("app/").write("app/"));
+///     let config = ServerConfig::default().auth(auth);
+///     let server = JsonKvsServer::with_config(engine, pool, config, None);
+///     server.serve(([127, 0, 0, 1], 4000))?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default = "default_method")]
+    method: AuthMethod,
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+fn default_method() -> AuthMethod {
+    AuthMethod::Hmac
+}
+
+impl AuthConfig {
+    /// Creates a configuration without any user, no client can connect until one is added
+    pub fn new(method: AuthMethod) -> Self {
+        Self {
+            method,
+            users: Vec::new(),
+        }
+    }
+
+    /// Adds a user
+    pub fn user(mut self, user: User) -> Self {
+        self.users.push(user);
+        self
+    }
+
+    fn challenge(&self) -> Challenge {
+        match self.method {
+            AuthMethod::Token => Challenge::Token,
+            AuthMethod::Hmac => {
+                let mut nonce = [0u8; NONCE_SIZE];
+                rand::thread_rng().fill_bytes(&mut nonce);
+                Challenge::Hmac(hex(&nonce))
+            }
+        }
+    }
+
+    /// Returns the user whose token the proof was made with
+    fn find_user(&self, challenge: &Challenge, proof: &str) -> Option<&User> {
+        self.users.iter().find(|user| {
+            constant_time_eq(prove(challenge, &user.token).as_bytes(), proof.as_bytes())
+        })
+    }
+}
+
+/// Authentication state of one connection
+#[derive(Debug)]
+pub(crate) struct Session<'a> {
+    auth: Option<&'a AuthConfig>,
+    /// Challenge that the next proof answers, a challenge can only be answered once
+    challenge: Option<Challenge>,
+    user: Option<&'a User>,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) fn new(auth: Option<&'a AuthConfig>) -> Self {
+        Self {
+            auth,
+            challenge: None,
+            user: None,
+        }
+    }
+
+    /// Answers the requests that do not go to the engine as they are: the steps of the
+    /// handshake, requests that the user can not run, and scans of a user that can not read
+    /// every key. Returns `None` for the requests that are executed.
+    pub(crate) fn respond<E>(&mut self, engine: &E, request: &Request) -> Option<Response>
+    where
+        E: KvsEngine,
+    {
+        let auth = self.auth?;
+        match request {
+            Request::Challenge => {
+                let challenge = auth.challenge();
+                self.challenge = Some(challenge.clone());
+                Some(Response::Challenge(ChallengeResponse::Ok(challenge)))
+            }
+            Request::Authenticate { proof } => {
+                self.user = self
+                    .challenge
+                    .take()
+                    .and_then(|challenge| auth.find_user(&challenge, proof));
+                Some(Response::Authenticate(match self.user {
+                    Some(user) => AuthenticateResponse::Ok(user.name.clone()),
+                    None => AuthenticateResponse::Err(RemoteError::from(&Error::new(
+                        ErrorKind::Unauthenticated,
+                        "Proof does not match the token of any user",
+                    ))),
+                }))
+            }
+            request => {
+                let user = match self.user {
+                    Some(user) => user,
+                    None => {
+                        let err = Error::new(
+                            ErrorKind::Unauthenticated,
+                            "Server requires authentication before any request",
+                        );
+                        return Some(Response::err(request, &err));
+                    }
+                };
+                if let Err(err) = authorize(user, request) {
+                    return Some(Response::err(request, &err));
+                }
+                match request {
+                    Request::Scan { start, limit } if !user.can_read("") => Some(Response::Scan(
+                        match scan_readable(engine, user, start.clone(), *limit) {
+                            Ok(keys) => ScanResponse::Ok(keys),
+                            Err(err) => ScanResponse::Err(RemoteError::from(&err)),
+                        },
+                    )),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Checks that the user can access every key of the request, and that only admins run admin
+/// requests
+fn authorize(user: &User, request: &Request) -> Result<()> {
+    if let Request::Admin { .. } = request {
+        return match user.admin {
+            true => Ok(()),
+            false => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("User {} can not run admin requests", user.name),
+            )),
+        };
+    }
+    let allowed = match request {
+        Request::Get { key } => user.can_read(key),
+        Request::Set { key, .. } | Request::Remove { key } | Request::Append { key, .. } => {
+            user.can_write(key)
+        }
+        // the new value is returned
+        Request::Increment { key, .. } => user.can_read(key) && user.can_write(key),
+        Request::MultiGet { keys } => keys.iter().all(|key| user.can_read(key)),
+        Request::MultiSet { pairs } => pairs.iter().all(|(key, _)| user.can_write(key)),
+        Request::MultiRemove { keys } => keys.iter().all(|key| user.can_write(key)),
+        // keys that the user can not read are skipped
+        Request::Scan { .. } => true,
+        Request::Watch { key_or_prefix } => user.can_read(key_or_prefix),
+        // a replica copies every key, and a promoted replica accepts writes to every key
+        Request::Replicate { .. } => user.can_read(""),
+        Request::Promote => user.can_write(""),
+        Request::ReplicationStatus
+        | Request::Challenge
+        | Request::Authenticate { .. }
+        | Request::Admin { .. } => true,
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("User {} can not access the keys of the request", user.name),
+        ))
+    }
+}
+
+/// Lists keys like `KvsEngine::scan` but skips the keys that the user can not read, the scan goes
+/// on until `limit` keys are found or there are no more keys
+fn scan_readable<E>(engine: &E, user: &User, mut start: String, limit: usize) -> Result<Vec<String>>
+where
+    E: KvsEngine,
+{
+    let mut keys = Vec::new();
+    while keys.len() < limit {
+        let page = engine.scan(start, limit)?;
+        let last_page = page.len() < limit;
+        start = match page.last() {
+            Some(last) => scan_successor(last),
+            None => break,
+        };
+        keys.extend(page.into_iter().filter(|key| user.can_read(key)));
+        if last_page {
+            break;
+        }
+    }
+    keys.truncate(limit);
+    Ok(keys)
+}
+
+/// Authenticates the client side of a JSON connection that has no request in flight, returns the
+/// name of the user
+pub(crate) fn authenticate<R, W>(
+    rstream: &mut Deserializer<IoRead<R>>,
+    wstream: &mut W,
+    token: &str,
+) -> Result<String>
+where
+    R: Read,
+    W: Write,
+{
+    serde_json::to_writer(&mut *wstream, &Request::Challenge)?;
+    wstream.flush()?;
+    let challenge = match ChallengeResponse::deserialize(&mut *rstream)? {
+        ChallengeResponse::Ok(challenge) => challenge,
+        ChallengeResponse::Err(err) => return Err(Error::from(err)),
+    };
+
+    let proof = prove(&challenge, token);
+    serde_json::to_writer(&mut *wstream, &Request::Authenticate { proof })?;
+    wstream.flush()?;
+    match AuthenticateResponse::deserialize(&mut *rstream)? {
+        AuthenticateResponse::Ok(name) => Ok(name),
+        AuthenticateResponse::Err(err) => Err(Error::from(err)),
+    }
+}
+
+fn prove(challenge: &Challenge, token: &str) -> String {
+    match challenge {
+        Challenge::Token => token.to_owned(),
+        Challenge::Hmac(nonce) => {
+            // HMAC takes keys of any length
+            let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).unwrap();
+            mac.update(nonce.as_bytes());
+            hex(&mac.finalize().into_bytes())
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compares without returning early, so that the time it takes does not tell how much of a
+/// guessed token was right
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
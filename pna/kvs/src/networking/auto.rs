@@ -0,0 +1,115 @@
+use crate::networking::{
+    binary, json, Addr, Connection, KvsServer, ServerConfig, ServerHandle, Stream, REJECT_TIMEOUT,
+};
+use crate::thread_pool::ThreadPool;
+use crate::{KvsEngine, Result};
+use std::io;
+
+/// Network server that serves both JSON clients and binary clients on the same address. The
+/// protocol of a connection is detected from the first byte that the client sends.
+#[derive(Debug)]
+pub struct AutoKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    engine: E,
+    pool: P,
+    config: ServerConfig,
+    logger: slog::Logger,
+}
+
+impl<E, P> KvsServer for AutoKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + 'static,
+{
+    fn serve<A>(self, addr: A) -> Result<ServerHandle>
+    where
+        A: Into<Addr>,
+    {
+        let config = self.config.clone();
+        super::serve_with(
+            self.engine,
+            self.pool,
+            &self.config,
+            self.logger,
+            addr.into(),
+            move |engine, stream, connection| handle(engine, stream, &config, connection),
+            reply_busy,
+        )
+    }
+}
+
+impl<E, P> AutoKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    /// Create a new server that detects the protocol of each client
+    pub fn new(engine: E, pool: P, logger: Option<slog::Logger>) -> Self {
+        Self::with_config(engine, pool, ServerConfig::default(), logger)
+    }
+
+    /// Create a new server that detects the protocol of each client and limits its clients
+    /// according to the given configuration
+    pub fn with_config(
+        engine: E,
+        pool: P,
+        config: ServerConfig,
+        logger: Option<slog::Logger>,
+    ) -> Self {
+        let logger = logger.unwrap_or_else(super::default_logger);
+
+        Self {
+            engine,
+            pool,
+            config,
+            logger,
+        }
+    }
+}
+
+/// Tells a client that the server has too many connections, in the protocol that it starts
+/// with. Clients of both protocols send first, a client that sends nothing gets a JSON reply.
+fn reply_busy(stream: &mut Stream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REJECT_TIMEOUT))?;
+    let mut first = [0u8; 1];
+    match stream.peek(&mut first) {
+        Ok(1) if first[0] == binary::MAGIC[0] => binary::reply_busy(stream),
+        _ => json::reply_busy(stream),
+    }
+}
+
+fn handle<E>(
+    engine: E,
+    stream: Stream,
+    config: &ServerConfig,
+    connection: &Connection,
+) -> Result<()>
+where
+    E: KvsEngine,
+{
+    config.apply(&stream)?;
+    let mut first = [0u8; 1];
+    match stream.peek(&mut first) {
+        // closed without sending anything
+        Ok(0) => return Ok(()),
+        Ok(_) => {}
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            return Ok(())
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    if first[0] == binary::MAGIC[0] {
+        binary::handle(engine, stream, config)
+    } else {
+        json::handle(engine, stream, config, connection)
+    }
+}
@@ -0,0 +1,410 @@
+use crate::networking::protocol::{
+    AppendResponse, GetResponse, IncrementResponse, MultiGetResponse, MultiRemoveResponse,
+    MultiSetResponse, RemoteError, RemoveResponse, Request, ScanResponse, SetResponse,
+};
+use crate::networking::{Addr, KvsClient, KvsServer, ServerConfig, ServerHandle, Stream};
+use crate::thread_pool::ThreadPool;
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+/// First bytes that a client of the binary protocol sends, no JSON message starts with them
+pub(crate) const MAGIC: &[u8; 4] = b"KVSB";
+
+/// Latest version of the binary protocol
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Encoding ID of a handshake reply that tells the client that the server has too many
+/// connections, it is sent with protocol version 0 and is never the ID of an encoding
+const BUSY: u8 = 0xff;
+
+/// Frames larger than this are rejected instead of being buffered
+const MAX_FRAME_SIZE: u32 = 64 * 1024 * 1024;
+
+/// Encodings of the messages that are carried inside the frames of the binary protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Messages are encoded with `bincode`
+    Bincode,
+    /// Messages are encoded with `serde_json`
+    Json,
+}
+
+impl Encoding {
+    fn id(&self) -> u8 {
+        match *self {
+            Self::Bincode => 1,
+            Self::Json => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Bincode),
+            2 => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    fn encode<T>(&self, value: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        match *self {
+            Self::Bincode => Ok(bincode::serialize(value)?),
+            Self::Json => Ok(serde_json::to_vec(value)?),
+        }
+    }
+
+    fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        match *self {
+            Self::Bincode => Ok(bincode::deserialize(bytes)?),
+            Self::Json => Ok(serde_json::from_slice(bytes)?),
+        }
+    }
+}
+
+/// Network client for length-prefixed binary frames. The connection starts with a handshake
+/// where the client proposes its protocol version and encodings, and the server picks the ones
+/// that are used for the rest of the connection.
+#[allow(missing_debug_implementations)]
+pub struct BinaryKvsClient {
+    rstream: BufReader<Stream>,
+    wstream: BufWriter<Stream>,
+    encoding: Encoding,
+}
+
+impl BinaryKvsClient {
+    /// Connect to the remote server at `addr` and return the client to it, the encoding is
+    /// negotiated from `encodings` which are ordered by preference
+    pub fn connect_with<A>(addr: A, encodings: &[Encoding]) -> Result<Self>
+    where
+        A: Into<Addr>,
+    {
+        // the handshake carries the number of encodings in one byte
+        let count = u8::try_from(encodings.len()).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidValue,
+                format!("Can not propose more than {} encodings", u8::MAX),
+            )
+        })?;
+        let wstream = Stream::connect(&addr.into())?;
+        let rstream = wstream.try_clone()?;
+        let mut rstream = BufReader::new(rstream);
+        let mut wstream = BufWriter::new(wstream);
+
+        wstream.write_all(MAGIC)?;
+        wstream.write_all(&[PROTOCOL_VERSION, count])?;
+        for encoding in encodings {
+            wstream.write_all(&[encoding.id()])?;
+        }
+        wstream.flush()?;
+
+        let mut reply = [0u8; 6];
+        rstream.read_exact(&mut reply)?;
+        if &reply[..4] != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                "Server replied with an invalid handshake",
+            ));
+        }
+        let encoding = match (reply[4], Encoding::from_id(reply[5])) {
+            (1..=PROTOCOL_VERSION, Some(encoding)) => encoding,
+            (0, None) if reply[5] == BUSY => {
+                return Err(Error::new(
+                    ErrorKind::ServerBusy,
+                    "Server reached its maximum number of connections",
+                ))
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Server does not support the protocol version or any of the encodings",
+                ))
+            }
+        };
+
+        Ok(Self {
+            rstream,
+            wstream,
+            encoding,
+        })
+    }
+
+    /// Returns the encoding that was negotiated with the server
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    fn call<T>(&mut self, request: &Request) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        write_frame(&mut self.wstream, &self.encoding.encode(request)?)?;
+        let frame = read_frame(&mut self.rstream, MAX_FRAME_SIZE)?.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                "Connection was closed before a response was received",
+            )
+        })?;
+        self.encoding.decode(&frame)
+    }
+}
+
+impl KvsClient for BinaryKvsClient {
+    /// Connect to the remote server at `addr` and return the client to it, preferring `bincode`
+    /// over JSON
+    fn connect<A>(addr: A) -> Result<Self>
+    where
+        A: Into<Addr>,
+    {
+        Self::connect_with(addr, &[Encoding::Bincode, Encoding::Json])
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        match self.call(&Request::Set { key, value })? {
+            SetResponse::Ok => Ok(()),
+            SetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        match self.call(&Request::Get { key })? {
+            GetResponse::Ok(val) => Ok(val),
+            GetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        match self.call(&Request::Remove { key })? {
+            RemoveResponse::Ok => Ok(()),
+            RemoveResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        match self.call(&Request::Increment { key, delta })? {
+            IncrementResponse::Ok(val) => Ok(val),
+            IncrementResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn append(&mut self, key: String, suffix: String) -> Result<()> {
+        match self.call(&Request::Append { key, suffix })? {
+            AppendResponse::Ok => Ok(()),
+            AppendResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn multi_get(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        match self.call(&Request::MultiGet { keys })? {
+            MultiGetResponse::Ok(vals) => Ok(vals),
+            MultiGetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn multi_set(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        match self.call(&Request::MultiSet { pairs })? {
+            MultiSetResponse::Ok => Ok(()),
+            MultiSetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn multi_remove(&mut self, keys: Vec<String>) -> Result<u64> {
+        match self.call(&Request::MultiRemove { keys })? {
+            MultiRemoveResponse::Ok(removed) => Ok(removed),
+            MultiRemoveResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn scan(&mut self, start: String, limit: usize) -> Result<Vec<String>> {
+        match self.call(&Request::Scan { start, limit })? {
+            ScanResponse::Ok(keys) => Ok(keys),
+            ScanResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+}
+
+/// Network server for length-prefixed binary frames
+#[derive(Debug)]
+pub struct BinaryKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    engine: E,
+    pool: P,
+    config: ServerConfig,
+    logger: slog::Logger,
+}
+
+impl<E, P> KvsServer for BinaryKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + 'static,
+{
+    fn serve<A>(self, addr: A) -> Result<ServerHandle>
+    where
+        A: Into<Addr>,
+    {
+        let config = self.config.clone();
+        super::serve_with(
+            self.engine,
+            self.pool,
+            &self.config,
+            self.logger,
+            addr.into(),
+            move |engine, stream, _| handle(engine, stream, &config),
+            reply_busy,
+        )
+    }
+}
+
+impl<E, P> BinaryKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    /// Create a new binary server
+    pub fn new(engine: E, pool: P, logger: Option<slog::Logger>) -> Self {
+        Self::with_config(engine, pool, ServerConfig::default(), logger)
+    }
+
+    /// Create a new binary server that limits its clients according to the given configuration
+    pub fn with_config(
+        engine: E,
+        pool: P,
+        config: ServerConfig,
+        logger: Option<slog::Logger>,
+    ) -> Self {
+        let logger = logger.unwrap_or_else(super::default_logger);
+
+        Self {
+            engine,
+            pool,
+            config,
+            logger,
+        }
+    }
+}
+
+/// Serves requests from a client that speaks the binary protocol until the connection is closed
+/// or stays idle for too long
+pub(crate) fn handle<E>(engine: E, stream: Stream, config: &ServerConfig) -> Result<()>
+where
+    E: KvsEngine,
+{
+    config.require_no_auth("binary")?;
+    config.apply(&stream)?;
+    let mut wstream = BufWriter::new(stream.try_clone()?);
+    let mut rstream = BufReader::new(stream);
+    let encoding = accept_handshake(&mut rstream, &mut wstream)?;
+    let max_request_size = config.get_max_request_size().min(MAX_FRAME_SIZE as usize) as u32;
+
+    while config.wait_for_request(&mut rstream)? {
+        let frame = match read_frame(&mut rstream, max_request_size) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(err) if err.kind() == ErrorKind::InvalidNetworkMessage => {
+                // every response has its error as the second variant, so the client decodes this
+                // one whatever it asked for. The rest of the frame is not read, so the connection
+                // is closed.
+                let response = SetResponse::Err(RemoteError::from(&err));
+                write_frame(&mut wstream, &encoding.encode(&response)?)?;
+                let _ = super::linger(rstream.get_mut());
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
+        let request = encoding.decode(&frame)?;
+        let response = config.execute(&engine, request);
+        write_frame(&mut wstream, &encoding.encode(&response)?)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the client's handshake and replies with the protocol version and the encoding that will
+/// be used, or with a rejection if there is none in common
+fn accept_handshake<R, W>(rstream: &mut R, wstream: &mut W) -> Result<Encoding>
+where
+    R: Read,
+    W: Write,
+{
+    let mut handshake = [0u8; 6];
+    rstream.read_exact(&mut handshake)?;
+    if &handshake[..4] != MAGIC {
+        return Err(Error::new(
+            ErrorKind::InvalidNetworkMessage,
+            "Client sent an invalid handshake",
+        ));
+    }
+    let version = handshake[4].min(PROTOCOL_VERSION);
+    let mut encoding_ids = vec![0u8; handshake[5] as usize];
+    rstream.read_exact(&mut encoding_ids)?;
+    // the client lists its encodings by preference
+    let encoding = encoding_ids.into_iter().find_map(Encoding::from_id);
+
+    wstream.write_all(MAGIC)?;
+    match encoding {
+        Some(encoding) if version > 0 => {
+            wstream.write_all(&[version, encoding.id()])?;
+            wstream.flush()?;
+            Ok(encoding)
+        }
+        _ => {
+            wstream.write_all(&[0, 0])?;
+            wstream.flush()?;
+            Err(Error::new(
+                ErrorKind::Unsupported,
+                "Client does not support the protocol version or any of the encodings",
+            ))
+        }
+    }
+}
+
+/// Tells a client that the server has too many connections, in place of the reply to its
+/// handshake
+pub(crate) fn reply_busy(stream: &mut Stream) -> io::Result<()> {
+    stream.write_all(MAGIC)?;
+    stream.write_all(&[0, BUSY])
+}
+
+/// Reads a frame that is prefixed with its length, returns `None` if the stream was closed in
+/// between frames
+fn read_frame<R>(rstream: &mut R, max_size: u32) -> Result<Option<Vec<u8>>>
+where
+    R: BufRead,
+{
+    if rstream.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut len = [0u8; 4];
+    rstream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > max_size {
+        return Err(Error::new(
+            ErrorKind::InvalidNetworkMessage,
+            format!("Frame of {} bytes exceeds the maximum frame size", len),
+        ));
+    }
+
+    let mut frame = vec![0u8; len as usize];
+    rstream.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+fn write_frame<W>(wstream: &mut W, frame: &[u8]) -> Result<()>
+where
+    W: Write,
+{
+    wstream.write_all(&(frame.len() as u32).to_be_bytes())?;
+    wstream.write_all(frame)?;
+    wstream.flush()?;
+    Ok(())
+}
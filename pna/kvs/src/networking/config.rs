@@ -0,0 +1,206 @@
+use crate::networking::metrics::{Metrics, Operation};
+use crate::networking::{protocol, Request, Response, Stream};
+use crate::networking::{AdminConfig, AuthConfig, RequestLog};
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use std::io::{self, BufRead, BufReader};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Maximum size in bytes of a request unless a server is configured otherwise
+pub(crate) const DEFAULT_MAX_REQUEST_SIZE: usize = 64 * 1024 * 1024;
+
+/// Limits that a server puts on its clients, so that slow, abandoned or misbehaving clients can
+/// not hold on to the server's resources forever
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{JsonKvsServer, ServerConfig};
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, KvsServer, Result};
+/// use std::time::Duration;
+///
+/// fn main() -> Result<()> {
+///     let engine = KvStore::open(std::env::current_dir()?)?;
+///     let pool = SharedQueueThreadPool::new(4)?;
+///     let config = ServerConfig::default()
+///         .idle_timeout(Some(Duration::from_secs(60)))
+///         .max_connections(Some(256));
+///     let server = JsonKvsServer::with_config(engine, pool, config, None);
+///     server.serve(([127, 0, 0, 1], 4000))?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    idle_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    max_connections: Option<usize>,
+    max_request_size: usize,
+    metrics: Option<Arc<Metrics>>,
+    auth: Option<Arc<AuthConfig>>,
+    request_log: Option<RequestLog>,
+    admin: Option<AdminConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Some(Duration::from_secs(300)),
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            max_connections: Some(1024),
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            metrics: None,
+            auth: None,
+            request_log: None,
+            admin: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Time that a connection can wait between requests before it is closed, `None` lets
+    /// connections stay open forever
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout.filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Time that the server waits for the rest of a request once the client started sending
+    /// it, `None` disables the timeout
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout.filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Time that the server waits for the client to accept a response, `None` disables the
+    /// timeout
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.write_timeout = timeout.filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Maximum number of connections that are served at the same time, clients that connect
+    /// when the limit is reached get a "server busy" error. `None` removes the limit.
+    pub fn max_connections(mut self, max: Option<usize>) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Maximum size in bytes of a request, the connection is closed after a larger request
+    pub fn max_request_size(mut self, bytes: usize) -> Self {
+        self.max_request_size = bytes;
+        self
+    }
+
+    /// Metrics that the server records its requests and connections in
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Users that clients must authenticate as, only the JSON protocol supports it
+    pub fn auth(mut self, auth: AuthConfig) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// Access log and slow log of the requests, only JSON connections are logged
+    pub fn request_log(mut self, log: RequestLog) -> Self {
+        self.request_log = Some(log);
+        self
+    }
+
+    /// Accepts admin requests, only on JSON connections
+    pub fn admin(mut self, admin: AdminConfig) -> Self {
+        self.admin = Some(admin);
+        self
+    }
+
+    pub(crate) fn get_max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    pub(crate) fn get_max_request_size(&self) -> usize {
+        self.max_request_size
+    }
+
+    pub(crate) fn get_metrics(&self) -> Option<&Arc<Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    pub(crate) fn get_auth(&self) -> Option<&AuthConfig> {
+        self.auth.as_deref()
+    }
+
+    pub(crate) fn get_request_log(&self) -> Option<&RequestLog> {
+        self.request_log.as_ref()
+    }
+
+    pub(crate) fn get_admin(&self) -> Option<&AdminConfig> {
+        self.admin.as_ref()
+    }
+
+    /// Fails if clients must authenticate, for the protocols that have no way to do so
+    pub(crate) fn require_no_auth(&self, protocol: &str) -> Result<()> {
+        match self.auth {
+            Some(_) => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Server requires authentication, which the {} protocol does not support",
+                    protocol
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a request that started at `started` in the metrics, if the server has any
+    pub(crate) fn record(&self, operation: Option<Operation>, failed: bool, started: Instant) {
+        if let (Some(metrics), Some(operation)) = (&self.metrics, operation) {
+            metrics.record(operation, failed, started);
+        }
+    }
+
+    /// Runs the request on the engine like `protocol::execute` and records it in the metrics
+    pub(crate) fn execute<E>(&self, engine: &E, request: Request) -> Response
+    where
+        E: KvsEngine,
+    {
+        let operation = Operation::of(&request);
+        let started = Instant::now();
+        let response = protocol::execute(engine, request);
+        self.record(operation, response.is_err(), started);
+        response
+    }
+
+    /// Applies the timeouts that do not change during the lifetime of the connection
+    pub(crate) fn apply(&self, stream: &Stream) -> Result<()> {
+        stream.set_write_timeout(self.write_timeout)?;
+        stream.set_read_timeout(self.idle_timeout)?;
+        Ok(())
+    }
+
+    /// Waits up to the idle timeout for the next request, then switches to the read timeout for
+    /// reading the request. Returns `false` if the client closed the connection or stayed idle
+    /// for too long.
+    pub(crate) fn wait_for_request(&self, reader: &mut BufReader<Stream>) -> Result<bool> {
+        reader.get_ref().set_read_timeout(self.idle_timeout)?;
+        let available = match reader.fill_buf() {
+            Ok(buf) => !buf.is_empty(),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                false
+            }
+            Err(err) => return Err(err.into()),
+        };
+        reader.get_ref().set_read_timeout(self.read_timeout)?;
+        Ok(available)
+    }
+}
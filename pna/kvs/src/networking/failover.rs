@@ -0,0 +1,368 @@
+use crate::networking::{Addr, AdminCommand, AdminReply, JsonKvsClient, KvsClient};
+use crate::replication::ReplicationStatus;
+use crate::{Error, ErrorKind, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Options of a `FailoverKvsClient`
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{Addr, FailoverConfig, FailoverKvsClient};
+/// use kvs::{KvsClient, Result};
+/// use std::time::Duration;
+///
+/// fn main() -> Result<()> {
+///     let addrs: Vec<Addr> = vec![([127, 0, 0, 1], 4000).into(), ([127, 0, 0, 1], 4001).into()];
+///     let config = FailoverConfig::default()
+///         .deadline(Duration::from_secs(30))
+///         .retry_writes(true);
+///     let mut client = FailoverKvsClient::connect_with_config(addrs, config)?;
+///     client.set("key".to_owned(), "value".to_owned())?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FailoverConfig {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    deadline: Duration,
+    retry_writes: bool,
+    max_idle_connections: usize,
+    token: Option<String>,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            deadline: Duration::from_secs(10),
+            retry_writes: false,
+            max_idle_connections: 8,
+            token: None,
+        }
+    }
+}
+
+// the token is left out so that it does not end up in logs
+impl fmt::Debug for FailoverConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FailoverConfig")
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("deadline", &self.deadline)
+            .field("retry_writes", &self.retry_writes)
+            .field("max_idle_connections", &self.max_idle_connections)
+            .finish()
+    }
+}
+
+impl FailoverConfig {
+    /// Time that is waited before the first retry, the wait doubles after every failed attempt
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Longest time that is waited in between two attempts
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Time after which an operation is given up, counted from its first attempt
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Retry `set` and `multi_set` even when the request might have reached the server. Setting
+    /// a key twice has the same effect as setting it once, unless another client wrote the key
+    /// in between.
+    pub fn retry_writes(mut self, retry: bool) -> Self {
+        self.retry_writes = retry;
+        self
+    }
+
+    /// Maximum number of open connections that are kept for later operations
+    pub fn max_idle_connections(mut self, max: usize) -> Self {
+        self.max_idle_connections = max;
+        self
+    }
+
+    /// Token that every connection authenticates with, for servers that require authentication
+    pub fn token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+}
+
+/// Network client that knows several servers and survives their restarts. A failed connection
+/// is replaced by a connection to the same server or to the next one, and operations are retried
+/// with an exponential backoff until the deadline of the configuration.
+///
+/// Reads are always retried. Other operations are only retried when the request can not have
+/// reached a server: the connection could not be opened, was found closed before sending, or the
+/// server answered that it is busy. `set` and `multi_set` can be retried in more cases with
+/// `FailoverConfig::retry_writes`.
+///
+/// Clones share their connections, so a clone can be given to each thread.
+#[derive(Clone)]
+pub struct FailoverKvsClient {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    addrs: Vec<Addr>,
+    config: FailoverConfig,
+    /// Index of the address that the next connection is opened to
+    preferred: AtomicUsize,
+    /// Connections that are not used by any operation, with the index of their address
+    idle: Mutex<Vec<(usize, JsonKvsClient)>>,
+}
+
+impl fmt::Debug for FailoverKvsClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FailoverKvsClient")
+            .field("addrs", &self.inner.addrs)
+            .field("config", &self.inner.config)
+            .finish()
+    }
+}
+
+/// Which failures an operation can be retried after
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Retry {
+    /// The operation has the same effect no matter how often it is done
+    Always,
+    /// The operation must not be done twice
+    Unsent,
+}
+
+impl FailoverKvsClient {
+    /// Connects to the first of the given servers that can be reached, trying each of them once
+    pub fn connect_with_config<I>(addrs: I, config: FailoverConfig) -> Result<Self>
+    where
+        I: IntoIterator,
+        I::Item: Into<Addr>,
+    {
+        let addrs: Vec<_> = addrs.into_iter().map(Into::into).collect();
+        if addrs.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidValue,
+                "No address of a server is given",
+            ));
+        }
+
+        let inner = Inner {
+            addrs,
+            config,
+            preferred: AtomicUsize::new(0),
+            idle: Mutex::new(Vec::new()),
+        };
+        let connection = inner.open()?;
+        inner.idle.lock().unwrap().push(connection);
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Returns the addresses of the servers that the client connects to
+    pub fn addrs(&self) -> &[Addr] {
+        &self.inner.addrs
+    }
+
+    /// Makes the replica that the client is connected to stop following its primary and accept
+    /// writes
+    pub fn promote(&mut self) -> Result<()> {
+        self.inner.call(Retry::Always, |client| client.promote())
+    }
+
+    /// Returns the role of the server that the client is connected to and how far a replica is
+    /// behind its primary
+    pub fn replication_status(&mut self) -> Result<ReplicationStatus> {
+        self.inner
+            .call(Retry::Always, |client| client.replication_status())
+    }
+
+    /// Runs a maintenance command on the server that the client is connected to, `token` is the
+    /// admin token of the server if it has one
+    pub fn admin(&mut self, command: AdminCommand, token: Option<&str>) -> Result<AdminReply> {
+        self.inner
+            .call(Retry::Unsent, |client| client.admin(command.clone(), token))
+    }
+
+    fn write_retry(&self) -> Retry {
+        if self.inner.config.retry_writes {
+            Retry::Always
+        } else {
+            Retry::Unsent
+        }
+    }
+}
+
+impl Inner {
+    /// Opens a connection to the preferred server, or to the ones after it if it can not be
+    /// reached
+    fn open(&self) -> Result<(usize, JsonKvsClient)> {
+        let start = self.preferred.load(Ordering::SeqCst);
+        let mut last_err = None;
+        for offset in 0..self.addrs.len() {
+            let index = (start + offset) % self.addrs.len();
+            let connected = match &self.config.token {
+                Some(token) => JsonKvsClient::connect_with_token(self.addrs[index].clone(), token),
+                None => JsonKvsClient::connect(self.addrs[index].clone()),
+            };
+            match connected {
+                Ok(client) => {
+                    self.preferred.store(index, Ordering::SeqCst);
+                    return Ok((index, client));
+                }
+                // the other servers are expected to have the same users
+                Err(err) if err.kind() == ErrorKind::Unauthenticated => return Err(err),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    /// Takes an idle connection that is still open, or opens a new one
+    fn checkout(&self) -> Result<(usize, JsonKvsClient)> {
+        loop {
+            let connection = self.idle.lock().unwrap().pop();
+            match connection {
+                Some((_, client)) if client.is_closed() => continue,
+                Some(connection) => return Ok(connection),
+                None => return self.open(),
+            }
+        }
+    }
+
+    fn checkin(&self, connection: (usize, JsonKvsClient)) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.config.max_idle_connections {
+            idle.push(connection);
+        }
+    }
+
+    /// Drops the connections to a server that failed and moves on to the next server
+    fn fail(&self, index: usize) {
+        self.idle.lock().unwrap().retain(|(i, _)| *i != index);
+        let next = (index + 1) % self.addrs.len();
+        let _ = self
+            .preferred
+            .compare_exchange(index, next, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    fn call<T, F>(&self, retry: Retry, mut op: F) -> Result<T>
+    where
+        F: FnMut(&mut JsonKvsClient) -> Result<T>,
+    {
+        let started = Instant::now();
+        let mut backoff = self.config.initial_backoff;
+        loop {
+            let err = match self.checkout() {
+                Ok((index, mut client)) => match op(&mut client) {
+                    Ok(value) => {
+                        self.checkin((index, client));
+                        return Ok(value);
+                    }
+                    Err(err) => match err.kind() {
+                        // the request was refused before it was looked at
+                        ErrorKind::ServerBusy => {
+                            self.fail(index);
+                            err
+                        }
+                        ErrorKind::Io => {
+                            self.fail(index);
+                            if retry == Retry::Unsent {
+                                return Err(err);
+                            }
+                            err
+                        }
+                        // the connection can not be trusted after a malformed response
+                        ErrorKind::InvalidNetworkMessage | ErrorKind::Serialization => {
+                            return Err(err)
+                        }
+                        // the server answered, the connection can be used again
+                        _ => {
+                            self.checkin((index, client));
+                            return Err(err);
+                        }
+                    },
+                },
+                // a wrong token stays wrong
+                Err(err) if err.kind() == ErrorKind::Unauthenticated => return Err(err),
+                Err(err) => err,
+            };
+
+            if started.elapsed() + backoff > self.config.deadline {
+                return Err(err);
+            }
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(self.config.max_backoff);
+        }
+    }
+}
+
+impl KvsClient for FailoverKvsClient {
+    /// Connect to the server at `addr` with the default configuration
+    fn connect<A>(addr: A) -> Result<Self>
+    where
+        A: Into<Addr>,
+    {
+        Self::connect_with_config(vec![addr.into()], FailoverConfig::default())
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.inner.call(self.write_retry(), |client| {
+            client.set(key.clone(), value.clone())
+        })
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.inner
+            .call(Retry::Always, |client| client.get(key.clone()))
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.inner
+            .call(Retry::Unsent, |client| client.remove(key.clone()))
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        self.inner
+            .call(Retry::Unsent, |client| client.increment(key.clone(), delta))
+    }
+
+    fn append(&mut self, key: String, suffix: String) -> Result<()> {
+        self.inner.call(Retry::Unsent, |client| {
+            client.append(key.clone(), suffix.clone())
+        })
+    }
+
+    fn multi_get(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.inner
+            .call(Retry::Always, |client| client.multi_get(keys.clone()))
+    }
+
+    fn multi_set(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.inner
+            .call(self.write_retry(), |client| client.multi_set(pairs.clone()))
+    }
+
+    fn multi_remove(&mut self, keys: Vec<String>) -> Result<u64> {
+        self.inner
+            .call(Retry::Unsent, |client| client.multi_remove(keys.clone()))
+    }
+
+    fn scan(&mut self, start: String, limit: usize) -> Result<Vec<String>> {
+        self.inner
+            .call(Retry::Always, |client| client.scan(start.clone(), limit))
+    }
+}
@@ -0,0 +1,734 @@
+use crate::engines::scan_successor;
+use crate::networking::metrics::Operation;
+use crate::networking::{Addr, KvsServer, RemoteError, ServerConfig, ServerHandle, Stream};
+use crate::thread_pool::ThreadPool;
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use serde_json::json;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Maximum size in bytes of the request line and the headers of a request
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+
+/// Number of keys that are listed when a listing does not give a limit
+const DEFAULT_LIST_LIMIT: usize = 100;
+
+/// Maximum number of keys that are listed at once
+const MAX_LIST_LIMIT: usize = 10_000;
+
+/// Network server that speaks HTTP/1.1, so that any `KvsEngine` can be used from a browser or with
+/// `curl`. Values are sent as plain text, everything else as JSON.
+///
+/// | Request | Response |
+/// |---|---|
+/// | `GET /keys/{key}` | the value, or 404 if the key does not exist |
+/// | `PUT /keys/{key}` | sets the value to the body |
+/// | `DELETE /keys/{key}` | removes the key, or 404 if it does not exist |
+/// | `GET /keys?prefix=&start=&end=&limit=` | `{"keys": [...], "next": ...}`, keys in order |
+/// | `GET /health` | `{"status": "ok"}` |
+/// | `GET /stats` | counters of the server |
+///
+/// A listing returns keys that are not less than `start`, less than `end` and that begin with
+/// `prefix`. When more keys may follow, `next` is the `start` of the next page.
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{HttpKvsServer, JsonKvsServer};
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, KvsServer, Result};
+/// use std::sync::Arc;
+///
+/// fn main() -> Result<()> {
+///     let engine = KvStore::open(std::env::current_dir()?)?;
+///     let pool = Arc::new(SharedQueueThreadPool::new(4)?);
+///     let tcp = JsonKvsServer::new(engine.clone(), Arc::clone(&pool), None);
+///     let http = HttpKvsServer::new(engine, pool, None);
+///     tcp.serve(([127, 0, 0, 1], 4000))?;
+///     http.serve(([127, 0, 0, 1], 8080))?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct HttpKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    engine: E,
+    pool: P,
+    config: ServerConfig,
+    logger: slog::Logger,
+}
+
+impl<E, P> KvsServer for HttpKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + 'static,
+{
+    fn serve<A>(self, addr: A) -> Result<ServerHandle>
+    where
+        A: Into<Addr>,
+    {
+        let config = self.config.clone();
+        let stats = Arc::new(HttpStats::new());
+        super::serve_with(
+            self.engine,
+            self.pool,
+            &self.config,
+            self.logger,
+            addr.into(),
+            move |engine, stream, _| handle(engine, stream, &config, &stats),
+            reply_busy,
+        )
+    }
+}
+
+impl<E, P> HttpKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    /// Create a new HTTP server
+    pub fn new(engine: E, pool: P, logger: Option<slog::Logger>) -> Self {
+        Self::with_config(engine, pool, ServerConfig::default(), logger)
+    }
+
+    /// Create a new HTTP server that limits its clients according to the given configuration
+    pub fn with_config(
+        engine: E,
+        pool: P,
+        config: ServerConfig,
+        logger: Option<slog::Logger>,
+    ) -> Self {
+        let logger = logger.unwrap_or_else(super::default_logger);
+
+        Self {
+            engine,
+            pool,
+            config,
+            logger,
+        }
+    }
+}
+
+/// Counters that are reported by `GET /stats`
+#[derive(Debug)]
+struct HttpStats {
+    started: Instant,
+    open_connections: AtomicU64,
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+impl HttpStats {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            open_connections: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            client_errors: AtomicU64::new(0),
+            server_errors: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, status: u16) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match status {
+            400..=499 => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            500..=599 => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+}
+
+/// Counts a connection as open until it is dropped
+struct OpenConnection<'a>(&'a HttpStats);
+
+impl<'a> OpenConnection<'a> {
+    fn new(stats: &'a HttpStats) -> Self {
+        stats.open_connections.fetch_add(1, Ordering::Relaxed);
+        Self(stats)
+    }
+}
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.0.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A request whose head and body have been read
+#[derive(Debug)]
+pub(super) struct HttpRequest {
+    pub(super) method: String,
+    pub(super) path: String,
+    query: Option<String>,
+    body: Vec<u8>,
+    /// The client asked for the connection to be closed after the response
+    close: bool,
+}
+
+#[derive(Debug)]
+pub(super) struct HttpResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+    /// Methods that the resource supports, sent with 405 responses
+    allow: Option<&'static str>,
+}
+
+impl HttpResponse {
+    pub(super) fn text(status: u16, body: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into_bytes(),
+            allow: None,
+        }
+    }
+
+    fn json(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.to_string().into_bytes(),
+            allow: None,
+        }
+    }
+
+    fn empty(status: u16) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: Vec::new(),
+            allow: None,
+        }
+    }
+
+    /// The body carries the error as a `RemoteError`, so clients can use the same codes as on the
+    /// other protocols
+    fn error(status: u16, err: &Error) -> Self {
+        let body = serde_json::to_value(RemoteError::from(err)).unwrap_or_default();
+        Self::json(status, body)
+    }
+
+    pub(super) fn method_not_allowed(allow: &'static str) -> Self {
+        let err = Error::new(ErrorKind::Unsupported, "Method is not allowed");
+        Self {
+            allow: Some(allow),
+            ..Self::error(405, &err)
+        }
+    }
+
+    /// Replaces the content type, for bodies in another text format
+    pub(super) fn content_type(mut self, content_type: &'static str) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    pub(super) fn not_found(path: &str) -> Self {
+        let err = Error::new(ErrorKind::Unsupported, format!("No resource at {}", path));
+        Self::error(404, &err)
+    }
+
+    fn write_to<W>(&self, w: &mut W, close: bool) -> io::Result<()>
+    where
+        W: Write,
+    {
+        write!(w, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        // a response without content must not describe its content
+        if self.status != 204 {
+            write!(w, "Content-Type: {}\r\n", self.content_type)?;
+            write!(w, "Content-Length: {}\r\n", self.body.len())?;
+        }
+        if let Some(allow) = self.allow {
+            write!(w, "Allow: {}\r\n", allow)?;
+        }
+        if close {
+            w.write_all(b"Connection: close\r\n")?;
+        }
+        w.write_all(b"\r\n")?;
+        w.write_all(&self.body)
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+/// Returns the status that an error of the engine is answered with
+fn error_status(err: &Error) -> u16 {
+    match err.kind() {
+        ErrorKind::KeyNotFound => 404,
+        ErrorKind::InvalidValue | ErrorKind::InvalidNetworkMessage => 400,
+        ErrorKind::ReadOnly => 403,
+        ErrorKind::ServerBusy => 503,
+        ErrorKind::Unsupported => 501,
+        _ => 500,
+    }
+}
+
+/// Serves requests from an HTTP client until the connection is closed, stays idle for too long
+/// or the client asks for it to be closed
+fn handle<E>(engine: E, stream: Stream, config: &ServerConfig, stats: &HttpStats) -> Result<()>
+where
+    E: KvsEngine,
+{
+    config.require_no_auth("HTTP")?;
+    let _open = OpenConnection::new(stats);
+    serve_requests(
+        stream,
+        config,
+        |status| stats.record(status),
+        |request| route(&engine, config, stats, request),
+    )
+}
+
+/// Reads requests from the connection and answers each of them with `route`, `record` is told the
+/// status of every response. Returns when the connection is closed, stays idle for too long or
+/// the client asks for it to be closed.
+pub(super) fn serve_requests<S, R>(
+    stream: Stream,
+    config: &ServerConfig,
+    record: S,
+    mut route: R,
+) -> Result<()>
+where
+    S: Fn(u16),
+    R: FnMut(&HttpRequest) -> HttpResponse,
+{
+    config.apply(&stream)?;
+    let mut wstream = BufWriter::new(stream.try_clone()?);
+    let mut rstream = BufReader::new(stream);
+    let max_body_size = config.get_max_request_size() as u64;
+
+    while config.wait_for_request(&mut rstream)? {
+        let request = match read_request(&mut rstream, &mut wstream, max_body_size) {
+            Ok(Ok(Some(request))) => request,
+            Ok(Ok(None)) => break,
+            Err(err) if err.kind() == ErrorKind::InvalidNetworkMessage => {
+                HttpResponse::error(400, &err).write_to(&mut wstream, true)?;
+                wstream.flush()?;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+            Ok(Err(response)) => {
+                // the rest of the request can not be skipped reliably, so the connection is closed
+                record(response.status);
+                response.write_to(&mut wstream, true)?;
+                wstream.flush()?;
+                let _ = super::linger(rstream.get_mut());
+                return Ok(());
+            }
+        };
+
+        let response = route(&request);
+        record(response.status);
+        response.write_to(&mut wstream, request.close)?;
+        wstream.flush()?;
+        if request.close {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the next request. Returns `Ok(None)` if the connection was closed before a request
+/// started, or the response that a malformed request is answered with.
+fn read_request<W>(
+    rstream: &mut BufReader<Stream>,
+    wstream: &mut W,
+    max_body_size: u64,
+) -> Result<std::result::Result<Option<HttpRequest>, HttpResponse>>
+where
+    W: Write,
+{
+    let bad_request = |message: &str| {
+        let err = Error::new(ErrorKind::InvalidNetworkMessage, message);
+        Err(HttpResponse::error(400, &err))
+    };
+
+    let mut head = (&mut *rstream).take(MAX_HEAD_SIZE);
+    let mut line = String::new();
+    // empty lines in front of a request are ignored
+    while line.trim_end().is_empty() {
+        line.clear();
+        if read_line(&mut head, &mut line)? == 0 {
+            return Ok(Ok(None));
+        }
+    }
+
+    let mut parts = line.split_whitespace();
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Ok(bad_request("Malformed request line")),
+    };
+    let mut close = match version {
+        "HTTP/1.1" => false,
+        "HTTP/1.0" => true,
+        _ => {
+            let err = Error::new(
+                ErrorKind::Unsupported,
+                "Only HTTP/1.0 and HTTP/1.1 are supported",
+            );
+            return Ok(Err(HttpResponse::error(505, &err)));
+        }
+    };
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_owned(), Some(query.to_owned())),
+        None => (target.to_owned(), None),
+    };
+    let method = method.to_owned();
+
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut expect_continue = false;
+    loop {
+        line.clear();
+        if read_line(&mut head, &mut line)? == 0 {
+            return Ok(bad_request("Request head is incomplete or too large"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = match header.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => return Ok(bad_request("Malformed header")),
+        };
+        if name.eq_ignore_ascii_case("Content-Length") {
+            match value.parse::<u64>() {
+                // repeating the same length is harmless, different lengths leave the end of the
+                // body to interpretation
+                Ok(length) if content_length.is_some_and(|prev| prev != length) => {
+                    return Ok(bad_request("Conflicting Content-Length headers"))
+                }
+                Ok(length) => content_length = Some(length),
+                Err(_) => return Ok(bad_request("Malformed Content-Length")),
+            }
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            if !value.eq_ignore_ascii_case("chunked") {
+                let err = Error::new(
+                    ErrorKind::Unsupported,
+                    "Only chunked transfer encoding is supported",
+                );
+                return Ok(Err(HttpResponse::error(501, &err)));
+            }
+            chunked = true;
+        } else if name.eq_ignore_ascii_case("Connection") {
+            if value.eq_ignore_ascii_case("close") {
+                close = true;
+            } else if value.eq_ignore_ascii_case("keep-alive") {
+                close = false;
+            }
+        } else if name.eq_ignore_ascii_case("Expect") {
+            expect_continue = value.eq_ignore_ascii_case("100-continue");
+        }
+    }
+
+    // a proxy in front of the server could frame the body by the other header, see RFC 7230
+    // section 3.3.3
+    if chunked && content_length.is_some() {
+        return Ok(bad_request(
+            "Request has both Content-Length and Transfer-Encoding",
+        ));
+    }
+
+    let too_large = || {
+        let err = Error::new(
+            ErrorKind::InvalidNetworkMessage,
+            format!(
+                "Request exceeds the maximum size of {} bytes",
+                max_body_size
+            ),
+        );
+        Err(HttpResponse::error(413, &err))
+    };
+    if content_length.is_some_and(|length| length > max_body_size) {
+        return Ok(too_large());
+    }
+    // clients that wait for permission do not send the body before they get it
+    if expect_continue && (chunked || content_length.is_some_and(|length| length > 0)) {
+        wstream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        wstream.flush()?;
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            let mut limited = (&mut *rstream).take(MAX_HEAD_SIZE);
+            if read_line(&mut limited, &mut line)? == 0 {
+                return Ok(bad_request("Chunked body is incomplete"));
+            }
+            // chunk extensions are ignored
+            let size = line.trim_end().split(';').next().unwrap_or_default();
+            let size = match u64::from_str_radix(size.trim(), 16) {
+                Ok(size) => size,
+                Err(_) => return Ok(bad_request("Malformed chunk size")),
+            };
+            if size == 0 {
+                break;
+            }
+            if (body.len() as u64)
+                .checked_add(size)
+                .is_none_or(|length| length > max_body_size)
+            {
+                return Ok(too_large());
+            }
+            (&mut *rstream).take(size).read_to_end(&mut body)?;
+            line.clear();
+            read_line(&mut (&mut *rstream).take(2), &mut line)?;
+            if line != "\r\n" {
+                return Ok(bad_request("Malformed chunk"));
+            }
+        }
+        // trailers are ignored
+        loop {
+            line.clear();
+            let mut limited = (&mut *rstream).take(MAX_HEAD_SIZE);
+            if read_line(&mut limited, &mut line)? == 0 {
+                return Ok(bad_request("Chunked body is incomplete"));
+            }
+            if line.trim_end().is_empty() {
+                break;
+            }
+        }
+    } else if let Some(length) = content_length {
+        (&mut *rstream).take(length).read_to_end(&mut body)?;
+        if (body.len() as u64) < length {
+            return Ok(bad_request("Body is shorter than its Content-Length"));
+        }
+    }
+
+    Ok(Ok(Some(HttpRequest {
+        method,
+        path,
+        query,
+        body,
+        close,
+    })))
+}
+
+/// Reads a line that must be valid UTF-8, returns the number of bytes read
+fn read_line<R>(reader: &mut R, line: &mut String) -> Result<usize>
+where
+    R: BufRead,
+{
+    reader.read_line(line).map_err(|err| match err.kind() {
+        io::ErrorKind::InvalidData => Error::new(
+            ErrorKind::InvalidNetworkMessage,
+            "Request head is not valid UTF-8",
+        ),
+        _ => Error::from(err),
+    })
+}
+
+fn route<E>(
+    engine: &E,
+    config: &ServerConfig,
+    stats: &HttpStats,
+    request: &HttpRequest,
+) -> HttpResponse
+where
+    E: KvsEngine,
+{
+    let method = request.method.as_str();
+    match request.path.as_str() {
+        "/health" => match method {
+            "GET" => HttpResponse::json(200, json!({ "status": "ok" })),
+            _ => HttpResponse::method_not_allowed("GET"),
+        },
+        "/stats" => match method {
+            "GET" => HttpResponse::json(200, stats_body(engine, stats)),
+            _ => HttpResponse::method_not_allowed("GET"),
+        },
+        "/keys" => match method {
+            "GET" => {
+                let started = Instant::now();
+                let result = list_keys(engine, request.query.as_deref().unwrap_or_default());
+                config.record(Some(Operation::Scan), result.is_err(), started);
+                result.unwrap_or_else(|err| HttpResponse::error(error_status(&err), &err))
+            }
+            _ => HttpResponse::method_not_allowed("GET"),
+        },
+        path => match path.strip_prefix("/keys/") {
+            Some(key) if !key.is_empty() => {
+                let key = match percent_decode(key, false) {
+                    Some(key) => key,
+                    None => {
+                        let err = Error::new(ErrorKind::InvalidValue, "Key is not valid UTF-8");
+                        return HttpResponse::error(400, &err);
+                    }
+                };
+                let operation = match method {
+                    "GET" => Some(Operation::Get),
+                    "PUT" => Some(Operation::Set),
+                    "DELETE" => Some(Operation::Remove),
+                    _ => None,
+                };
+                let started = Instant::now();
+                let result = key_request(engine, method, key, &request.body);
+                config.record(operation, result.is_err(), started);
+                result.unwrap_or_else(|err| HttpResponse::error(error_status(&err), &err))
+            }
+            _ => HttpResponse::not_found(path),
+        },
+    }
+}
+
+fn key_request<E>(engine: &E, method: &str, key: String, body: &[u8]) -> Result<HttpResponse>
+where
+    E: KvsEngine,
+{
+    match method {
+        "GET" => match engine.get(key)? {
+            Some(value) => Ok(HttpResponse::text(200, value)),
+            None => Err(Error::from(ErrorKind::KeyNotFound)),
+        },
+        "PUT" => {
+            let value = String::from_utf8(body.to_vec())
+                .map_err(|_| Error::new(ErrorKind::InvalidValue, "Value is not valid UTF-8"))?;
+            engine.set(key, value)?;
+            Ok(HttpResponse::empty(204))
+        }
+        "DELETE" => {
+            engine.remove(key)?;
+            Ok(HttpResponse::empty(204))
+        }
+        _ => Ok(HttpResponse::method_not_allowed("GET, PUT, DELETE")),
+    }
+}
+
+fn list_keys<E>(engine: &E, query: &str) -> Result<HttpResponse>
+where
+    E: KvsEngine,
+{
+    let mut start = String::new();
+    let mut end = None;
+    let mut prefix = String::new();
+    let mut limit = DEFAULT_LIST_LIMIT;
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        let value = percent_decode(value, true).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidValue,
+                format!("Parameter {} is not valid UTF-8", name),
+            )
+        })?;
+        match name {
+            "start" => start = value,
+            "end" => end = Some(value),
+            "prefix" => prefix = value,
+            "limit" => {
+                limit = value
+                    .parse()
+                    .ok()
+                    .filter(|limit| (1..=MAX_LIST_LIMIT).contains(limit))
+                    .ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidValue,
+                            format!("Limit must be between 1 and {}", MAX_LIST_LIMIT),
+                        )
+                    })?
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidValue,
+                    format!("Unknown parameter {}", name),
+                ))
+            }
+        }
+    }
+
+    // keys that begin with the prefix are not less than the prefix
+    if start < prefix {
+        start = prefix.clone();
+    }
+    let scanned = engine.scan(start, limit)?;
+    let full = scanned.len() == limit;
+    let keys: Vec<_> = scanned
+        .into_iter()
+        .take_while(|key| key.starts_with(&prefix) && end.as_ref().is_none_or(|end| key < end))
+        .collect();
+    let next = match keys.last() {
+        Some(last) if full && keys.len() == limit => Some(scan_successor(last)),
+        _ => None,
+    };
+    Ok(HttpResponse::json(
+        200,
+        json!({ "keys": keys, "next": next }),
+    ))
+}
+
+fn stats_body<E>(engine: &E, stats: &HttpStats) -> serde_json::Value
+where
+    E: KvsEngine,
+{
+    json!({
+        "uptime_secs": stats.started.elapsed().as_secs(),
+        "open_connections": stats.open_connections.load(Ordering::Relaxed),
+        "requests": stats.requests.load(Ordering::Relaxed),
+        "client_errors": stats.client_errors.load(Ordering::Relaxed),
+        "server_errors": stats.server_errors.load(Ordering::Relaxed),
+        "replication": engine.replication().map(|replication| replication.status()),
+    })
+}
+
+/// Decodes `%XX` escapes, and `+` as a space in query strings. Returns `None` if an escape is
+/// malformed or the result is not valid UTF-8.
+fn percent_decode(s: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                let hex = std::str::from_utf8(hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Tells a client that the server has too many connections to serve it
+pub(super) fn reply_busy(stream: &mut Stream) -> io::Result<()> {
+    let err = Error::new(
+        ErrorKind::ServerBusy,
+        "Server reached its maximum number of connections",
+    );
+    HttpResponse::error(503, &err).write_to(stream, true)
+}
@@ -1,34 +1,307 @@
-use crate::networking::{KvsClient, KvsServer};
+use crate::engines::WriteOp;
+use crate::networking::admin;
+use crate::networking::auth::{self, Session};
+use crate::networking::metrics::Operation;
+use crate::networking::protocol::{
+    AdminCommand, AdminReply, AdminResponse, AppendResponse, Envelope, GetResponse, Incoming,
+    IncrementResponse, MultiGetResponse, MultiRemoveResponse, MultiSetResponse, PromoteResponse,
+    RemoteError, RemoveResponse, ReplicationStatusResponse, Request, Response, ScanResponse,
+    SetResponse, WatchMessage,
+};
+use crate::networking::{
+    Addr, Connection, KvsClient, KvsServer, Registration, ServerConfig, ServerHandle, Stream,
+};
+use crate::replication::{self, ReplicationStatus};
 use crate::thread_pool::ThreadPool;
 use crate::{Error, ErrorKind, KvsEngine, Result};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::de::{Deserializer, IoRead};
-use slog::Drain;
-use std::io::{BufReader, BufWriter, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
 
-/// Network client for JSON message
+/// Number of pipelined requests that can be waiting for a response, older responses are read
+/// and buffered before more requests are sent so that neither side blocks on a full socket
+const MAX_IN_FLIGHT: usize = 128;
+
+/// Time without any message after which a watched server is considered gone
+const WATCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Decodes the body of a response to a pipelined request, it depends on the kind of the request
+type ResponseDecoder = fn(serde_json::Value) -> serde_json::Result<Response>;
+
+/// Network client for JSON message.
+///
+/// Besides the blocking methods of `KvsClient`, requests can be pipelined with `send` and their
+/// responses collected later with `recv`. Pipelined requests are tagged with an ID and the
+/// server answers them in the order that they were sent.
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{JsonKvsClient, Request, Response, SetResponse};
+/// use kvs::{KvsClient, Result};
+///
+/// fn main() -> Result<()> {
+///     let mut client = JsonKvsClient::connect(([127, 0, 0, 1], 4000))?;
+///     let requests = (0..1000).map(|i| Request::Set {
+///         key: format!("key{}", i),
+///         value: format!("value{}", i),
+///     });
+///     for response in client.pipeline(requests)? {
+///         assert!(matches!(response, Response::Set(SetResponse::Ok)));
+///     }
+///     Ok(())
+/// }
+/// ```
 #[allow(missing_debug_implementations)]
 pub struct JsonKvsClient {
-    rstream: Deserializer<IoRead<BufReader<TcpStream>>>,
-    wstream: BufWriter<TcpStream>,
+    rstream: Deserializer<IoRead<BufReader<Stream>>>,
+    wstream: BufWriter<Stream>,
+    next_id: u64,
+    in_flight: VecDeque<(u64, ResponseDecoder)>,
+    received: VecDeque<(u64, Response)>,
+}
+
+impl JsonKvsClient {
+    /// Connects to the remote server at `addr` and authenticates with `token`, for servers that
+    /// require authentication
+    pub fn connect_with_token<A>(addr: A, token: &str) -> Result<Self>
+    where
+        A: Into<Addr>,
+    {
+        let mut client = Self::connect(addr)?;
+        client.authenticate(token)?;
+        Ok(client)
+    }
+
+    /// Authenticates the connection as the user whose token is `token`, the following requests
+    /// can access the keys of that user. Returns the name of the user.
+    pub fn authenticate(&mut self, token: &str) -> Result<String> {
+        self.finish_in_flight()?;
+        auth::authenticate(&mut self.rstream, &mut self.wstream, token)
+    }
+
+    /// Queues the request without waiting for its response and returns the ID of the request.
+    /// Requests are buffered, they are sent at the latest when a response is received.
+    pub fn send(&mut self, request: Request) -> Result<u64> {
+        if self.in_flight.len() >= MAX_IN_FLIGHT {
+            let response = self.read_tagged()?;
+            self.received.push_back(response);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let decoder: ResponseDecoder = match request {
+            Request::Set { .. } => |v| serde_json::from_value(v).map(Response::Set),
+            Request::Get { .. } => |v| serde_json::from_value(v).map(Response::Get),
+            Request::Remove { .. } => |v| serde_json::from_value(v).map(Response::Remove),
+            Request::Increment { .. } => |v| serde_json::from_value(v).map(Response::Increment),
+            Request::Append { .. } => |v| serde_json::from_value(v).map(Response::Append),
+            Request::MultiGet { .. } => |v| serde_json::from_value(v).map(Response::MultiGet),
+            Request::MultiSet { .. } => |v| serde_json::from_value(v).map(Response::MultiSet),
+            Request::MultiRemove { .. } => |v| serde_json::from_value(v).map(Response::MultiRemove),
+            Request::Scan { .. } => |v| serde_json::from_value(v).map(Response::Scan),
+            Request::Replicate { .. } => |v| serde_json::from_value(v).map(Response::Replicate),
+            Request::Watch { .. } => |v| serde_json::from_value(v).map(Response::Watch),
+            Request::Promote => |v| serde_json::from_value(v).map(Response::Promote),
+            Request::ReplicationStatus => {
+                |v| serde_json::from_value(v).map(Response::ReplicationStatus)
+            }
+            Request::Challenge => |v| serde_json::from_value(v).map(Response::Challenge),
+            Request::Authenticate { .. } => {
+                |v| serde_json::from_value(v).map(Response::Authenticate)
+            }
+            Request::Admin { .. } => |v| serde_json::from_value(v).map(Response::Admin),
+        };
+        serde_json::to_writer(&mut self.wstream, &Envelope { id, body: request })?;
+        self.in_flight.push_back((id, decoder));
+        Ok(id)
+    }
+
+    /// Returns the ID and the response of the oldest request that was queued with `send` and
+    /// whose response has not been returned yet
+    pub fn recv(&mut self) -> Result<(u64, Response)> {
+        match self.received.pop_front() {
+            Some(response) => Ok(response),
+            None => self.read_tagged(),
+        }
+    }
+
+    /// Sends every request before waiting for any response, and returns the responses in the
+    /// order of the requests
+    pub fn pipeline<I>(&mut self, requests: I) -> Result<Vec<Response>>
+    where
+        I: IntoIterator<Item = Request>,
+    {
+        // responses to earlier calls to `send` are kept for `recv`, they are not part of the result
+        self.finish_in_flight()?;
+        let earlier = std::mem::take(&mut self.received);
+
+        let mut count = 0;
+        for request in requests {
+            self.send(request)?;
+            count += 1;
+        }
+        let responses = (0..count)
+            .map(|_| self.recv().map(|(_, response)| response))
+            .collect();
+
+        self.received = earlier;
+        responses
+    }
+
+    fn read_tagged(&mut self) -> Result<(u64, Response)> {
+        let (id, decoder) = self.in_flight.pop_front().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                "There is no request that is waiting for a response",
+            )
+        })?;
+        self.wstream.flush()?;
+
+        let envelope = Envelope::<serde_json::Value>::deserialize(&mut self.rstream)?;
+        if envelope.id != id {
+            return Err(Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                format!("Expected response to request {}, got {}", id, envelope.id),
+            ));
+        }
+        Ok((id, decoder(envelope.body)?))
+    }
+
+    /// Makes a replica stop following its primary and accept writes
+    pub fn promote(&mut self) -> Result<()> {
+        self.finish_in_flight()?;
+        serde_json::to_writer(&mut self.wstream, &Request::Promote)?;
+        self.wstream.flush()?;
+
+        match PromoteResponse::deserialize(&mut self.rstream)? {
+            PromoteResponse::Ok => Ok(()),
+            PromoteResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Returns the role of the server and how far a replica is behind its primary
+    pub fn replication_status(&mut self) -> Result<ReplicationStatus> {
+        self.finish_in_flight()?;
+        serde_json::to_writer(&mut self.wstream, &Request::ReplicationStatus)?;
+        self.wstream.flush()?;
+
+        match ReplicationStatusResponse::deserialize(&mut self.rstream)? {
+            ReplicationStatusResponse::Ok(status) => Ok(status),
+            ReplicationStatusResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Runs a maintenance command on the server, `token` is the admin token of the server if it
+    /// has one
+    pub fn admin(&mut self, command: AdminCommand, token: Option<&str>) -> Result<AdminReply> {
+        self.finish_in_flight()?;
+        let token = token.map(str::to_owned);
+        serde_json::to_writer(&mut self.wstream, &Request::Admin { token, command })?;
+        self.wstream.flush()?;
+
+        match AdminResponse::deserialize(&mut self.rstream)? {
+            AdminResponse::Ok(reply) => Ok(reply),
+            AdminResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    /// Turns the connection into a stream of the operations on the keys that start with
+    /// `key_or_prefix`, in the order that they are committed on the server. The stream starts
+    /// once this returns, and it ends with an error if the server is gone or if the client reads
+    /// too slowly for the server to keep the changes that it has not read yet.
+    pub fn watch(mut self, key_or_prefix: String) -> Result<Watcher> {
+        self.finish_in_flight()?;
+        serde_json::to_writer(&mut self.wstream, &Request::Watch { key_or_prefix })?;
+        self.wstream.flush()?;
+
+        match WatchMessage::deserialize(&mut self.rstream)? {
+            WatchMessage::Started => {}
+            WatchMessage::Err(err) => return Err(Error::from(err)),
+            message => {
+                return Err(Error::new(
+                    ErrorKind::InvalidNetworkMessage,
+                    format!("Expected the watch to start, got {:?}", message),
+                ))
+            }
+        }
+        // the server sends a heartbeat at least every second
+        self.wstream
+            .get_ref()
+            .set_read_timeout(Some(WATCH_TIMEOUT))?;
+        Ok(Watcher {
+            rstream: self.rstream,
+            ops: VecDeque::new(),
+        })
+    }
+
+    /// Returns `true` if the server closed the connection or the connection failed, without
+    /// waiting for the server. Requests can not have been sent on a closed connection.
+    pub(crate) fn is_closed(&self) -> bool {
+        super::peer_closed(self.wstream.get_ref())
+    }
+
+    /// Reads the responses to pipelined requests so that the next response on the stream is
+    /// the one to a blocking call
+    fn finish_in_flight(&mut self) -> Result<()> {
+        while !self.in_flight.is_empty() {
+            let response = self.read_tagged()?;
+            self.received.push_back(response);
+        }
+        Ok(())
+    }
+}
+
+/// Operations on watched keys that a server pushes, see `JsonKvsClient::watch`. Iterating blocks
+/// until the next operation and ends when the server closes the connection.
+#[allow(missing_debug_implementations)]
+pub struct Watcher {
+    rstream: Deserializer<IoRead<BufReader<Stream>>>,
+    /// Operations of a change that were not returned yet
+    ops: VecDeque<WriteOp>,
+}
+
+impl Iterator for Watcher {
+    type Item = Result<WriteOp>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(op) = self.ops.pop_front() {
+                return Some(Ok(op));
+            }
+            match WatchMessage::deserialize(&mut self.rstream) {
+                Ok(WatchMessage::Change(ops)) => self.ops.extend(ops),
+                Ok(WatchMessage::Started) | Ok(WatchMessage::Heartbeat) => {}
+                Ok(WatchMessage::Err(err)) => return Some(Err(Error::from(err))),
+                Err(err) if err.is_eof() => return None,
+                Err(err) => return Some(Err(Error::from(err))),
+            }
+        }
+    }
 }
 
 impl KvsClient for JsonKvsClient {
     /// Connect to the remote server at `addr` and return the client to it
     fn connect<A>(addr: A) -> Result<Self>
     where
-        A: Into<SocketAddr>,
+        A: Into<Addr>,
     {
-        let wstream = TcpStream::connect(addr.into())?;
+        let wstream = Stream::connect(&addr.into())?;
         let rstream = wstream.try_clone()?;
         Ok(Self {
             rstream: Deserializer::new(IoRead::new(BufReader::new(rstream))),
             wstream: BufWriter::new(wstream),
+            next_id: 0,
+            in_flight: VecDeque::new(),
+            received: VecDeque::new(),
         })
     }
 
     fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.finish_in_flight()?;
         let set_request = Request::Set { key, value };
         serde_json::to_writer(&mut self.wstream, &set_request)?;
         self.wstream.flush()?;
@@ -36,11 +309,12 @@ impl KvsClient for JsonKvsClient {
         let set_response = SetResponse::deserialize(&mut self.rstream)?;
         match set_response {
             SetResponse::Ok => Ok(()),
-            SetResponse::Err(err) => Err(Error::new(ErrorKind::ServerError, err)),
+            SetResponse::Err(err) => Err(Error::from(err)),
         }
     }
 
     fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.finish_in_flight()?;
         let get_request = Request::Get { key };
         serde_json::to_writer(&mut self.wstream, &get_request)?;
         self.wstream.flush()?;
@@ -48,11 +322,12 @@ impl KvsClient for JsonKvsClient {
         let get_response = GetResponse::deserialize(&mut self.rstream)?;
         match get_response {
             GetResponse::Ok(val) => Ok(val),
-            GetResponse::Err(err) => Err(Error::new(ErrorKind::ServerError, err)),
+            GetResponse::Err(err) => Err(Error::from(err)),
         }
     }
 
     fn remove(&mut self, key: String) -> Result<()> {
+        self.finish_in_flight()?;
         let remove_request = Request::Remove { key };
         serde_json::to_writer(&mut self.wstream, &remove_request)?;
         self.wstream.flush()?;
@@ -60,7 +335,85 @@ impl KvsClient for JsonKvsClient {
         let remove_response = RemoveResponse::deserialize(&mut self.rstream)?;
         match remove_response {
             RemoveResponse::Ok => Ok(()),
-            RemoveResponse::Err(err) => Err(Error::new(ErrorKind::ServerError, err)),
+            RemoveResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        self.finish_in_flight()?;
+        let increment_request = Request::Increment { key, delta };
+        serde_json::to_writer(&mut self.wstream, &increment_request)?;
+        self.wstream.flush()?;
+
+        let increment_response = IncrementResponse::deserialize(&mut self.rstream)?;
+        match increment_response {
+            IncrementResponse::Ok(val) => Ok(val),
+            IncrementResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn append(&mut self, key: String, suffix: String) -> Result<()> {
+        self.finish_in_flight()?;
+        let append_request = Request::Append { key, suffix };
+        serde_json::to_writer(&mut self.wstream, &append_request)?;
+        self.wstream.flush()?;
+
+        let append_response = AppendResponse::deserialize(&mut self.rstream)?;
+        match append_response {
+            AppendResponse::Ok => Ok(()),
+            AppendResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn multi_get(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.finish_in_flight()?;
+        let multi_get_request = Request::MultiGet { keys };
+        serde_json::to_writer(&mut self.wstream, &multi_get_request)?;
+        self.wstream.flush()?;
+
+        let multi_get_response = MultiGetResponse::deserialize(&mut self.rstream)?;
+        match multi_get_response {
+            MultiGetResponse::Ok(vals) => Ok(vals),
+            MultiGetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn multi_set(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.finish_in_flight()?;
+        let multi_set_request = Request::MultiSet { pairs };
+        serde_json::to_writer(&mut self.wstream, &multi_set_request)?;
+        self.wstream.flush()?;
+
+        let multi_set_response = MultiSetResponse::deserialize(&mut self.rstream)?;
+        match multi_set_response {
+            MultiSetResponse::Ok => Ok(()),
+            MultiSetResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn multi_remove(&mut self, keys: Vec<String>) -> Result<u64> {
+        self.finish_in_flight()?;
+        let multi_remove_request = Request::MultiRemove { keys };
+        serde_json::to_writer(&mut self.wstream, &multi_remove_request)?;
+        self.wstream.flush()?;
+
+        let multi_remove_response = MultiRemoveResponse::deserialize(&mut self.rstream)?;
+        match multi_remove_response {
+            MultiRemoveResponse::Ok(removed) => Ok(removed),
+            MultiRemoveResponse::Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    fn scan(&mut self, start: String, limit: usize) -> Result<Vec<String>> {
+        self.finish_in_flight()?;
+        let scan_request = Request::Scan { start, limit };
+        serde_json::to_writer(&mut self.wstream, &scan_request)?;
+        self.wstream.flush()?;
+
+        let scan_response = ScanResponse::deserialize(&mut self.rstream)?;
+        match scan_response {
+            ScanResponse::Ok(keys) => Ok(keys),
+            ScanResponse::Err(err) => Err(Error::from(err)),
         }
     }
 }
@@ -74,40 +427,29 @@ where
 {
     engine: E,
     pool: P,
+    config: ServerConfig,
     logger: slog::Logger,
 }
 
 impl<E, P> KvsServer for JsonKvsServer<E, P>
 where
     E: KvsEngine,
-    P: ThreadPool,
+    P: ThreadPool + Send + 'static,
 {
-    fn serve<A>(&self, addr: A) -> Result<()>
+    fn serve<A>(self, addr: A) -> Result<ServerHandle>
     where
-        A: Into<SocketAddr>,
+        A: Into<Addr>,
     {
-        let addr = addr.into();
-        let logger = self.logger.new(o!("addr" => addr.to_string()));
-        info!(logger, "Starting key-value store server");
-
-        let tcp_listener = TcpListener::bind(addr)?;
-        for stream in tcp_listener.incoming() {
-            if let Err(err) = stream {
-                error!(logger, "Could not connect TcpStream"; "error" => err);
-                continue;
-            }
-
-            let stream = stream.unwrap();
-            let engine = self.engine.clone();
-            let logger = logger.new(o!( "peer_addr" => stream.peer_addr()?.to_string() ));
-
-            self.pool.spawn(move || {
-                if let Err(err) = Self::handle(engine, stream) {
-                    error!(logger, "Could not handle client"; "error" => format!("{}", err));
-                }
-            });
-        }
-        Ok(())
+        let config = self.config.clone();
+        super::serve_with(
+            self.engine,
+            self.pool,
+            &self.config,
+            self.logger,
+            addr.into(),
+            move |engine, stream, connection| handle(engine, stream, &config, connection),
+            reply_busy,
+        )
     }
 }
 
@@ -118,104 +460,167 @@ where
 {
     /// Create a new JSON server
     pub fn new(engine: E, pool: P, logger: Option<slog::Logger>) -> Self {
-        let logger = logger.map(|l| l.into()).unwrap_or({
-            // TODO: make default log config
-            let decorator = slog_term::TermDecorator::new().build();
-            let drain = slog_term::FullFormat::new(decorator).build().fuse();
-            let drain = slog_async::Async::new(drain).build().fuse();
-            slog::Logger::root(drain, o!())
-        });
+        Self::with_config(engine, pool, ServerConfig::default(), logger)
+    }
+
+    /// Create a new JSON server that limits its clients according to the given configuration
+    pub fn with_config(
+        engine: E,
+        pool: P,
+        config: ServerConfig,
+        logger: Option<slog::Logger>,
+    ) -> Self {
+        let logger = logger.unwrap_or_else(super::default_logger);
 
         Self {
             engine,
             pool,
+            config,
             logger,
         }
     }
+}
 
-    fn handle(engine: E, stream: TcpStream) -> Result<()> {
-        let mut wstream = BufWriter::new(stream.try_clone()?);
-        let rstream = Deserializer::new(IoRead::new(BufReader::new(stream)));
+/// Serves requests from a client that speaks JSON until the connection is closed or stays idle
+/// for too long. Requests are answered in the order that they are received, tagged requests get a
+/// response with the same ID.
+pub(crate) fn handle<E>(
+    engine: E,
+    stream: Stream,
+    config: &ServerConfig,
+    connection: &Connection,
+) -> Result<()>
+where
+    E: KvsEngine,
+{
+    config.apply(&stream)?;
+    let mut wstream = BufWriter::new(stream.try_clone()?);
+    let mut rstream = BufReader::new(stream);
+    let max_request_size = config.get_max_request_size() as u64;
+    let mut session = Session::new(config.get_auth());
+    let request_log = config
+        .get_request_log()
+        .map(|log| log.connection(&connection.logger));
 
-        for request in rstream.into_iter() {
-            let request = request?;
-            match request {
-                Request::Set { key, value } => {
-                    let res = match engine.set(key, value) {
-                        Ok(_) => SetResponse::Ok,
-                        Err(err) => SetResponse::Err(format!("{}", err)),
-                    };
-                    serde_json::to_writer(&mut wstream, &res)?;
-                    wstream.flush()?;
-                }
-                Request::Get { key } => {
-                    let res = match engine.get(key) {
-                        Ok(v) => GetResponse::Ok(v),
-                        Err(err) => GetResponse::Err(format!("{}", err)),
-                    };
-                    serde_json::to_writer(&mut wstream, &res)?;
-                    wstream.flush()?;
-                }
-                Request::Remove { key } => {
-                    let res = match engine.remove(key) {
-                        Ok(_) => RemoveResponse::Ok,
-                        Err(err) => RemoveResponse::Err(format!("{}", err)),
-                    };
-                    serde_json::to_writer(&mut wstream, &res)?;
-                    wstream.flush()?;
-                }
-            };
+    while config.wait_for_request(&mut rstream)? {
+        // whitespace in between requests must not start the read timeout
+        let buf = rstream.fill_buf()?;
+        let whitespace = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        if whitespace > 0 {
+            rstream.consume(whitespace);
+            continue;
         }
 
-        Ok(())
-    }
-}
+        // one byte over the limit tells a request of the maximum size apart from a larger one
+        let mut limited = (&mut rstream).take(max_request_size + 1);
+        let incoming = Incoming::deserialize(&mut Deserializer::from_reader(&mut limited));
+        if limited.limit() == 0 {
+            let err = Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                format!(
+                    "Request exceeds the maximum size of {} bytes",
+                    max_request_size
+                ),
+            );
+            // the rest of the request can not be skipped reliably, so the connection is closed
+            serde_json::to_writer(&mut wstream, &SetResponse::Err(RemoteError::from(&err)))?;
+            wstream.flush()?;
+            let _ = super::linger(rstream.get_mut());
+            return Err(err);
+        }
 
-/// Network request message for KvsEngine command
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Request {
-    /// Set command request
-    Set {
-        /// Set key
-        key: String,
-        /// Set valye
-        value: String,
-    },
-    /// Get command request
-    Get {
-        /// Get key
-        key: String,
-    },
-    /// Remove command request
-    Remove {
-        /// Remove key
-        key: String,
-    },
-}
+        let (id, request) = match incoming? {
+            Incoming::Tagged(Envelope { id, body }) => (Some(id), body),
+            Incoming::Untagged(request) => (None, request),
+        };
+        let started = Instant::now();
+        let entry = request_log.as_ref().map(|log| log.start(&request));
+        let response = match session.respond(&engine, &request) {
+            Some(response) => {
+                config.record(Operation::of(&request), response.is_err(), started);
+                response
+            }
+            None => match (id, request) {
+                (None, request @ (Request::Replicate { .. } | Request::Watch { .. }))
+                    if engine.replication().is_some() =>
+                {
+                    let stream = rstream.into_inner();
+                    return spawn_stream(engine, request, stream, wstream, connection);
+                }
+                (_, Request::Admin { token, command }) => admin::execute(
+                    &engine,
+                    config.get_admin(),
+                    connection,
+                    token.as_deref(),
+                    command,
+                ),
+                (_, request) => config.execute(&engine, request),
+            },
+        };
+        if let (Some(log), Some(entry)) = (&request_log, entry) {
+            log.finish(entry, &response);
+        }
+        match id {
+            Some(id) => serde_json::to_writer(&mut wstream, &Envelope { id, body: response })?,
+            None => serde_json::to_writer(&mut wstream, &response)?,
+        }
+        wstream.flush()?;
+    }
 
-/// Network request message for KvsEngine set command
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum SetResponse {
-    /// Set command suceeded
-    Ok,
-    /// Set command failed
-    Err(String),
+    Ok(())
 }
 
-/// Network request message for KvsEngine get command
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum GetResponse {
-    /// Get command suceeded
-    Ok(Option<String>),
-    /// Get command failed
-    Err(String),
+/// Streams changes to a replica or a watcher on a thread of its own, so that a stream that lasts
+/// as long as its client stays connected does not hold a thread of the pool
+fn spawn_stream<E>(
+    engine: E,
+    request: Request,
+    stream: Stream,
+    mut wstream: BufWriter<Stream>,
+    connection: &Connection,
+) -> Result<()>
+where
+    E: KvsEngine,
+{
+    let guard = match connection.stream(&stream)? {
+        Registration::Accepted(guard) => guard,
+        Registration::Busy => {
+            let err = Error::new(
+                ErrorKind::ServerBusy,
+                "Server reached its maximum number of streams",
+            );
+            serde_json::to_writer(&mut wstream, &Response::err(&request, &err))?;
+            wstream.flush()?;
+            return Err(err);
+        }
+        Registration::Stopping => return Ok(()),
+    };
+    let logger = connection.logger.clone();
+    thread::spawn(move || {
+        let _guard = guard;
+        let replication = engine.replication().unwrap();
+        let result = match request {
+            Request::Replicate { since } => {
+                replication::serve_replica(&engine, replication, since, &stream, &mut wstream)
+            }
+            Request::Watch { key_or_prefix } => {
+                replication::serve_watcher(replication, &key_or_prefix, &stream, &mut wstream)
+            }
+            _ => unreachable!("only replicate and watch requests are streamed"),
+        };
+        if let Err(err) = result {
+            error!(logger, "Could not handle client"; "error" => format!("{}", err));
+        }
+    });
+    Ok(())
 }
 
-/// Network request message for KvsEngine remove command
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum RemoveResponse {
-    /// Remove command suceeded
-    Ok,
-    /// Remove command failed
-    Err(String),
+/// Tells a client that the server has too many connections to serve it
+pub(crate) fn reply_busy(stream: &mut Stream) -> io::Result<()> {
+    let err = Error::new(
+        ErrorKind::ServerBusy,
+        "Server reached its maximum number of connections",
+    );
+    serde_json::to_writer(stream, &SetResponse::Err(RemoteError::from(&err)))?;
+    Ok(())
 }
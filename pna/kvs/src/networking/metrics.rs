@@ -0,0 +1,369 @@
+use crate::networking::http::{self, HttpRequest, HttpResponse};
+use crate::networking::{Addr, KvsServer, Request, ServerConfig, ServerHandle, Stream};
+use crate::thread_pool::{NaiveThreadPool, ThreadPool};
+use crate::{KvStore, Result};
+use std::convert::TryFrom;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds in seconds of the buckets of the request latency histograms
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// Maximum number of scrapes that are served at the same time
+const MAX_SCRAPERS: usize = 16;
+
+/// Operations whose requests are counted and timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Set,
+    Get,
+    Remove,
+    Increment,
+    Append,
+    MultiGet,
+    MultiSet,
+    MultiRemove,
+    Scan,
+}
+
+impl Operation {
+    const ALL: [Operation; 9] = [
+        Self::Set,
+        Self::Get,
+        Self::Remove,
+        Self::Increment,
+        Self::Append,
+        Self::MultiGet,
+        Self::MultiSet,
+        Self::MultiRemove,
+        Self::Scan,
+    ];
+
+    /// Returns the operation of a request, replication, watch, authentication and admin requests
+    /// are not counted
+    pub(crate) fn of(request: &Request) -> Option<Self> {
+        match request {
+            Request::Set { .. } => Some(Self::Set),
+            Request::Get { .. } => Some(Self::Get),
+            Request::Remove { .. } => Some(Self::Remove),
+            Request::Increment { .. } => Some(Self::Increment),
+            Request::Append { .. } => Some(Self::Append),
+            Request::MultiGet { .. } => Some(Self::MultiGet),
+            Request::MultiSet { .. } => Some(Self::MultiSet),
+            Request::MultiRemove { .. } => Some(Self::MultiRemove),
+            Request::Scan { .. } => Some(Self::Scan),
+            Request::Replicate { .. }
+            | Request::Watch { .. }
+            | Request::Promote
+            | Request::ReplicationStatus
+            | Request::Challenge
+            | Request::Authenticate { .. }
+            | Request::Admin { .. } => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Set => "set",
+            Self::Get => "get",
+            Self::Remove => "remove",
+            Self::Increment => "increment",
+            Self::Append => "append",
+            Self::MultiGet => "multi_get",
+            Self::MultiSet => "multi_set",
+            Self::MultiRemove => "multi_remove",
+            Self::Scan => "scan",
+        }
+    }
+}
+
+/// Counters and latency histogram of one operation
+#[derive(Debug, Default)]
+struct OperationMetrics {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    /// Number of requests per bucket, the last bucket holds the requests slower than every bound
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    duration_nanos: AtomicU64,
+}
+
+/// Writes more lines of the exposition, such as the gauges of an engine or a thread pool
+type Collector = Box<dyn Fn(&mut String) -> fmt::Result + Send + Sync>;
+
+/// Metrics of a server, rendered in the Prometheus text format. Servers record their requests and
+/// connections once the metrics are part of their `ServerConfig`, and several servers can share
+/// the same metrics.
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{JsonKvsServer, Metrics, MetricsServer, ServerConfig};
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, KvsServer, Result};
+/// use std::sync::Arc;
+///
+/// fn main() -> Result<()> {
+///     let engine = KvStore::open(std::env::current_dir()?)?;
+///     let pool = Arc::new(SharedQueueThreadPool::new(4)?);
+///     let metrics = Arc::new(Metrics::new());
+///     metrics.observe_pool(Arc::clone(&pool));
+///     metrics.observe_kv_store(engine.clone());
+///     let config = ServerConfig::default().metrics(Arc::clone(&metrics));
+///     JsonKvsServer::with_config(engine, pool, config, None).serve(([127, 0, 0, 1], 4000))?;
+///     MetricsServer::new(metrics, None).serve(([127, 0, 0, 1], 9100))?;
+///     Ok(())
+/// }
+/// ```
+pub struct Metrics {
+    operations: [OperationMetrics; Operation::ALL.len()],
+    connections: AtomicUsize,
+    collectors: Mutex<Vec<Collector>>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("operations", &self.operations)
+            .field("connections", &self.connections)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Creates metrics where nothing was recorded yet
+    pub fn new() -> Self {
+        Self {
+            operations: Default::default(),
+            connections: AtomicUsize::new(0),
+            collectors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Reports the number of tasks that wait for a thread of the pool, if the pool can tell
+    pub fn observe_pool<P>(&self, pool: P)
+    where
+        P: ThreadPool + Send + Sync + 'static,
+    {
+        self.collect(move |out| match pool.queued_jobs() {
+            Some(queued) => {
+                writeln!(
+                    out,
+                    "# HELP kvs_thread_pool_queued_jobs Tasks that wait for a thread of the pool"
+                )?;
+                writeln!(out, "# TYPE kvs_thread_pool_queued_jobs gauge")?;
+                writeln!(out, "kvs_thread_pool_queued_jobs {}", queued)
+            }
+            None => Ok(()),
+        });
+    }
+
+    /// Reports the garbage in the logs of the store and the number of compactions
+    pub fn observe_kv_store(&self, store: KvStore) {
+        // the store is only read when the metrics are rendered
+        let store = Mutex::new(store);
+        self.collect(move |out| {
+            let stats = store.lock().unwrap().stats();
+            writeln!(
+                out,
+                "# HELP kvs_engine_garbage_bytes Bytes in the logs that are no longer referenced"
+            )?;
+            writeln!(out, "# TYPE kvs_engine_garbage_bytes gauge")?;
+            writeln!(out, "kvs_engine_garbage_bytes {}", stats.garbage_bytes)?;
+            writeln!(
+                out,
+                "# HELP kvs_engine_merges_total Compactions since the store was opened"
+            )?;
+            writeln!(out, "# TYPE kvs_engine_merges_total counter")?;
+            writeln!(out, "kvs_engine_merges_total {}", stats.merges)
+        });
+    }
+
+    fn collect<F>(&self, collector: F)
+    where
+        F: Fn(&mut String) -> fmt::Result + Send + Sync + 'static,
+    {
+        self.collectors.lock().unwrap().push(Box::new(collector));
+    }
+
+    /// Returns the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        // writing to a string never fails
+        let _ = self.render_to(&mut out);
+        out
+    }
+
+    fn render_to(&self, out: &mut String) -> fmt::Result {
+        writeln!(out, "# HELP kvs_requests_total Requests that were served")?;
+        writeln!(out, "# TYPE kvs_requests_total counter")?;
+        for (op, metrics) in self.operations() {
+            let requests = metrics.requests.load(Ordering::Relaxed);
+            writeln!(out, "kvs_requests_total{{op=\"{}\"}} {}", op, requests)?;
+        }
+
+        writeln!(
+            out,
+            "# HELP kvs_request_errors_total Requests that were answered with an error"
+        )?;
+        writeln!(out, "# TYPE kvs_request_errors_total counter")?;
+        for (op, metrics) in self.operations() {
+            let errors = metrics.errors.load(Ordering::Relaxed);
+            writeln!(out, "kvs_request_errors_total{{op=\"{}\"}} {}", op, errors)?;
+        }
+
+        writeln!(
+            out,
+            "# HELP kvs_request_duration_seconds Time that was spent on the requests"
+        )?;
+        writeln!(out, "# TYPE kvs_request_duration_seconds histogram")?;
+        for (op, metrics) in self.operations() {
+            let mut count = 0;
+            for (i, bucket) in metrics.buckets.iter().enumerate() {
+                count += bucket.load(Ordering::Relaxed);
+                let le = match LATENCY_BUCKETS.get(i) {
+                    Some(bound) => bound.to_string(),
+                    None => "+Inf".to_owned(),
+                };
+                writeln!(
+                    out,
+                    "kvs_request_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    op, le, count
+                )?;
+            }
+            let sum = Duration::from_nanos(metrics.duration_nanos.load(Ordering::Relaxed));
+            writeln!(
+                out,
+                "kvs_request_duration_seconds_sum{{op=\"{}\"}} {}",
+                op,
+                sum.as_secs_f64()
+            )?;
+            writeln!(
+                out,
+                "kvs_request_duration_seconds_count{{op=\"{}\"}} {}",
+                op, count
+            )?;
+        }
+
+        writeln!(out, "# HELP kvs_connections Connections that are open")?;
+        writeln!(out, "# TYPE kvs_connections gauge")?;
+        writeln!(
+            out,
+            "kvs_connections {}",
+            self.connections.load(Ordering::Relaxed)
+        )?;
+
+        for collector in self.collectors.lock().unwrap().iter() {
+            collector(out)?;
+        }
+        Ok(())
+    }
+
+    fn operations(&self) -> impl Iterator<Item = (&'static str, &OperationMetrics)> {
+        Operation::ALL
+            .iter()
+            .map(|op| op.as_str())
+            .zip(self.operations.iter())
+    }
+
+    /// Records a request that started at `started`
+    pub(crate) fn record(&self, operation: Operation, failed: bool, started: Instant) {
+        let elapsed = started.elapsed();
+        let metrics = &self.operations[operation as usize];
+        let secs = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        metrics.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        metrics.requests.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            metrics.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        metrics.duration_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Counts a connection as open until the returned value is dropped
+    pub(crate) fn open_connection(self: &Arc<Self>) -> OpenConnection {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        OpenConnection(Arc::clone(self))
+    }
+}
+
+/// Counts a connection as open until it is dropped
+pub(crate) struct OpenConnection(Arc<Metrics>);
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// HTTP server that answers `GET /metrics` with the metrics in the Prometheus text format.
+///
+/// It runs each scrape on its own thread, so that the metrics can be read while the thread pool
+/// of the servers is busy.
+#[derive(Debug)]
+pub struct MetricsServer {
+    metrics: Arc<Metrics>,
+    config: ServerConfig,
+    logger: slog::Logger,
+}
+
+impl MetricsServer {
+    /// Create a new metrics server
+    pub fn new(metrics: Arc<Metrics>, logger: Option<slog::Logger>) -> Self {
+        let logger = logger.unwrap_or_else(super::default_logger);
+        let config = ServerConfig::default().max_connections(Some(MAX_SCRAPERS));
+
+        Self {
+            metrics,
+            config,
+            logger,
+        }
+    }
+}
+
+impl KvsServer for MetricsServer {
+    fn serve<A>(self, addr: A) -> Result<ServerHandle>
+    where
+        A: Into<Addr>,
+    {
+        let config = self.config.clone();
+        super::serve_state(
+            self.metrics,
+            NaiveThreadPool,
+            &self.config,
+            self.logger,
+            addr.into(),
+            move |metrics, stream, _| handle(&metrics, stream, &config),
+            http::reply_busy,
+            || Ok(()),
+        )
+    }
+}
+
+fn handle(metrics: &Metrics, stream: Stream, config: &ServerConfig) -> Result<()> {
+    http::serve_requests(stream, config, |_| {}, |request| route(metrics, request))
+}
+
+fn route(metrics: &Metrics, request: &HttpRequest) -> HttpResponse {
+    match request.path.as_str() {
+        "/metrics" => match request.method.as_str() {
+            "GET" => HttpResponse::text(200, metrics.render())
+                .content_type("text/plain; version=0.0.4; charset=utf-8"),
+            _ => HttpResponse::method_not_allowed("GET"),
+        },
+        path => HttpResponse::not_found(path),
+    }
+}
@@ -1,31 +1,320 @@
 //! Module for handling network communication between client and server
 
+mod admin;
+mod async_json;
+mod auth;
+mod auto;
+mod binary;
+mod config;
+mod failover;
+mod http;
 mod json;
+mod metrics;
+mod protocol;
+mod request_log;
+mod resp;
+mod server_handle;
+mod sharded;
+mod transport;
 
-pub use json::{JsonKvsClient, JsonKvsServer};
+pub use admin::{AdminConfig, LogLevel, LogLevelFilter};
+pub use async_json::{AsyncJsonKvsClient, AsyncJsonKvsServer};
+pub(crate) use auth::authenticate;
+pub use auth::{AuthConfig, AuthMethod, User};
+pub use auto::AutoKvsServer;
+pub use binary::{BinaryKvsClient, BinaryKvsServer, Encoding, PROTOCOL_VERSION};
+pub use config::ServerConfig;
+pub use failover::{FailoverConfig, FailoverKvsClient};
+pub use http::HttpKvsServer;
+pub use json::{JsonKvsClient, JsonKvsServer, Watcher};
+pub use metrics::{Metrics, MetricsServer};
+pub use protocol::{
+    AdminCommand, AdminReply, AdminResponse, AppendResponse, AuthenticateResponse, Challenge,
+    ChallengeResponse, ClientInfo, Envelope, GetResponse, IncrementResponse, MultiGetResponse,
+    MultiRemoveResponse, MultiSetResponse, PromoteResponse, RemoteError, RemoveResponse,
+    ReplicationMessage, ReplicationStatusResponse, Request, Response, ScanResponse, SetResponse,
+    WatchMessage,
+};
+pub use request_log::{LogFormat, RequestLog};
+pub use resp::RespKvsServer;
+pub(crate) use server_handle::{Connection, Registration};
+pub use server_handle::{ServerHandle, DEFAULT_SHUTDOWN_DEADLINE};
+pub use sharded::{ClusterConfig, ShardedKvsClient};
+pub use transport::Addr;
+pub(crate) use transport::Stream;
 
-use crate::Result;
-use std::net::SocketAddr;
+use crate::thread_pool::ThreadPool;
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use slog::Drain;
+use std::io::{self, Read};
+use std::net::Shutdown;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use server_handle::Connections;
+use transport::Listener;
 
 /// Client interface
 pub trait KvsClient {
-    /// Connect to the remote server
+    /// Connect to the remote server, over TCP or a Unix domain socket
     fn connect<A>(addr: A) -> Result<Self>
     where
         Self: Sized,
-        A: Into<SocketAddr>;
+        A: Into<Addr>;
     /// Send set command
     fn set(&mut self, key: String, value: String) -> Result<()>;
     /// Send get command
     fn get(&mut self, key: String) -> Result<Option<String>>;
     /// Send remove command
     fn remove(&mut self, key: String) -> Result<()>;
+    /// Send increment command
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64>;
+    /// Send append command
+    fn append(&mut self, key: String, suffix: String) -> Result<()>;
+    /// Send get command for many keys in one round trip, values are returned in the order of
+    /// the keys
+    fn multi_get(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>>;
+    /// Send set command for many keys in one round trip, the pairs are set atomically if the
+    /// server's engine supports it
+    fn multi_set(&mut self, pairs: Vec<(String, String)>) -> Result<()>;
+    /// Send remove command for many keys in one round trip, returns the number of keys that
+    /// were removed
+    fn multi_remove(&mut self, keys: Vec<String>) -> Result<u64>;
+    /// Send scan command, returns at most `limit` keys in order, starting with the smallest key
+    /// that is not less than `start`
+    fn scan(&mut self, start: String, limit: usize) -> Result<Vec<String>>;
+}
+
+/// Time that a rejected client is given to accept the "server busy" response and to finish
+/// sending its request
+pub(crate) const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Number of rejected clients that are answered at the same time, the others are closed without
+/// a response
+const MAX_REJECTING: usize = 64;
+
+/// Number of bytes that are read from a rejected client before its connection is closed
+const MAX_REJECT_DRAIN: u64 = 64 * 1024;
+
+/// Logger that is used by servers when the caller does not provide one
+pub(crate) fn default_logger() -> slog::Logger {
+    // TODO: make default log config
+    let decorator = slog_term::TermDecorator::new().build();
+    let drain = slog_term::FullFormat::new(decorator).build().fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    slog::Logger::root(drain, o!())
+}
+
+/// Accepts connections on the given address in the background and handles each of them on the
+/// thread pool, until the returned handle shuts the server down. Connections over the limit of
+/// the configuration are answered with `busy` and closed. `handle` gets the connection, whose
+/// logger carries the address of the client.
+fn serve_with<E, P, H>(
+    engine: E,
+    pool: P,
+    config: &ServerConfig,
+    logger: slog::Logger,
+    addr: Addr,
+    handle: H,
+    busy: fn(&mut Stream) -> io::Result<()>,
+) -> Result<ServerHandle>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + 'static,
+    H: Fn(E, Stream, &Connection) -> Result<()> + Clone + Send + 'static,
+{
+    let flushed = engine.clone();
+    serve_state(
+        engine,
+        pool,
+        config,
+        logger,
+        addr,
+        handle,
+        busy,
+        move || flushed.flush(),
+    )
+}
+
+/// Like `serve_with`, for servers whose connections share any `state` instead of an engine.
+/// `flush` runs once the server has shut down.
+#[allow(clippy::too_many_arguments)]
+fn serve_state<S, P, H, F>(
+    state: S,
+    pool: P,
+    config: &ServerConfig,
+    logger: slog::Logger,
+    addr: Addr,
+    handle: H,
+    busy: fn(&mut Stream) -> io::Result<()>,
+    flush: F,
+) -> Result<ServerHandle>
+where
+    S: Clone + Send + 'static,
+    P: ThreadPool + Send + 'static,
+    H: Fn(S, Stream, &Connection) -> Result<()> + Clone + Send + 'static,
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    let logger = logger.new(o!("addr" => addr.to_string()));
+    info!(logger, "Starting key-value store server");
+
+    let listener = Listener::bind(&addr)?;
+    let local_addr = listener.local_addr()?;
+    let connections = Arc::new(Connections::default());
+    let max_connections = config.get_max_connections();
+    let rejecting = Arc::new(AtomicUsize::new(0));
+    let metrics = config.get_metrics().cloned();
+
+    let accept_thread = {
+        let connections = Arc::clone(&connections);
+        let logger = logger.clone();
+        thread::spawn(move || {
+            loop {
+                let stream = listener.accept();
+                if connections.is_stopping() {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        error!(logger, "Could not accept connection"; "error" => err);
+                        continue;
+                    }
+                };
+
+                let logger = match stream.peer_addr() {
+                    Some(peer_addr) => logger.new(o!( "peer_addr" => peer_addr )),
+                    None => logger.clone(),
+                };
+                let guard = match connections.register(&stream, max_connections) {
+                    Ok(Registration::Accepted(guard)) => guard,
+                    Ok(Registration::Busy) => {
+                        warn!(logger, "Rejecting connection over the connection limit");
+                        // the accepting thread must not be held up by the rejected client
+                        if rejecting.fetch_add(1, Ordering::SeqCst) < MAX_REJECTING {
+                            let rejecting = Arc::clone(&rejecting);
+                            thread::spawn(move || {
+                                let _ = reject(stream, busy);
+                                rejecting.fetch_sub(1, Ordering::SeqCst);
+                            });
+                        } else {
+                            rejecting.fetch_sub(1, Ordering::SeqCst);
+                        }
+                        continue;
+                    }
+                    Ok(Registration::Stopping) => break,
+                    Err(err) => {
+                        error!(logger, "Could not track connection"; "error" => format!("{}", err));
+                        continue;
+                    }
+                };
+                let state = state.clone();
+                let handle = handle.clone();
+                let open = metrics.as_ref().map(|metrics| metrics.open_connection());
+                let connection = Connection::new(logger, Arc::clone(&connections));
+
+                pool.spawn(move || {
+                    let _guard = guard;
+                    let _open = open;
+                    if let Err(err) = handle(state, stream, &connection) {
+                        error!(connection.logger, "Could not handle client"; "error" => format!("{}", err));
+                    }
+                });
+            }
+        })
+    };
+
+    Ok(ServerHandle::new(
+        local_addr,
+        connections,
+        accept_thread,
+        flush,
+        logger,
+    ))
+}
+
+/// Returns `true` if the peer closed the connection or the connection failed, without waiting
+/// for the peer
+pub(crate) fn peer_closed(stream: &Stream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let mut buf = [0u8; 1];
+    let closed = match stream.peek(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(err) => err.kind() != io::ErrorKind::WouldBlock,
+    };
+    stream.set_nonblocking(false).is_err() || closed
+}
+
+/// Answers a client that can not be served and closes the connection
+fn reject(mut stream: Stream, busy: fn(&mut Stream) -> io::Result<()>) -> io::Result<()> {
+    stream.set_write_timeout(Some(REJECT_TIMEOUT))?;
+    busy(&mut stream)?;
+    linger(&mut stream)
+}
+
+/// Stops sending and reads what the client still sends before the connection is closed. Closing
+/// a connection with unread data resets it, and the client would lose the last response.
+pub(crate) fn linger(stream: &mut Stream) -> io::Result<()> {
+    stream.set_read_timeout(Some(REJECT_TIMEOUT))?;
+    stream.shutdown(Shutdown::Write)?;
+    io::copy(&mut stream.take(MAX_REJECT_DRAIN), &mut io::sink())?;
+    Ok(())
+}
+
+/// Different protocols that a server can speak
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// JSON and the binary protocol on the same address, detected for each connection
+    Auto,
+    /// Only JSON messages
+    Json,
+    /// Only the length-prefixed binary protocol
+    Binary,
+    /// The Redis serialization protocol
+    Resp,
+}
+
+impl Protocol {
+    /// Get the string representation of the protocol
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Self::Auto => "auto",
+            Self::Json => "json",
+            Self::Binary => "binary",
+            Self::Resp => "resp",
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Protocol> {
+        let name = s.to_lowercase();
+        match name.as_str() {
+            "auto" => Ok(Self::Auto),
+            "json" => Ok(Self::Json),
+            "binary" => Ok(Self::Binary),
+            "resp" => Ok(Self::Resp),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Could not found protocol named '{}'", name),
+            )),
+        }
+    }
 }
 
 /// Server interface
 pub trait KvsServer {
-    /// Start accepting requests on the given socket address
-    fn serve<A>(&self, addr: A) -> Result<()>
+    /// Start accepting requests on the given TCP address or Unix domain socket in the
+    /// background, the returned handle is used to shut the server down
+    fn serve<A>(self, addr: A) -> Result<ServerHandle>
     where
-        A: Into<SocketAddr>;
+        A: Into<Addr>;
 }
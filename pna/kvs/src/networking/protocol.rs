@@ -0,0 +1,641 @@
+//! Messages that are exchanged between clients and servers, independent of how they are encoded
+
+use crate::engines::{WriteBatch, WriteOp};
+use crate::replication::{Change, Position, ReplicationStatus};
+use crate::{Error, ErrorKind, KvsEngine};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Network request message for KvsEngine command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Set command request
+    Set {
+        /// Set key
+        key: String,
+        /// Set valye
+        value: String,
+    },
+    /// Get command request
+    Get {
+        /// Get key
+        key: String,
+    },
+    /// Remove command request
+    Remove {
+        /// Remove key
+        key: String,
+    },
+    /// Increment command request
+    Increment {
+        /// Increment key
+        key: String,
+        /// Amount that is added to the value
+        delta: i64,
+    },
+    /// Append command request
+    Append {
+        /// Append key
+        key: String,
+        /// String that is appended to the value
+        suffix: String,
+    },
+    /// Get command request for many keys
+    MultiGet {
+        /// Get keys
+        keys: Vec<String>,
+    },
+    /// Set command request for many keys, the pairs are set atomically if the engine supports it
+    MultiSet {
+        /// Set keys and values
+        pairs: Vec<(String, String)>,
+    },
+    /// Remove command request for many keys, keys that do not exist are skipped
+    MultiRemove {
+        /// Remove keys
+        keys: Vec<String>,
+    },
+    /// Scan command request, lists keys in order
+    Scan {
+        /// Smallest key that is listed
+        start: String,
+        /// Maximum number of keys that are listed
+        limit: usize,
+    },
+    /// Turns the connection into a stream of `ReplicationMessage`, it must be sent without an
+    /// envelope on a JSON connection
+    Replicate {
+        /// Position that the replica has applied, the stream starts with a snapshot if the
+        /// changes after it are no longer kept
+        since: Option<Position>,
+    },
+    /// Turns the connection into a stream of `WatchMessage` with the changes of the keys that
+    /// start with `key_or_prefix`, it must be sent without an envelope on a JSON connection
+    Watch {
+        /// Key or prefix of the keys that are watched, an empty prefix watches every key
+        key_or_prefix: String,
+    },
+    /// Promote command request, a replica stops following its primary and accepts writes
+    Promote,
+    /// Replication status command request
+    ReplicationStatus,
+    /// Starts the authentication of the connection, the server answers how the client proves
+    /// that it knows the token of a user
+    Challenge,
+    /// Authenticates the connection as the user whose token the proof was made with, it answers
+    /// the latest challenge of the connection
+    Authenticate {
+        /// The token itself or the HMAC of the challenge, depending on the challenge
+        proof: String,
+    },
+    /// Maintenance command request, only servers that accept admin requests run it
+    Admin {
+        /// Admin token of the server, if it has one
+        token: Option<String>,
+        /// What the server does
+        command: AdminCommand,
+    },
+}
+
+/// Maintenance commands that an admin can run on a server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdminCommand {
+    /// Reclaims the space of overwritten and removed values
+    Compact,
+    /// Writes every completed write to disk
+    Flush,
+    /// Copies the data to a new directory on the server's host, a relative path is relative to
+    /// the working directory of the server
+    Checkpoint {
+        /// Directory that is created for the copy
+        path: String,
+    },
+    /// Returns the statistics of the engine
+    Stats,
+    /// Changes which records the server's log keeps
+    SetLogLevel {
+        /// Name of the least severe level that is kept, such as `info` or `debug`
+        level: String,
+    },
+    /// Lists the connections that the server is serving
+    Clients,
+}
+
+/// An error that happened on the server. The code identifies the `ErrorKind` of the error, so the
+/// client can handle it without parsing the message, which is only meant for humans.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteError {
+    /// Stable number that identifies the kind of the error
+    pub code: u16,
+    /// Description of the error
+    pub message: String,
+}
+
+impl RemoteError {
+    /// Returns the kind of the error, codes that are unknown to this version of the library are
+    /// reported as `ErrorKind::ServerError`
+    pub fn kind(&self) -> ErrorKind {
+        match self.code {
+            1 => ErrorKind::KeyNotFound,
+            2 => ErrorKind::CorruptedLog,
+            3 => ErrorKind::CorruptedIndex,
+            4 => ErrorKind::InvalidValue,
+            5 => ErrorKind::InvalidNetworkMessage,
+            6 => ErrorKind::UnsupportedKvsEngine,
+            7 => ErrorKind::UnsupportedCodec,
+            9 => ErrorKind::Canceled,
+            10 => ErrorKind::Io,
+            11 => ErrorKind::Serialization,
+            12 => ErrorKind::Unsupported,
+            13 => ErrorKind::Internal,
+            14 => ErrorKind::ServerBusy,
+            15 => ErrorKind::ReadOnly,
+            16 => ErrorKind::Unauthenticated,
+            17 => ErrorKind::PermissionDenied,
+            _ => ErrorKind::ServerError,
+        }
+    }
+}
+
+/// Codes must never be reused for a different kind, since clients of older versions rely on them
+fn error_code(kind: ErrorKind) -> u16 {
+    match kind {
+        ErrorKind::KeyNotFound => 1,
+        ErrorKind::CorruptedLog => 2,
+        ErrorKind::CorruptedIndex => 3,
+        ErrorKind::InvalidValue => 4,
+        ErrorKind::InvalidNetworkMessage => 5,
+        ErrorKind::UnsupportedKvsEngine => 6,
+        ErrorKind::UnsupportedCodec => 7,
+        ErrorKind::ServerError => 8,
+        ErrorKind::Canceled => 9,
+        ErrorKind::Io => 10,
+        ErrorKind::Serialization => 11,
+        ErrorKind::Unsupported => 12,
+        ErrorKind::Internal => 13,
+        ErrorKind::ServerBusy => 14,
+        ErrorKind::ReadOnly => 15,
+        ErrorKind::Unauthenticated => 16,
+        ErrorKind::PermissionDenied => 17,
+    }
+}
+
+impl From<&Error> for RemoteError {
+    fn from(err: &Error) -> Self {
+        Self {
+            code: error_code(err.kind()),
+            message: err.message(),
+        }
+    }
+}
+
+impl From<RemoteError> for Error {
+    fn from(err: RemoteError) -> Self {
+        Error::new(err.kind(), err)
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+/// Network request message for KvsEngine set command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SetResponse {
+    /// Set command suceeded
+    Ok,
+    /// Set command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine get command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GetResponse {
+    /// Get command suceeded
+    Ok(Option<String>),
+    /// Get command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine remove command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RemoveResponse {
+    /// Remove command suceeded
+    Ok,
+    /// Remove command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine increment command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum IncrementResponse {
+    /// Increment command suceeded, carrying the new value
+    Ok(i64),
+    /// Increment command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine append command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AppendResponse {
+    /// Append command suceeded
+    Ok,
+    /// Append command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine multi-get command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MultiGetResponse {
+    /// Multi-get command suceeded, carrying the values in the order of the keys
+    Ok(Vec<Option<String>>),
+    /// Multi-get command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine multi-set command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MultiSetResponse {
+    /// Multi-set command suceeded
+    Ok,
+    /// Multi-set command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine multi-remove command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MultiRemoveResponse {
+    /// Multi-remove command suceeded, carrying the number of keys that were removed
+    Ok(u64),
+    /// Multi-remove command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine scan command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ScanResponse {
+    /// Scan command suceeded, carrying the keys in order
+    Ok(Vec<String>),
+    /// Scan command failed
+    Err(RemoteError),
+}
+
+/// Message on a replication stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationMessage {
+    /// Starts a snapshot of the primary's data, it replaces all data of the replica
+    SnapshotStart {
+        /// Position that the replica has applied once the snapshot ends
+        position: Position,
+    },
+    /// Pairs that are part of the snapshot
+    SnapshotChunk {
+        /// Keys and values
+        pairs: Vec<(String, String)>,
+    },
+    /// Ends the snapshot
+    SnapshotEnd,
+    /// A change that was committed on the primary
+    Change(Change),
+    /// Sent when there are no changes, so that the replica knows that the primary is alive
+    Heartbeat {
+        /// Latest position of the primary
+        position: Position,
+    },
+    /// The stream can not continue
+    Err(RemoteError),
+}
+
+/// Message on a watch stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WatchMessage {
+    /// The server watches the keys, every change that is committed afterwards is sent
+    Started,
+    /// Operations of a change that was committed, only the ones on watched keys
+    Change(Vec<WriteOp>),
+    /// Sent when there are no changes, so that the client knows that the server is alive
+    Heartbeat,
+    /// The stream can not continue
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine promote command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PromoteResponse {
+    /// Promote command suceeded
+    Ok,
+    /// Promote command failed
+    Err(RemoteError),
+}
+
+/// Network request message for KvsEngine replication status command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationStatusResponse {
+    /// Replication status command suceeded
+    Ok(ReplicationStatus),
+    /// Replication status command failed
+    Err(RemoteError),
+}
+
+/// How a client proves that it knows the token of a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Challenge {
+    /// The proof is the token
+    Token,
+    /// The proof is the hex-encoded HMAC-SHA256 of the nonce, keyed with the token
+    Hmac(String),
+}
+
+/// Network response message for a challenge request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChallengeResponse {
+    /// Challenge command suceeded
+    Ok(Challenge),
+    /// Challenge command failed
+    Err(RemoteError),
+}
+
+/// Network response message for an authenticate request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AuthenticateResponse {
+    /// Authenticate command suceeded, carrying the name of the user
+    Ok(String),
+    /// Authenticate command failed
+    Err(RemoteError),
+}
+
+/// A connection that a server is serving
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    /// Number that identifies the connection on the server
+    pub id: u64,
+    /// Address of the client, clients of a Unix domain socket usually have none
+    pub peer_addr: Option<String>,
+    /// Number of seconds since the client connected
+    pub connected_secs: u64,
+}
+
+/// Result of an admin command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdminReply {
+    /// The command was run
+    Done,
+    /// Named statistics of the engine
+    Stats(Vec<(String, u64)>),
+    /// Connections of the server
+    Clients(Vec<ClientInfo>),
+}
+
+/// Network response message for an admin request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminResponse {
+    /// Admin command suceeded
+    Ok(AdminReply),
+    /// Admin command failed
+    Err(RemoteError),
+}
+
+/// Any of the response messages, it is encoded exactly like the response that it wraps
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Response {
+    /// Response to a set request
+    Set(SetResponse),
+    /// Response to a get request
+    Get(GetResponse),
+    /// Response to a remove request
+    Remove(RemoveResponse),
+    /// Response to an increment request
+    Increment(IncrementResponse),
+    /// Response to an append request
+    Append(AppendResponse),
+    /// Response to a multi-get request
+    MultiGet(MultiGetResponse),
+    /// Response to a multi-set request
+    MultiSet(MultiSetResponse),
+    /// Response to a multi-remove request
+    MultiRemove(MultiRemoveResponse),
+    /// Response to a scan request
+    Scan(ScanResponse),
+    /// Response to a replicate request that could not be streamed
+    Replicate(ReplicationMessage),
+    /// Response to a watch request that could not be streamed
+    Watch(WatchMessage),
+    /// Response to a promote request
+    Promote(PromoteResponse),
+    /// Response to a replication status request
+    ReplicationStatus(ReplicationStatusResponse),
+    /// Response to a challenge request
+    Challenge(ChallengeResponse),
+    /// Response to an authenticate request
+    Authenticate(AuthenticateResponse),
+    /// Response to an admin request
+    Admin(AdminResponse),
+}
+
+impl Response {
+    /// Returns `true` if the request was answered with an error
+    pub(crate) fn is_err(&self) -> bool {
+        self.error().is_some()
+    }
+
+    /// Returns the error that the request was answered with
+    pub(crate) fn error(&self) -> Option<&RemoteError> {
+        match self {
+            Self::Set(SetResponse::Err(err))
+            | Self::Get(GetResponse::Err(err))
+            | Self::Remove(RemoveResponse::Err(err))
+            | Self::Increment(IncrementResponse::Err(err))
+            | Self::Append(AppendResponse::Err(err))
+            | Self::MultiGet(MultiGetResponse::Err(err))
+            | Self::MultiSet(MultiSetResponse::Err(err))
+            | Self::MultiRemove(MultiRemoveResponse::Err(err))
+            | Self::Scan(ScanResponse::Err(err))
+            | Self::Replicate(ReplicationMessage::Err(err))
+            | Self::Watch(WatchMessage::Err(err))
+            | Self::Promote(PromoteResponse::Err(err))
+            | Self::ReplicationStatus(ReplicationStatusResponse::Err(err))
+            | Self::Challenge(ChallengeResponse::Err(err))
+            | Self::Authenticate(AuthenticateResponse::Err(err))
+            | Self::Admin(AdminResponse::Err(err)) => Some(err),
+            _ => None,
+        }
+    }
+
+    /// Returns the response that answers the request with an error
+    pub(crate) fn err(request: &Request, err: &Error) -> Self {
+        let err = RemoteError::from(err);
+        match request {
+            Request::Set { .. } => Self::Set(SetResponse::Err(err)),
+            Request::Get { .. } => Self::Get(GetResponse::Err(err)),
+            Request::Remove { .. } => Self::Remove(RemoveResponse::Err(err)),
+            Request::Increment { .. } => Self::Increment(IncrementResponse::Err(err)),
+            Request::Append { .. } => Self::Append(AppendResponse::Err(err)),
+            Request::MultiGet { .. } => Self::MultiGet(MultiGetResponse::Err(err)),
+            Request::MultiSet { .. } => Self::MultiSet(MultiSetResponse::Err(err)),
+            Request::MultiRemove { .. } => Self::MultiRemove(MultiRemoveResponse::Err(err)),
+            Request::Scan { .. } => Self::Scan(ScanResponse::Err(err)),
+            Request::Replicate { .. } => Self::Replicate(ReplicationMessage::Err(err)),
+            Request::Watch { .. } => Self::Watch(WatchMessage::Err(err)),
+            Request::Promote => Self::Promote(PromoteResponse::Err(err)),
+            Request::ReplicationStatus => {
+                Self::ReplicationStatus(ReplicationStatusResponse::Err(err))
+            }
+            Request::Challenge => Self::Challenge(ChallengeResponse::Err(err)),
+            Request::Authenticate { .. } => Self::Authenticate(AuthenticateResponse::Err(err)),
+            Request::Admin { .. } => Self::Admin(AdminResponse::Err(err)),
+        }
+    }
+}
+
+/// A message that is tagged with the ID of its request. A response carries the ID of the request
+/// that it answers, so that a client can have many requests in flight on one connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    /// ID that is chosen by the client
+    pub id: u64,
+    /// The wrapped request or response
+    pub body: T,
+}
+
+/// Requests that a server accepts, either tagged with an ID or not
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Incoming {
+    Tagged(Envelope<Request>),
+    Untagged(Request),
+}
+
+/// Runs the request on the engine and returns the response that is sent back to the client
+pub(crate) fn execute<E>(engine: &E, request: Request) -> Response
+where
+    E: KvsEngine,
+{
+    match request {
+        Request::Set { key, value } => Response::Set(match engine.set(key, value) {
+            Ok(_) => SetResponse::Ok,
+            Err(err) => SetResponse::Err(RemoteError::from(&err)),
+        }),
+        Request::Get { key } => Response::Get(match engine.get(key) {
+            Ok(v) => GetResponse::Ok(v),
+            Err(err) => GetResponse::Err(RemoteError::from(&err)),
+        }),
+        Request::Remove { key } => Response::Remove(match engine.remove(key) {
+            Ok(_) => RemoveResponse::Ok,
+            Err(err) => RemoveResponse::Err(RemoteError::from(&err)),
+        }),
+        Request::Increment { key, delta } => {
+            Response::Increment(match engine.increment(key, delta) {
+                Ok(v) => IncrementResponse::Ok(v),
+                Err(err) => IncrementResponse::Err(RemoteError::from(&err)),
+            })
+        }
+        Request::Append { key, suffix } => Response::Append(match engine.append(key, suffix) {
+            Ok(_) => AppendResponse::Ok,
+            Err(err) => AppendResponse::Err(RemoteError::from(&err)),
+        }),
+        Request::MultiGet { keys } => Response::MultiGet(
+            match keys.into_iter().map(|key| engine.get(key)).collect() {
+                Ok(vals) => MultiGetResponse::Ok(vals),
+                Err(err) => MultiGetResponse::Err(RemoteError::from(&err)),
+            },
+        ),
+        Request::MultiSet { pairs } => {
+            let mut batch = WriteBatch::default();
+            for (key, value) in pairs {
+                batch.set(key, value);
+            }
+            Response::MultiSet(match engine.apply_batch(batch) {
+                Ok(_) => MultiSetResponse::Ok,
+                Err(err) => MultiSetResponse::Err(RemoteError::from(&err)),
+            })
+        }
+        Request::MultiRemove { keys } => {
+            let mut removed = 0;
+            let mut result = Ok(());
+            for key in keys {
+                match engine.remove(key) {
+                    Ok(_) => removed += 1,
+                    Err(err) if err.kind() == ErrorKind::KeyNotFound => {}
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                }
+            }
+            Response::MultiRemove(match result {
+                Ok(_) => MultiRemoveResponse::Ok(removed),
+                Err(err) => MultiRemoveResponse::Err(RemoteError::from(&err)),
+            })
+        }
+        Request::Scan { start, limit } => Response::Scan(match engine.scan(start, limit) {
+            Ok(keys) => ScanResponse::Ok(keys),
+            Err(err) => ScanResponse::Err(RemoteError::from(&err)),
+        }),
+        Request::Replicate { .. } => {
+            let err = match engine.replication() {
+                Some(_) => Error::new(
+                    ErrorKind::InvalidNetworkMessage,
+                    "Replication streams must be requested without an ID on a JSON connection",
+                ),
+                None => not_replicated(),
+            };
+            Response::Replicate(ReplicationMessage::Err(RemoteError::from(&err)))
+        }
+        Request::Watch { .. } => {
+            let err = match engine.replication() {
+                Some(_) => Error::new(
+                    ErrorKind::InvalidNetworkMessage,
+                    "Watch streams must be requested without an ID on a JSON connection",
+                ),
+                None => not_replicated(),
+            };
+            Response::Watch(WatchMessage::Err(RemoteError::from(&err)))
+        }
+        Request::Promote => Response::Promote(
+            match engine
+                .replication()
+                .ok_or_else(not_replicated)
+                .and_then(|r| r.promote())
+            {
+                Ok(_) => PromoteResponse::Ok,
+                Err(err) => PromoteResponse::Err(RemoteError::from(&err)),
+            },
+        ),
+        Request::ReplicationStatus => Response::ReplicationStatus(match engine.replication() {
+            Some(replication) => ReplicationStatusResponse::Ok(replication.status()),
+            None => ReplicationStatusResponse::Err(RemoteError::from(&not_replicated())),
+        }),
+        // servers that require authentication answer these before a request gets here
+        request @ Request::Challenge | request @ Request::Authenticate { .. } => {
+            Response::err(&request, &no_auth())
+        }
+        // servers that accept admin requests answer these before a request gets here
+        request @ Request::Admin { .. } => Response::err(&request, &no_admin()),
+    }
+}
+
+pub(crate) fn no_auth() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "Server does not authenticate its clients",
+    )
+}
+
+pub(crate) fn no_admin() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "Server does not accept admin requests",
+    )
+}
+
+pub(crate) fn not_replicated() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "Server does not record its changes for replication",
+    )
+}
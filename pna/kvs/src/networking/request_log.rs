@@ -0,0 +1,234 @@
+use crate::networking::protocol::{GetResponse, MultiGetResponse, ScanResponse};
+use crate::networking::{Request, Response};
+use crate::{Error, ErrorKind, Result};
+use serde::Deserialize;
+use slog::Drain;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How the records of a request log are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line, for log collectors
+    Json,
+    /// Lines like the rest of the server's log, for people
+    Terminal,
+}
+
+impl LogFormat {
+    /// Get the string representation of the format
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Self::Json => "json",
+            Self::Terminal => "terminal",
+        }
+    }
+
+    /// Creates a logger that writes its records to `writer` in this format, on a background
+    /// thread so that requests do not wait for the writes
+    pub fn logger<W>(self, writer: W) -> slog::Logger
+    where
+        W: io::Write + Send + 'static,
+    {
+        let drain = match self {
+            Self::Json => slog_async::Async::new(slog_json::Json::default(writer).fuse()).build(),
+            Self::Terminal => {
+                let decorator = slog_term::PlainDecorator::new(writer);
+                slog_async::Async::new(slog_term::FullFormat::new(decorator).build().fuse()).build()
+            }
+        };
+        slog::Logger::root(drain.fuse(), o!())
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LogFormat> {
+        let name = s.to_lowercase();
+        match name.as_str() {
+            "json" => Ok(Self::Json),
+            "terminal" => Ok(Self::Terminal),
+            _ => Err(Error::new(
+                ErrorKind::InvalidValue,
+                format!("Could not found log format named '{}'", name),
+            )),
+        }
+    }
+}
+
+/// Logs of the requests that JSON connections send. The access log has a record for a sample of
+/// the requests, the slow log has a record for every request that took at least a threshold.
+/// Records carry the key-values of the server's logger, such as the address of the client, and
+/// the operation, the size of its keys and values, its result and its latency in microseconds.
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{JsonKvsServer, LogFormat, RequestLog, ServerConfig};
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, KvsServer, Result};
+/// use std::time::Duration;
+///
+/// fn main() -> Result<()> {
+///     let engine = KvStore::open(std::env::current_dir()?)?;
+///     let pool = SharedQueueThreadPool::new(4)?;
+///     let logger = LogFormat::Json.logger(std::io::stderr());
+///     let log = RequestLog::default()
+///         .access(logger.clone(), 0.01)
+///         .slow(logger, Duration::from_millis(100));
+///     let config = ServerConfig::default().request_log(log);
+///     let server = JsonKvsServer::with_config(engine, pool, config, None);
+///     server.serve(([127, 0, 0, 1], 4000))?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RequestLog {
+    access: Option<(slog::Logger, f64)>,
+    slow: Option<(slog::Logger, Duration)>,
+}
+
+/// What is known about a request before it is answered
+#[derive(Debug)]
+pub(crate) struct Entry {
+    operation: &'static str,
+    key_size: usize,
+    value_size: usize,
+    started: Instant,
+}
+
+impl RequestLog {
+    /// Logs each request with the probability `sample_rate`, 1 logs every request
+    pub fn access(mut self, logger: slog::Logger, sample_rate: f64) -> Self {
+        self.access = Some((logger, sample_rate.clamp(0.0, 1.0)));
+        self
+    }
+
+    /// Logs every request that takes at least `threshold`
+    pub fn slow(mut self, logger: slog::Logger, threshold: Duration) -> Self {
+        self.slow = Some((logger, threshold));
+        self
+    }
+
+    /// Returns the logs of one connection, whose records also carry the key-values of `logger`
+    pub(crate) fn connection(&self, logger: &slog::Logger) -> Self {
+        let child = |log: &slog::Logger| log.new(o!(logger.list().clone()));
+        Self {
+            access: self.access.as_ref().map(|(log, rate)| (child(log), *rate)),
+            slow: self
+                .slow
+                .as_ref()
+                .map(|(log, threshold)| (child(log), *threshold)),
+        }
+    }
+
+    /// Starts timing a request
+    pub(crate) fn start(&self, request: &Request) -> Entry {
+        let (key_size, value_size) = request_sizes(request);
+        Entry {
+            operation: operation(request),
+            key_size,
+            value_size,
+            started: Instant::now(),
+        }
+    }
+
+    /// Logs the request of the entry if it is sampled or slow
+    pub(crate) fn finish(&self, entry: Entry, response: &Response) {
+        let latency = entry.started.elapsed();
+        let access = self
+            .access
+            .as_ref()
+            .filter(|(_, rate)| *rate >= 1.0 || rand::random::<f64>() < *rate);
+        let slow = self
+            .slow
+            .as_ref()
+            .filter(|(_, threshold)| latency >= *threshold);
+        if access.is_none() && slow.is_none() {
+            return;
+        }
+
+        let value_size = entry.value_size + response_size(response);
+        let result = match response.error() {
+            Some(err) => format!("{:?}", err.kind()),
+            None => "Ok".to_owned(),
+        };
+        let latency_us = latency.as_micros() as u64;
+        if let Some((logger, _)) = access {
+            info!(logger, "Request";
+                "op" => entry.operation,
+                "key_size" => entry.key_size,
+                "value_size" => value_size,
+                "result" => &result,
+                "latency_us" => latency_us);
+        }
+        if let Some((logger, _)) = slow {
+            warn!(logger, "Slow request";
+                "op" => entry.operation,
+                "key_size" => entry.key_size,
+                "value_size" => value_size,
+                "result" => &result,
+                "latency_us" => latency_us);
+        }
+    }
+}
+
+fn operation(request: &Request) -> &'static str {
+    match request {
+        Request::Set { .. } => "set",
+        Request::Get { .. } => "get",
+        Request::Remove { .. } => "remove",
+        Request::Increment { .. } => "increment",
+        Request::Append { .. } => "append",
+        Request::MultiGet { .. } => "multi_get",
+        Request::MultiSet { .. } => "multi_set",
+        Request::MultiRemove { .. } => "multi_remove",
+        Request::Scan { .. } => "scan",
+        Request::Replicate { .. } => "replicate",
+        Request::Watch { .. } => "watch",
+        Request::Promote => "promote",
+        Request::ReplicationStatus => "replication_status",
+        Request::Challenge => "challenge",
+        Request::Authenticate { .. } => "authenticate",
+        Request::Admin { .. } => "admin",
+    }
+}
+
+/// Returns the number of bytes of the keys and of the values that the request carries
+fn request_sizes(request: &Request) -> (usize, usize) {
+    let keys = |keys: &[String]| keys.iter().map(String::len).sum();
+    match request {
+        Request::Set { key, value } => (key.len(), value.len()),
+        Request::Append { key, suffix } => (key.len(), suffix.len()),
+        Request::Get { key } | Request::Remove { key } | Request::Increment { key, .. } => {
+            (key.len(), 0)
+        }
+        Request::MultiGet { keys: k } | Request::MultiRemove { keys: k } => (keys(k), 0),
+        Request::MultiSet { pairs } => pairs.iter().fold((0, 0), |(k, v), (key, value)| {
+            (k + key.len(), v + value.len())
+        }),
+        Request::Scan { start, .. } => (start.len(), 0),
+        Request::Watch { key_or_prefix } => (key_or_prefix.len(), 0),
+        Request::Replicate { .. }
+        | Request::Promote
+        | Request::ReplicationStatus
+        | Request::Challenge
+        | Request::Authenticate { .. }
+        | Request::Admin { .. } => (0, 0),
+    }
+}
+
+/// Returns the number of bytes of the values, or the keys of a scan, that the response carries
+fn response_size(response: &Response) -> usize {
+    match response {
+        Response::Get(GetResponse::Ok(Some(value))) => value.len(),
+        Response::MultiGet(MultiGetResponse::Ok(values)) => {
+            values.iter().flatten().map(String::len).sum()
+        }
+        Response::Scan(ScanResponse::Ok(keys)) => keys.iter().map(String::len).sum(),
+        _ => 0,
+    }
+}
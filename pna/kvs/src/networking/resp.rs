@@ -0,0 +1,603 @@
+use crate::engines::{scan_successor, WriteBatch};
+use crate::networking::metrics::Operation;
+use crate::networking::{Addr, KvsServer, ServerConfig, ServerHandle, Stream};
+use crate::thread_pool::ThreadPool;
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Bulk strings larger than this are rejected, it is the same limit that Redis uses
+const MAX_BULK_SIZE: usize = 512 * 1024 * 1024;
+
+/// Commands with more arguments than this are rejected instead of being buffered
+const MAX_ARGS: usize = 1024 * 1024;
+
+/// `SCAN` patterns longer than this are rejected, matching takes time proportional to the size of
+/// the pattern times the size of the key
+const MAX_PATTERN_SIZE: usize = 256;
+
+/// Number of keys that are scanned when `SCAN` is called without `COUNT`
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// Number of unfinished scans whose cursors are remembered, the oldest one is forgotten first
+const MAX_SCAN_CURSORS: usize = 4096;
+
+/// Network server that speaks RESP2, so that `redis-cli` and Redis client libraries can be used
+/// with any `KvsEngine`.
+///
+/// Supported commands are `GET`, `SET`, `DEL`, `EXISTS`, `PING`, `INCR`, `MGET`, `MSET` and
+/// `SCAN`. Commands can be sent either as arrays of bulk strings or inline.
+#[derive(Debug)]
+pub struct RespKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    engine: E,
+    pool: P,
+    config: ServerConfig,
+    logger: slog::Logger,
+    cursors: Arc<Mutex<ScanCursors>>,
+}
+
+impl<E, P> KvsServer for RespKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool + Send + 'static,
+{
+    fn serve<A>(self, addr: A) -> Result<ServerHandle>
+    where
+        A: Into<Addr>,
+    {
+        let cursors = self.cursors;
+        let config = self.config.clone();
+        super::serve_with(
+            self.engine,
+            self.pool,
+            &self.config,
+            self.logger,
+            addr.into(),
+            move |engine, stream, _| handle(engine, stream, &config, &cursors),
+            reply_busy,
+        )
+    }
+}
+
+impl<E, P> RespKvsServer<E, P>
+where
+    E: KvsEngine,
+    P: ThreadPool,
+{
+    /// Create a new RESP server
+    pub fn new(engine: E, pool: P, logger: Option<slog::Logger>) -> Self {
+        Self::with_config(engine, pool, ServerConfig::default(), logger)
+    }
+
+    /// Create a new RESP server that limits its clients according to the given configuration
+    pub fn with_config(
+        engine: E,
+        pool: P,
+        config: ServerConfig,
+        logger: Option<slog::Logger>,
+    ) -> Self {
+        let logger = logger.unwrap_or_else(super::default_logger);
+
+        Self {
+            engine,
+            pool,
+            config,
+            logger,
+            cursors: Arc::new(Mutex::new(ScanCursors::default())),
+        }
+    }
+}
+
+/// Positions of the unfinished scans. A `SCAN` cursor has to be an integer, so the key that the
+/// next call continues from is kept on the server and is shared by every connection.
+#[derive(Debug, Default)]
+struct ScanCursors {
+    next_id: u64,
+    positions: HashMap<u64, String>,
+    order: VecDeque<u64>,
+}
+
+impl ScanCursors {
+    fn insert(&mut self, position: String) -> u64 {
+        // 0 is the cursor that starts and ends a scan
+        self.next_id += 1;
+        let id = self.next_id;
+        self.positions.insert(id, position);
+        self.order.push_back(id);
+        while self.order.len() > MAX_SCAN_CURSORS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.positions.remove(&oldest);
+            }
+        }
+        id
+    }
+
+    fn get(&self, id: u64) -> Option<String> {
+        self.positions.get(&id).cloned()
+    }
+}
+
+/// Values that are sent back to the client
+#[derive(Debug)]
+enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn bulk_string(s: String) -> Self {
+        Self::Bulk(Some(s.into_bytes()))
+    }
+
+    fn write_to<W>(&self, w: &mut W) -> Result<()>
+    where
+        W: Write,
+    {
+        match self {
+            Self::Simple(s) => write!(w, "+{}\r\n", s)?,
+            // error messages must not break the line-based framing
+            Self::Error(s) => write!(w, "-{}\r\n", s.replace(&['\r', '\n'][..], " "))?,
+            Self::Integer(n) => write!(w, ":{}\r\n", n)?,
+            Self::Bulk(None) => w.write_all(b"$-1\r\n")?,
+            Self::Bulk(Some(bytes)) => {
+                write!(w, "${}\r\n", bytes.len())?;
+                w.write_all(bytes)?;
+                w.write_all(b"\r\n")?;
+            }
+            Self::Array(replies) => {
+                write!(w, "*{}\r\n", replies.len())?;
+                for reply in replies {
+                    reply.write_to(w)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Error> for Reply {
+    fn from(err: Error) -> Self {
+        Self::Error(format!("ERR {}", err))
+    }
+}
+
+/// Serves commands from a RESP client until the connection is closed or stays idle for too long
+fn handle<E>(
+    engine: E,
+    stream: Stream,
+    config: &ServerConfig,
+    cursors: &Mutex<ScanCursors>,
+) -> Result<()>
+where
+    E: KvsEngine,
+{
+    config.require_no_auth("RESP")?;
+    config.apply(&stream)?;
+    let mut wstream = BufWriter::new(stream.try_clone()?);
+    let mut rstream = BufReader::new(stream);
+    let max_request_size = config.get_max_request_size();
+
+    while config.wait_for_request(&mut rstream)? {
+        let args = match read_command(&mut rstream, max_request_size) {
+            Ok(Some(args)) => args,
+            Ok(None) => break,
+            Err(err) if err.kind() == ErrorKind::InvalidNetworkMessage => {
+                // the stream can not be resynchronized after a malformed command
+                Reply::Error(format!("ERR Protocol error: {}", err)).write_to(&mut wstream)?;
+                wstream.flush()?;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
+        if args.is_empty() {
+            continue;
+        }
+
+        let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+        let reply = if quit {
+            Reply::Simple("OK")
+        } else {
+            let operation = operation(&args[0]);
+            let started = Instant::now();
+            let reply = execute(&engine, cursors, args).unwrap_or_else(Reply::from);
+            config.record(operation, matches!(reply, Reply::Error(_)), started);
+            reply
+        };
+        reply.write_to(&mut wstream)?;
+
+        // replies to pipelined commands are flushed together
+        if quit || rstream.buffer().is_empty() {
+            wstream.flush()?;
+        }
+        if quit {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the operation that a command is counted as in the metrics
+fn operation(name: &[u8]) -> Option<Operation> {
+    match name.to_ascii_uppercase().as_slice() {
+        b"GET" => Some(Operation::Get),
+        b"SET" => Some(Operation::Set),
+        b"DEL" => Some(Operation::MultiRemove),
+        b"EXISTS" | b"MGET" => Some(Operation::MultiGet),
+        b"INCR" => Some(Operation::Increment),
+        b"MSET" => Some(Operation::MultiSet),
+        b"SCAN" => Some(Operation::Scan),
+        _ => None,
+    }
+}
+
+fn execute<E>(engine: &E, cursors: &Mutex<ScanCursors>, args: Vec<Vec<u8>>) -> Result<Reply>
+where
+    E: KvsEngine,
+{
+    let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+    let args = match args
+        .into_iter()
+        .skip(1)
+        .map(String::from_utf8)
+        .collect::<std::result::Result<Vec<String>, _>>()
+    {
+        Ok(args) => args,
+        Err(_) => {
+            return Ok(Reply::Error(
+                "ERR keys and values must be valid UTF-8".to_owned(),
+            ))
+        }
+    };
+
+    let reply = match (name.as_str(), args.len()) {
+        ("PING", 0) => Reply::Simple("PONG"),
+        ("PING", 1) => Reply::bulk_string(args.into_iter().next().unwrap()),
+        // clients such as `redis-cli` ask for the command table when they start
+        ("COMMAND", _) => Reply::Array(Vec::new()),
+        ("GET", 1) => {
+            let key = args.into_iter().next().unwrap();
+            Reply::Bulk(engine.get(key)?.map(String::into_bytes))
+        }
+        ("SET", 2) => {
+            let mut args = args.into_iter();
+            engine.set(args.next().unwrap(), args.next().unwrap())?;
+            Reply::Simple("OK")
+        }
+        ("SET", n) if n > 2 => Reply::Error("ERR syntax error".to_owned()),
+        ("DEL", n) if n > 0 => {
+            let mut removed = 0;
+            for key in args {
+                match engine.remove(key) {
+                    Ok(()) => removed += 1,
+                    Err(err) if err.kind() == ErrorKind::KeyNotFound => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            Reply::Integer(removed)
+        }
+        ("EXISTS", n) if n > 0 => {
+            let mut found = 0;
+            for key in args {
+                if engine.get(key)?.is_some() {
+                    found += 1;
+                }
+            }
+            Reply::Integer(found)
+        }
+        ("INCR", 1) => {
+            let key = args.into_iter().next().unwrap();
+            match engine.increment(key, 1) {
+                Ok(val) => Reply::Integer(val),
+                Err(err) if err.kind() == ErrorKind::InvalidValue => {
+                    Reply::Error("ERR value is not an integer or out of range".to_owned())
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        ("MGET", n) if n > 0 => {
+            let mut values = Vec::with_capacity(n);
+            for key in args {
+                values.push(Reply::Bulk(engine.get(key)?.map(String::into_bytes)));
+            }
+            Reply::Array(values)
+        }
+        ("MSET", n) if n > 0 && n % 2 == 0 => {
+            let mut batch = WriteBatch::default();
+            let mut args = args.into_iter();
+            while let (Some(key), Some(value)) = (args.next(), args.next()) {
+                batch.set(key, value);
+            }
+            engine.apply_batch(batch)?;
+            Reply::Simple("OK")
+        }
+        ("SCAN", n) if n > 0 => scan(engine, cursors, args)?,
+        ("PING", _)
+        | ("GET", _)
+        | ("SET", _)
+        | ("DEL", _)
+        | ("EXISTS", _)
+        | ("INCR", _)
+        | ("MGET", _)
+        | ("MSET", _)
+        | ("SCAN", _) => Reply::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name.to_lowercase()
+        )),
+        _ => Reply::Error(format!("ERR unknown command '{}'", name.to_lowercase())),
+    };
+    Ok(reply)
+}
+
+/// Runs `SCAN cursor [MATCH pattern] [COUNT count]`. Like in Redis, `COUNT` is the amount of work
+/// done by a call, so fewer keys can be returned when some of them do not match the pattern.
+fn scan<E>(engine: &E, cursors: &Mutex<ScanCursors>, args: Vec<String>) -> Result<Reply>
+where
+    E: KvsEngine,
+{
+    let invalid_cursor = || Reply::Error("ERR invalid cursor".to_owned());
+    let cursor = match args[0].parse::<u64>() {
+        Ok(cursor) => cursor,
+        Err(_) => return Ok(invalid_cursor()),
+    };
+    let start = if cursor == 0 {
+        String::new()
+    } else {
+        match cursors.lock().unwrap().get(cursor) {
+            Some(start) => start,
+            None => return Ok(invalid_cursor()),
+        }
+    };
+
+    let mut pattern = None;
+    let mut count = DEFAULT_SCAN_COUNT;
+    let mut options = args[1..].chunks(2);
+    for option in &mut options {
+        match option {
+            [name, value] if name.eq_ignore_ascii_case("MATCH") => {
+                if value.len() > MAX_PATTERN_SIZE {
+                    return Ok(Reply::Error("ERR pattern is too long".to_owned()));
+                }
+                pattern = Some(value.as_str())
+            }
+            [name, value] if name.eq_ignore_ascii_case("COUNT") => match value.parse() {
+                Ok(n) if n > 0 => count = n,
+                _ => return Ok(Reply::Error("ERR syntax error".to_owned())),
+            },
+            _ => return Ok(Reply::Error("ERR syntax error".to_owned())),
+        }
+    }
+
+    let keys = engine.scan(start, count)?;
+    let next_cursor = if keys.len() < count {
+        0
+    } else {
+        let last = keys.last().expect("scan returned no keys");
+        cursors.lock().unwrap().insert(scan_successor(last))
+    };
+    let keys = keys
+        .into_iter()
+        .filter(|key| pattern.is_none_or(|p| glob_match(p.as_bytes(), key.as_bytes())))
+        .map(Reply::bulk_string)
+        .collect();
+
+    Ok(Reply::Array(vec![
+        Reply::bulk_string(next_cursor.to_string()),
+        Reply::Array(keys),
+    ]))
+}
+
+/// Matches `text` against a glob-style pattern with the same syntax that Redis uses, `*`, `?`,
+/// `[abc]`, `[^abc]`, `[a-z]` and `\` to escape special characters.
+///
+/// Only the last `*` is backtracked to, by letting it match one more byte of the text, so that
+/// patterns with many stars do not take exponential time.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // position in the pattern after the last `*`, and the position in the text it matched up to
+    let mut backtrack = None;
+    while t < text.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            backtrack = Some((p, t));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], text[t]) {
+            p += len;
+            t += 1;
+            continue;
+        }
+        match backtrack {
+            Some((star_p, star_t)) => {
+                p = star_p;
+                t = star_t + 1;
+                backtrack = Some((star_p, t));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Matches a byte against the element at the start of `pattern`, other than `*`. Returns the
+/// length of the element if it matches.
+fn match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    let (&first, rest) = pattern.split_first()?;
+    match first {
+        b'?' => Some(1),
+        b'[' => match rest.iter().position(|&b| b == b']') {
+            Some(end) => {
+                let (class, negated) = match rest[..end].split_first() {
+                    Some((b'^', class)) => (class, true),
+                    _ => (&rest[..end], false),
+                };
+                (class_match(class, c) != negated).then_some(end + 2)
+            }
+            // an unterminated class is matched literally
+            None => (c == b'[').then_some(1),
+        },
+        b'\\' if !rest.is_empty() => (rest[0] == c).then_some(2),
+        _ => (first == c).then_some(1),
+    }
+}
+
+fn class_match(class: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == b'-' {
+            let (lo, hi) = if class[i] <= class[i + 2] {
+                (class[i], class[i + 2])
+            } else {
+                (class[i + 2], class[i])
+            };
+            if (lo..=hi).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+/// Reads the next command, either as an array of bulk strings or as an inline command. Returns
+/// `None` if the connection was closed. Commands larger than `max_size` bytes are rejected.
+fn read_command<R>(r: &mut R, max_size: usize) -> Result<Option<Vec<Vec<u8>>>>
+where
+    R: BufRead,
+{
+    let mut budget = Budget {
+        max_size,
+        remaining: max_size,
+    };
+    let line = match read_line(r, &mut budget)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    if line.first() != Some(&b'*') {
+        let args = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.to_vec())
+            .collect();
+        return Ok(Some(args));
+    }
+
+    // the lengths are only claims of the client, buffers grow as the bytes arrive
+    let nargs = parse_length(&line[1..], MAX_ARGS)?;
+    let mut args = Vec::new();
+    for _ in 0..nargs {
+        let header = read_line(r, &mut budget)?.ok_or_else(unexpected_eof)?;
+        if header.first() != Some(&b'$') {
+            return Err(Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                format!("expected '$', got '{}'", String::from_utf8_lossy(&header)),
+            ));
+        }
+        let len = parse_length(&header[1..], MAX_BULK_SIZE)?;
+        budget.take(len + 2)?;
+        let mut arg = Vec::new();
+        (&mut *r).take(len as u64 + 2).read_to_end(&mut arg)?;
+        if arg.len() < len + 2 {
+            return Err(unexpected_eof());
+        }
+        if !arg.ends_with(b"\r\n") {
+            return Err(Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                "bulk string is not terminated by CRLF",
+            ));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+/// Reads a line without its line terminator, lines are terminated by CRLF but a single LF is
+/// also accepted so that inline commands can be typed into a terminal. The line counts against
+/// the budget of the command.
+fn read_line<R>(r: &mut R, budget: &mut Budget) -> Result<Option<Vec<u8>>>
+where
+    R: BufRead,
+{
+    let mut line = Vec::new();
+    // one byte over the budget tells a line that uses all of it apart from a larger one
+    (&mut *r)
+        .take((budget.remaining as u64).saturating_add(1))
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    budget.take(line.len())?;
+    if line.pop() != Some(b'\n') {
+        return Err(Error::new(
+            ErrorKind::InvalidNetworkMessage,
+            "line is not terminated",
+        ));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_length(bytes: &[u8], max: usize) -> Result<usize> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|&len| len <= max)
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                format!("invalid length '{}'", String::from_utf8_lossy(bytes)),
+            )
+        })
+}
+
+/// Bytes that are left for the rest of a command
+struct Budget {
+    max_size: usize,
+    remaining: usize,
+}
+
+impl Budget {
+    fn take(&mut self, len: usize) -> Result<()> {
+        if len > self.remaining {
+            return Err(Error::new(
+                ErrorKind::InvalidNetworkMessage,
+                format!(
+                    "command exceeds the maximum size of {} bytes",
+                    self.max_size
+                ),
+            ));
+        }
+        self.remaining -= len;
+        Ok(())
+    }
+}
+
+fn unexpected_eof() -> Error {
+    Error::new(
+        ErrorKind::InvalidNetworkMessage,
+        "connection closed in the middle of a command",
+    )
+}
+
+/// Tells a client that the server has too many connections, with the error that Redis sends
+fn reply_busy(stream: &mut Stream) -> io::Result<()> {
+    stream.write_all(b"-ERR max number of clients reached\r\n")
+}
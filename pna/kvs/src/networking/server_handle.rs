@@ -0,0 +1,284 @@
+use crate::networking::protocol::ClientInfo;
+use crate::networking::transport::{self, Addr, Stream};
+use crate::{Error, ErrorKind, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{Shutdown, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Time that in-flight requests are given to finish when `ServerHandle::shutdown` is called
+pub const DEFAULT_SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Number of connections that can stream changes at the same time, they are counted apart from
+/// the connection limit since each of them is served on its own thread
+const MAX_STREAMS: usize = 256;
+
+/// Controls a server that is running in the background. Dropping the handle leaves the server
+/// running.
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::JsonKvsServer;
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, KvsServer, Result};
+///
+/// fn main() -> Result<()> {
+///     let engine = KvStore::open(std::env::current_dir()?)?;
+///     let pool = SharedQueueThreadPool::new(4)?;
+///     let server = JsonKvsServer::new(engine, pool, None);
+///     let handle = server.serve(([127, 0, 0, 1], 4000))?;
+///     // ...
+///     handle.shutdown()
+/// }
+/// ```
+pub struct ServerHandle {
+    local_addr: Addr,
+    connections: Arc<Connections>,
+    accept_thread: JoinHandle<()>,
+    flush: Box<dyn FnOnce() -> Result<()> + Send>,
+    logger: slog::Logger,
+}
+
+impl fmt::Debug for ServerHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerHandle")
+            .field("local_addr", &self.local_addr)
+            .finish()
+    }
+}
+
+impl ServerHandle {
+    pub(crate) fn new<F>(
+        local_addr: Addr,
+        connections: Arc<Connections>,
+        accept_thread: JoinHandle<()>,
+        flush: F,
+        logger: slog::Logger,
+    ) -> Self
+    where
+        F: FnOnce() -> Result<()> + Send + 'static,
+    {
+        Self {
+            local_addr,
+            connections,
+            accept_thread,
+            flush: Box::new(flush),
+            logger,
+        }
+    }
+
+    /// Returns the address that the server is listening on
+    pub fn local_addr(&self) -> Addr {
+        self.local_addr.clone()
+    }
+
+    /// Stops the server and waits up to `DEFAULT_SHUTDOWN_DEADLINE` for in-flight requests
+    pub fn shutdown(self) -> Result<()> {
+        self.shutdown_within(DEFAULT_SHUTDOWN_DEADLINE)
+    }
+
+    /// Stops accepting connections and stops reading new requests from open connections. Requests
+    /// that are being served are given until `deadline` to finish, connections that are still
+    /// open afterwards are closed. The engine is flushed before returning.
+    pub fn shutdown_within(self, deadline: Duration) -> Result<()> {
+        info!(self.logger, "Shutting down key-value store server");
+        self.connections.stop();
+
+        // the accept loop is blocked until the next connection arrives
+        let mut wakeup_addr = self.local_addr.clone();
+        if let Addr::Tcp(addr) = &mut wakeup_addr {
+            if addr.ip().is_unspecified() {
+                match addr {
+                    SocketAddr::V4(_) => addr.set_ip([127, 0, 0, 1].into()),
+                    SocketAddr::V6(_) => addr.set_ip(std::net::Ipv6Addr::LOCALHOST.into()),
+                }
+            }
+        }
+        let _ = Stream::connect(&wakeup_addr);
+        self.accept_thread
+            .join()
+            .map_err(|_| Error::new(ErrorKind::Internal, "Server's accepting thread panicked"))?;
+        transport::unbind(&self.local_addr);
+
+        let remaining = self.connections.wait_idle(deadline);
+        if remaining > 0 {
+            warn!(self.logger, "Closing connections that did not finish in time"; "connections" => remaining);
+            self.connections.close_all();
+        }
+
+        (self.flush)()?;
+        info!(self.logger, "Key-value store server stopped");
+        Ok(())
+    }
+}
+
+/// Connections that are being served, they are tracked so that the server can stop them
+#[derive(Debug, Default)]
+pub(crate) struct Connections {
+    stopping: AtomicBool,
+    streams: Mutex<(u64, HashMap<u64, Tracked>)>,
+    idle: Condvar,
+}
+
+/// A connection that is being served
+#[derive(Debug)]
+struct Tracked {
+    stream: Stream,
+    peer_addr: Option<String>,
+    connected: Instant,
+    streaming: bool,
+}
+
+impl Connections {
+    /// Tracks the connection until the returned guard is dropped, unless the server is stopping
+    /// or already serves `max_connections` connections
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        stream: &Stream,
+        max_connections: Option<usize>,
+    ) -> Result<Registration> {
+        self.track(stream, false, max_connections)
+    }
+
+    fn track(
+        self: &Arc<Self>,
+        stream: &Stream,
+        streaming: bool,
+        max: Option<usize>,
+    ) -> Result<Registration> {
+        let tracked = Tracked {
+            stream: stream.try_clone()?,
+            peer_addr: stream.peer_addr(),
+            connected: Instant::now(),
+            streaming,
+        };
+        let mut streams = self.streams.lock().unwrap();
+        if self.is_stopping() {
+            return Ok(Registration::Stopping);
+        }
+        let (next_id, streams) = &mut *streams;
+        let count = || {
+            streams
+                .values()
+                .filter(|tracked| tracked.streaming == streaming)
+                .count()
+        };
+        if max.is_some_and(|max| count() >= max) {
+            return Ok(Registration::Busy);
+        }
+        let id = *next_id;
+        *next_id += 1;
+        streams.insert(id, tracked);
+        Ok(Registration::Accepted(ConnectionGuard {
+            id,
+            connections: Arc::clone(self),
+        }))
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Lists the connections in the order that they were accepted
+    pub(crate) fn clients(&self) -> Vec<ClientInfo> {
+        let streams = self.streams.lock().unwrap();
+        let mut clients: Vec<ClientInfo> = streams
+            .1
+            .iter()
+            .map(|(id, tracked)| ClientInfo {
+                id: *id,
+                peer_addr: tracked.peer_addr.clone(),
+                connected_secs: tracked.connected.elapsed().as_secs(),
+            })
+            .collect();
+        clients.sort_unstable_by_key(|client| client.id);
+        clients
+    }
+
+    /// Makes every connection see the end of its stream once the request that it is serving
+    /// has been answered
+    fn stop(&self) {
+        let streams = self.streams.lock().unwrap();
+        self.stopping.store(true, Ordering::SeqCst);
+        for tracked in streams.1.values() {
+            let _ = tracked.stream.shutdown(Shutdown::Read);
+        }
+    }
+
+    /// Waits until every connection is closed or the deadline passes, and returns the number of
+    /// connections that are still open
+    fn wait_idle(&self, deadline: Duration) -> usize {
+        let streams = self.streams.lock().unwrap();
+        let (streams, _) = self
+            .idle
+            .wait_timeout_while(streams, deadline, |(_, streams)| !streams.is_empty())
+            .unwrap();
+        streams.1.len()
+    }
+
+    fn close_all(&self) {
+        let streams = self.streams.lock().unwrap();
+        for tracked in streams.1.values() {
+            let _ = tracked.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Outcome of registering a new connection
+#[derive(Debug)]
+pub(crate) enum Registration {
+    Accepted(ConnectionGuard),
+    Busy,
+    Stopping,
+}
+
+/// Removes a connection from the tracked connections when it is dropped
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard {
+    id: u64,
+    connections: Arc<Connections>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut streams = self.connections.streams.lock().unwrap();
+        streams.1.remove(&self.id);
+        if streams.1.is_empty() {
+            self.connections.idle.notify_all();
+        }
+    }
+}
+
+/// The connection that a handler serves, with the server's other connections
+#[derive(Debug)]
+pub(crate) struct Connection {
+    /// Logger that carries the address of the client
+    pub(crate) logger: slog::Logger,
+    connections: Arc<Connections>,
+}
+
+impl Connection {
+    pub(crate) fn new(logger: slog::Logger, connections: Arc<Connections>) -> Self {
+        Self {
+            logger,
+            connections,
+        }
+    }
+
+    /// Tracks the connection as a stream of changes until the returned guard is dropped, unless
+    /// the server is stopping or already serves `MAX_STREAMS` streams. A stream does not count
+    /// against the connection limit, so the guard of the connection can be dropped once its
+    /// stream is served on another thread.
+    pub(crate) fn stream(&self, stream: &Stream) -> Result<Registration> {
+        self.connections.track(stream, true, Some(MAX_STREAMS))
+    }
+
+    /// Lists the connections of the server, this one included
+    pub(crate) fn clients(&self) -> Vec<ClientInfo> {
+        self.connections.clients()
+    }
+}
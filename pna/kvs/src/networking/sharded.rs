@@ -0,0 +1,381 @@
+use crate::engines::scan_successor;
+use crate::networking::{Addr, FailoverConfig, FailoverKvsClient, KvsClient};
+use crate::{Error, ErrorKind, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// Number of points that each node has on the hash ring when the configuration does not say
+const DEFAULT_VIRTUAL_NODES: usize = 160;
+
+/// Number of keys that are looked at at once when rebalancing
+const REBALANCE_PAGE_SIZE: usize = 1000;
+
+/// Nodes of a cluster and how keys are spread over them. It can be read from a TOML file:
+///
+/// ```toml
+/// nodes = ["127.0.0.1:4000", "127.0.0.1:4001", "127.0.0.1:4002"]
+/// virtual_nodes = 160
+/// ```
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::{ClusterConfig, ShardedKvsClient};
+/// use kvs::{KvsClient, Result};
+///
+/// fn main() -> Result<()> {
+///     let config = ClusterConfig::from_file("cluster.toml")?;
+///     let mut client = ShardedKvsClient::connect_with_config(config)?;
+///     client.set("key".to_owned(), "value".to_owned())?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    nodes: Vec<SocketAddr>,
+    #[serde(default = "default_virtual_nodes")]
+    virtual_nodes: usize,
+    #[serde(skip)]
+    failover: FailoverConfig,
+}
+
+fn default_virtual_nodes() -> usize {
+    DEFAULT_VIRTUAL_NODES
+}
+
+impl ClusterConfig {
+    /// Creates a configuration for the servers at the given addresses
+    pub fn new<I>(nodes: I) -> Self
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        Self {
+            nodes: nodes.into_iter().collect(),
+            virtual_nodes: DEFAULT_VIRTUAL_NODES,
+            failover: FailoverConfig::default(),
+        }
+    }
+
+    /// Reads the configuration from a TOML file
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidValue,
+                format!("Invalid cluster config {}: {}", path.display(), err),
+            )
+        })
+    }
+
+    /// Number of points that each node has on the hash ring. More points spread the keys more
+    /// evenly, every client of the cluster must use the same number.
+    pub fn virtual_nodes(mut self, virtual_nodes: usize) -> Self {
+        self.virtual_nodes = virtual_nodes;
+        self
+    }
+
+    /// Options of the connections to each node
+    pub fn failover(mut self, failover: FailoverConfig) -> Self {
+        self.failover = failover;
+        self
+    }
+
+    /// Returns the addresses of the nodes
+    pub fn nodes(&self) -> &[SocketAddr] {
+        &self.nodes
+    }
+}
+
+/// Consistent hash ring, a node owns the keys whose hash falls between the point of another node
+/// and one of its own points. Adding or removing a node only moves the keys of its points.
+#[derive(Debug, Clone)]
+struct HashRing {
+    /// Points of the ring in ascending order, with the index of the node that they belong to
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    fn new(nodes: &[SocketAddr], virtual_nodes: usize) -> Self {
+        let mut points: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, addr)| {
+                (0..virtual_nodes).map(move |i| (hash(format!("{}#{}", addr, i).as_bytes()), index))
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points }
+    }
+
+    /// Returns the index of the node that owns the key
+    fn node(&self, key: &str) -> usize {
+        let hash = hash(key.as_bytes());
+        let i = self.points.partition_point(|&(point, _)| point < hash);
+        self.points[i % self.points.len()].1
+    }
+}
+
+/// 64-bit FNV-1a followed by the finalizer of MurmurHash3, so that similar keys and node names
+/// land far apart. It must never change, since it decides where existing keys are stored.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Network client that spreads keys over several servers with consistent hashing. Each key is
+/// stored on exactly one server, so operations on many keys are split into one request per
+/// server and are only atomic per server.
+///
+/// The connections to each server are kept by a `FailoverKvsClient` and clones share them.
+#[derive(Debug, Clone)]
+pub struct ShardedKvsClient {
+    config: ClusterConfig,
+    ring: HashRing,
+    clients: Vec<FailoverKvsClient>,
+}
+
+impl ShardedKvsClient {
+    /// Connects to every node of the cluster
+    pub fn connect_with_config(config: ClusterConfig) -> Result<Self> {
+        if config.nodes.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidValue,
+                "No node of the cluster is given",
+            ));
+        }
+        if config.virtual_nodes == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidValue,
+                "Each node needs at least one virtual node",
+            ));
+        }
+        let mut seen = HashSet::new();
+        if let Some(addr) = config.nodes.iter().find(|addr| !seen.insert(*addr)) {
+            return Err(Error::new(
+                ErrorKind::InvalidValue,
+                format!("Node {} is listed more than once", addr),
+            ));
+        }
+
+        let clients = config
+            .nodes
+            .iter()
+            .map(|&addr| {
+                FailoverKvsClient::connect_with_config(vec![addr], config.failover.clone())
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            ring: HashRing::new(&config.nodes, config.virtual_nodes),
+            config,
+            clients,
+        })
+    }
+
+    /// Returns the addresses of the nodes of the cluster
+    pub fn nodes(&self) -> &[SocketAddr] {
+        &self.config.nodes
+    }
+
+    /// Returns the address of the node that stores the key
+    pub fn node_of(&self, key: &str) -> SocketAddr {
+        self.config.nodes[self.ring.node(key)]
+    }
+
+    /// Moves every key that is stored on the wrong node to the node that owns it in this
+    /// cluster, and returns the number of keys that were moved. The nodes of this cluster are
+    /// always checked, `previous` lists other nodes that still hold keys, such as the nodes that
+    /// were removed from the cluster.
+    ///
+    /// Clients should use the new configuration before rebalancing starts. A key that already
+    /// exists on its new node was written there by such a client, so it is kept and the old copy
+    /// is removed.
+    pub fn rebalance(&mut self, previous: &[SocketAddr]) -> Result<u64> {
+        let mut moved = 0;
+        for index in 0..self.clients.len() {
+            let mut source = self.clients[index].clone();
+            moved += self.drain_misplaced(&mut source, Some(index))?;
+        }
+        for addr in previous {
+            if self.config.nodes.contains(addr) {
+                continue;
+            }
+            let mut source =
+                FailoverKvsClient::connect_with_config(vec![*addr], self.config.failover.clone())?;
+            moved += self.drain_misplaced(&mut source, None)?;
+        }
+        Ok(moved)
+    }
+
+    /// Moves the keys of one node that are owned by another node, `index` is the index of the
+    /// node in this cluster if it is part of it
+    fn drain_misplaced(
+        &mut self,
+        source: &mut FailoverKvsClient,
+        index: Option<usize>,
+    ) -> Result<u64> {
+        let mut moved = 0;
+        let mut start = String::new();
+        loop {
+            let keys = source.scan(start, REBALANCE_PAGE_SIZE)?;
+            start = match keys.last() {
+                Some(last) => scan_successor(last),
+                None => return Ok(moved),
+            };
+
+            let misplaced: Vec<_> = keys
+                .into_iter()
+                .filter(|key| Some(self.ring.node(key)) != index)
+                .collect();
+            if misplaced.is_empty() {
+                continue;
+            }
+            let vals = source.multi_get(misplaced.clone())?;
+            let mut by_node = vec![Vec::new(); self.clients.len()];
+            for (key, val) in misplaced.iter().zip(vals) {
+                // the key may have been removed since it was listed
+                if let Some(val) = val {
+                    by_node[self.ring.node(key)].push((key.clone(), val));
+                }
+            }
+            for (node, pairs) in by_node.into_iter().enumerate() {
+                if pairs.is_empty() {
+                    continue;
+                }
+                let keys = pairs.iter().map(|(key, _)| key.clone()).collect();
+                let existing = self.clients[node].multi_get(keys)?;
+                let pairs: Vec<_> = pairs
+                    .into_iter()
+                    .zip(existing)
+                    .filter(|(_, existing)| existing.is_none())
+                    .map(|(pair, _)| pair)
+                    .collect();
+                if !pairs.is_empty() {
+                    moved += pairs.len() as u64;
+                    self.clients[node].multi_set(pairs)?;
+                }
+            }
+            source.multi_remove(misplaced)?;
+        }
+    }
+
+    /// Splits the items by the node that owns their key, keeping the position of each item
+    fn split<T, F>(&self, items: Vec<T>, key: F) -> Vec<(Vec<usize>, Vec<T>)>
+    where
+        F: Fn(&T) -> &str,
+    {
+        let mut by_node: Vec<_> = (0..self.clients.len())
+            .map(|_| (Vec::new(), Vec::new()))
+            .collect();
+        for (position, item) in items.into_iter().enumerate() {
+            let (positions, items) = &mut by_node[self.ring.node(key(&item))];
+            positions.push(position);
+            items.push(item);
+        }
+        by_node
+    }
+
+    fn client(&mut self, key: &str) -> &mut FailoverKvsClient {
+        let index = self.ring.node(key);
+        &mut self.clients[index]
+    }
+}
+
+impl KvsClient for ShardedKvsClient {
+    /// Connect to a cluster that only has the server at `addr`, the nodes of a cluster are
+    /// always TCP addresses
+    fn connect<A>(addr: A) -> Result<Self>
+    where
+        A: Into<Addr>,
+    {
+        match addr.into() {
+            Addr::Tcp(addr) => Self::connect_with_config(ClusterConfig::new(vec![addr])),
+            #[allow(unreachable_patterns)]
+            addr => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Nodes of a cluster must be TCP addresses, not {}", addr),
+            )),
+        }
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.client(&key).set(key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.client(&key).get(key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.client(&key).remove(key)
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        self.client(&key).increment(key, delta)
+    }
+
+    fn append(&mut self, key: String, suffix: String) -> Result<()> {
+        self.client(&key).append(key, suffix)
+    }
+
+    fn multi_get(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        let mut vals = vec![None; keys.len()];
+        for (node, (positions, keys)) in self.split(keys, |key| key).into_iter().enumerate() {
+            if keys.is_empty() {
+                continue;
+            }
+            for (position, val) in positions
+                .into_iter()
+                .zip(self.clients[node].multi_get(keys)?)
+            {
+                vals[position] = val;
+            }
+        }
+        Ok(vals)
+    }
+
+    fn multi_set(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        for (node, (_, pairs)) in self.split(pairs, |(key, _)| key).into_iter().enumerate() {
+            if !pairs.is_empty() {
+                self.clients[node].multi_set(pairs)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn multi_remove(&mut self, keys: Vec<String>) -> Result<u64> {
+        let mut removed = 0;
+        for (node, (_, keys)) in self.split(keys, |key| key).into_iter().enumerate() {
+            if !keys.is_empty() {
+                removed += self.clients[node].multi_remove(keys)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn scan(&mut self, start: String, limit: usize) -> Result<Vec<String>> {
+        // the first keys of the cluster are among the first keys of each node
+        let mut keys = Vec::new();
+        for client in &mut self.clients {
+            keys.extend(client.scan(start.clone(), limit)?);
+        }
+        keys.sort_unstable();
+        keys.truncate(limit);
+        Ok(keys)
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{Error, ErrorKind, Result};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Prefix that tells the address of a Unix domain socket apart from a TCP address
+const UNIX_PREFIX: &str = "unix:";
+
+/// Address of a server, either a TCP socket address or the path of a Unix domain socket. Its
+/// string form is `127.0.0.1:4000` or `unix:/path/to.sock`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Addr {
+    /// TCP socket address
+    Tcp(SocketAddr),
+    /// Path of a Unix domain socket, for clients on the same host
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+impl FromStr for Addr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix(UNIX_PREFIX) {
+            #[cfg(unix)]
+            Some(path) if !path.is_empty() => Ok(Self::Unix(PathBuf::from(path))),
+            #[cfg(unix)]
+            Some(_) => Err(Error::new(
+                ErrorKind::InvalidValue,
+                "Address of a Unix domain socket needs a path",
+            )),
+            #[cfg(not(unix))]
+            Some(_) => Err(Error::new(
+                ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            )),
+            None => s.parse().map(Self::Tcp).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidValue,
+                    format!("Invalid address '{}', expected ip:port or unix:path", s),
+                )
+            }),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Addr {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<SocketAddr> for Addr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl From<SocketAddrV4> for Addr {
+    fn from(addr: SocketAddrV4) -> Self {
+        Self::Tcp(addr.into())
+    }
+}
+
+impl From<SocketAddrV6> for Addr {
+    fn from(addr: SocketAddrV6) -> Self {
+        Self::Tcp(addr.into())
+    }
+}
+
+impl<I> From<(I, u16)> for Addr
+where
+    I: Into<IpAddr>,
+{
+    fn from(addr: (I, u16)) -> Self {
+        Self::Tcp(addr.into())
+    }
+}
+
+impl PartialEq<SocketAddr> for Addr {
+    fn eq(&self, other: &SocketAddr) -> bool {
+        matches!(self, Self::Tcp(addr) if addr == other)
+    }
+}
+
+/// A connection over either transport
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub(crate) fn connect(addr: &Addr) -> io::Result<Self> {
+        match addr {
+            Addr::Tcp(addr) => TcpStream::connect(addr).map(Self::Tcp),
+            #[cfg(unix)]
+            Addr::Unix(path) => UnixStream::connect(path).map(Self::Unix),
+        }
+    }
+
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+        }
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    /// Reads without removing the data from the stream
+    pub(crate) fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.peek(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => peek_unix(stream, buf),
+        }
+    }
+
+    /// Returns the address of the peer for logging, clients of a Unix domain socket usually
+    /// have none
+    pub(crate) fn peer_addr(&self) -> Option<String> {
+        match self {
+            Self::Tcp(stream) => stream.peer_addr().ok().map(|addr| addr.to_string()),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream
+                .peer_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string())),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+// `UnixStream::peek` is not stable yet
+#[cfg(unix)]
+fn peek_unix(stream: &UnixStream, buf: &mut [u8]) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    let read = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            libc::MSG_PEEK,
+        )
+    };
+    if read < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(read as usize)
+    }
+}
+
+/// A listener on either transport
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listens on `addr`. The socket file of a Unix domain socket that no server listens on
+    /// anymore, which is left behind when a server is killed, is replaced.
+    pub(crate) fn bind(addr: &Addr) -> io::Result<Self> {
+        match addr {
+            Addr::Tcp(addr) => TcpListener::bind(addr).map(Self::Tcp),
+            #[cfg(unix)]
+            Addr::Unix(path) => match UnixListener::bind(path) {
+                Err(err) if err.kind() == io::ErrorKind::AddrInUse && is_stale_socket(path) => {
+                    std::fs::remove_file(path)?;
+                    UnixListener::bind(path).map(Self::Unix)
+                }
+                listener => listener.map(Self::Unix),
+            },
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<Addr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().map(Addr::Tcp),
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let addr = listener.local_addr()?;
+                let path = addr
+                    .as_pathname()
+                    .ok_or_else(|| io::Error::other("Unix domain socket has no path"))?;
+                Ok(Addr::Unix(path.to_owned()))
+            }
+        }
+    }
+
+    pub(crate) fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Self::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Self::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+}
+
+/// Returns `true` if the path is a socket file that nothing listens on
+#[cfg(unix)]
+fn is_stale_socket(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    let is_socket = std::fs::symlink_metadata(path)
+        .map(|metadata| metadata.file_type().is_socket())
+        .unwrap_or(false);
+    is_socket && UnixStream::connect(path).is_err()
+}
+
+/// Removes the socket file of a server that stopped listening
+pub(crate) fn unbind(addr: &Addr) {
+    #[cfg(unix)]
+    if let Addr::Unix(path) = addr {
+        let _ = std::fs::remove_file(path);
+    }
+    #[cfg(not(unix))]
+    let _ = addr;
+}
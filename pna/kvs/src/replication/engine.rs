@@ -0,0 +1,157 @@
+use crate::engines::{WriteBatch, WriteOp};
+use crate::replication::{follower, Replication};
+use crate::{KvsEngine, Result};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Engine that records its writes so that replicas can follow it, or that follows a primary.
+///
+/// Writes are recorded by their effect, an increment is recorded as setting the new value, so a
+/// replica ends up with the same data no matter which engine it uses. Recording is engine
+/// independent and the feed is only kept in memory, so a replica that reconnects after the
+/// primary restarted copies a new snapshot.
+///
+/// # Usages
+///
+/// ```no_run
+/// use kvs::networking::JsonKvsServer;
+/// use kvs::replication::ReplicatedKvsEngine;
+/// use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+/// use kvs::{KvStore, KvsServer, Result};
+///
+/// fn main() -> Result<()> {
+///     let engine = KvStore::open(std::env::current_dir()?)?;
+///     let engine = ReplicatedKvsEngine::replica_of(engine, ([127, 0, 0, 1], 4000).into(), None);
+///     let pool = SharedQueueThreadPool::new(4)?;
+///     JsonKvsServer::new(engine, pool, None).serve(([127, 0, 0, 1], 4001))?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplicatedKvsEngine<E>
+where
+    E: KvsEngine,
+{
+    engine: E,
+    replication: Arc<Replication>,
+}
+
+impl<E> ReplicatedKvsEngine<E>
+where
+    E: KvsEngine,
+{
+    /// Wraps an engine that accepts writes and streams them to replicas
+    pub fn primary(engine: E) -> Self {
+        Self {
+            engine,
+            replication: Arc::new(Replication::new(None)),
+        }
+    }
+
+    /// Wraps an engine that follows the primary at `addr` in the background, until it is promoted
+    /// or every clone of the engine is dropped
+    pub fn replica_of(engine: E, addr: SocketAddr, logger: Option<slog::Logger>) -> Self {
+        Self::replica_of_with_token(engine, addr, None, logger)
+    }
+
+    /// Wraps an engine that follows the primary at `addr` like `replica_of`, the connection to
+    /// the primary authenticates with `token` if one is given. The token's user must be able to
+    /// read every key.
+    pub fn replica_of_with_token(
+        engine: E,
+        addr: SocketAddr,
+        token: Option<String>,
+        logger: Option<slog::Logger>,
+    ) -> Self {
+        let logger = logger.unwrap_or_else(crate::networking::default_logger);
+        let replication = Arc::new(Replication::new(Some(addr)));
+        follower::spawn(
+            engine.clone(),
+            Arc::downgrade(&replication),
+            addr,
+            token,
+            logger.new(o!("primary" => addr.to_string())),
+        );
+        Self {
+            engine,
+            replication,
+        }
+    }
+}
+
+impl<E> KvsEngine for ReplicatedKvsEngine<E>
+where
+    E: KvsEngine,
+{
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.replication.write(|| {
+            self.engine.set(key.clone(), value.clone())?;
+            Ok(vec![WriteOp::Set { key, value }])
+        })
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.engine.get(key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.replication.write(|| {
+            self.engine.remove(key.clone())?;
+            Ok(vec![WriteOp::Remove { key }])
+        })
+    }
+
+    fn increment(&self, key: String, delta: i64) -> Result<i64> {
+        let mut result = 0;
+        self.replication.write(|| {
+            result = self.engine.increment(key.clone(), delta)?;
+            Ok(vec![WriteOp::Set {
+                key,
+                value: result.to_string(),
+            }])
+        })?;
+        Ok(result)
+    }
+
+    fn append(&self, key: String, suffix: String) -> Result<()> {
+        self.replication.write(|| {
+            self.engine.append(key.clone(), suffix)?;
+            // no other write can happen in between
+            let value = self.engine.get(key.clone())?.unwrap_or_default();
+            Ok(vec![WriteOp::Set { key, value }])
+        })
+    }
+
+    fn apply_batch(&self, batch: WriteBatch) -> Result<()> {
+        self.replication.write(|| {
+            let ops = batch.ops().to_vec();
+            self.engine.apply_batch(batch)?;
+            Ok(ops)
+        })
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.engine.flush()
+    }
+
+    fn scan(&self, start: String, limit: usize) -> Result<Vec<String>> {
+        self.engine.scan(start, limit)
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.engine.compact()
+    }
+
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        self.engine.checkpoint(path)
+    }
+
+    fn statistics(&self) -> Result<Vec<(String, u64)>> {
+        self.engine.statistics()
+    }
+
+    fn replication(&self) -> Option<&Replication> {
+        Some(&self.replication)
+    }
+}
@@ -0,0 +1,169 @@
+use crate::engines::WriteOp;
+use crate::replication::{Change, Position};
+use crate::{Error, ErrorKind, Result};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Maximum number of changes that are kept for replicas that reconnect
+const MAX_RETAINED_CHANGES: usize = 100_000;
+
+/// Maximum total size in bytes of the keys and values of the changes that are kept
+const MAX_RETAINED_BYTES: usize = 64 * 1024 * 1024;
+
+/// Recent changes of an engine, in the order that they were committed
+#[derive(Debug)]
+pub(crate) struct ChangeFeed {
+    epoch: u64,
+    /// Held while a write is applied to the engine, so that changes are recorded in the order
+    /// that they took effect
+    writer: Mutex<()>,
+    state: Mutex<FeedState>,
+    published: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct FeedState {
+    /// Sequence number of the latest change
+    seq: u64,
+    changes: VecDeque<Arc<Change>>,
+    bytes: usize,
+}
+
+impl FeedState {
+    /// Sequence number of the oldest change that is kept
+    fn first_seq(&self) -> u64 {
+        self.seq + 1 - self.changes.len() as u64
+    }
+}
+
+impl ChangeFeed {
+    pub(crate) fn new() -> Self {
+        // the epoch only has to differ from the epochs of earlier runs
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default()
+            ^ u64::from(std::process::id()) << 32;
+        Self {
+            epoch,
+            writer: Mutex::new(()),
+            state: Mutex::new(FeedState::default()),
+            published: Condvar::new(),
+        }
+    }
+
+    /// Returns the position of the latest change
+    pub(crate) fn latest(&self) -> Position {
+        Position {
+            epoch: self.epoch,
+            seq: self.state.lock().unwrap().seq,
+        }
+    }
+
+    /// Runs a write and records the operations that it returns as one change
+    pub(crate) fn publish<F>(&self, write: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<Vec<WriteOp>>,
+    {
+        let _writer = self.writer.lock().unwrap();
+        let ops = write()?;
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.seq += 1;
+        let change = Change {
+            seq: state.seq,
+            ops,
+        };
+        state.bytes += change_size(&change);
+        state.changes.push_back(Arc::new(change));
+        while state.changes.len() > MAX_RETAINED_CHANGES
+            || (state.bytes > MAX_RETAINED_BYTES && state.changes.len() > 1)
+        {
+            if let Some(oldest) = state.changes.pop_front() {
+                state.bytes -= change_size(&oldest);
+            }
+        }
+        self.published.notify_all();
+        Ok(())
+    }
+
+    /// Returns a subscription to the changes after `position`, if they are still kept
+    pub(crate) fn resume(&self, position: Position) -> Option<Subscription<'_>> {
+        let state = self.state.lock().unwrap();
+        if position.epoch != self.epoch
+            || position.seq > state.seq
+            || position.seq + 1 < state.first_seq()
+        {
+            return None;
+        }
+        Some(Subscription {
+            feed: self,
+            next_seq: position.seq + 1,
+        })
+    }
+
+    /// Returns the latest position and a subscription to the changes after it
+    pub(crate) fn subscribe(&self) -> (Position, Subscription<'_>) {
+        let seq = self.state.lock().unwrap().seq;
+        let subscription = Subscription {
+            feed: self,
+            next_seq: seq + 1,
+        };
+        (
+            Position {
+                epoch: self.epoch,
+                seq,
+            },
+            subscription,
+        )
+    }
+}
+
+/// Reads the changes of a feed in order
+#[derive(Debug)]
+pub(crate) struct Subscription<'a> {
+    feed: &'a ChangeFeed,
+    next_seq: u64,
+}
+
+impl<'a> Subscription<'a> {
+    /// Waits up to `timeout` for the next change. Fails if the change is no longer kept because
+    /// the subscriber fell too far behind.
+    pub(crate) fn next(&mut self, timeout: Duration) -> Result<Option<Arc<Change>>> {
+        let state = self.feed.state.lock().unwrap();
+        let (state, _) = self
+            .feed
+            .published
+            .wait_timeout_while(state, timeout, |state| state.seq < self.next_seq)
+            .unwrap();
+        if state.seq < self.next_seq {
+            return Ok(None);
+        }
+
+        let first_seq = state.first_seq();
+        if self.next_seq < first_seq {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Subscriber fell behind the changes that are kept",
+            ));
+        }
+        let change = Arc::clone(&state.changes[(self.next_seq - first_seq) as usize]);
+        self.next_seq += 1;
+        Ok(Some(change))
+    }
+}
+
+fn change_size(change: &Change) -> usize {
+    change
+        .ops
+        .iter()
+        .map(|op| match op {
+            WriteOp::Set { key, value } => key.len() + value.len(),
+            WriteOp::Remove { key } => key.len(),
+        })
+        .sum()
+}
@@ -0,0 +1,213 @@
+use crate::engines::{scan_successor, WriteBatch, WriteOp};
+use crate::networking::{self, ReplicationMessage, Request};
+use crate::replication::primary::HEARTBEAT_INTERVAL;
+use crate::replication::{Position, Replication};
+use crate::{Error, ErrorKind, KvsEngine, Result};
+use serde::Deserialize;
+use serde_json::Deserializer;
+use std::collections::{BTreeSet, HashSet};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// Time that is waited before reconnecting to the primary
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Number of missed heartbeats after which the primary is considered gone
+const MISSED_HEARTBEATS: u32 = 5;
+
+/// Number of keys that are listed at once when a snapshot starts
+const SCAN_PAGE_SIZE: usize = 1000;
+
+/// Follows the primary in the background until the replica is promoted or dropped
+pub(super) fn spawn<E>(
+    engine: E,
+    replication: Weak<Replication>,
+    primary: SocketAddr,
+    token: Option<String>,
+    logger: slog::Logger,
+) where
+    E: KvsEngine,
+{
+    thread::spawn(move || loop {
+        match replication.upgrade() {
+            Some(replication) if replication.is_replica() => {}
+            _ => break,
+        }
+        info!(logger, "Connecting to primary");
+        match follow(&engine, &replication, primary, token.as_deref()) {
+            Ok(()) => info!(logger, "Stopped following primary"),
+            Err(err) => warn!(logger, "Lost connection to primary"; "error" => format!("{}", err)),
+        }
+        if let Some(replication) = replication.upgrade() {
+            let mut follower = replication.follower.lock().unwrap();
+            follower.stream = None;
+        }
+        thread::sleep(RECONNECT_DELAY);
+    });
+}
+
+/// Applies the stream of the primary until the connection fails, returns `Ok` if the replica was
+/// promoted or dropped
+fn follow<E>(
+    engine: &E,
+    replication: &Weak<Replication>,
+    primary: SocketAddr,
+    token: Option<&str>,
+) -> Result<()>
+where
+    E: KvsEngine,
+{
+    let stream = TcpStream::connect(primary)?;
+    stream.set_read_timeout(Some(HEARTBEAT_INTERVAL * MISSED_HEARTBEATS))?;
+    let since = {
+        let replication = match replication.upgrade() {
+            Some(replication) => replication,
+            None => return Ok(()),
+        };
+        let mut follower = replication.follower.lock().unwrap();
+        if !replication.is_replica() {
+            return Ok(());
+        }
+        // promoting shuts the stream down
+        follower.stream = Some(stream.try_clone()?);
+        follower.applied
+    };
+
+    let mut wstream = BufWriter::new(stream.try_clone()?);
+    let mut rstream = Deserializer::from_reader(BufReader::new(stream));
+    if let Some(token) = token {
+        networking::authenticate(&mut rstream, &mut wstream, token)?;
+    }
+    serde_json::to_writer(&mut wstream, &Request::Replicate { since })?;
+    wstream.flush()?;
+
+    let mut snapshot: Option<(Position, BTreeSet<String>)> = None;
+    let mut epoch = since.map(|since| since.epoch);
+    loop {
+        let message = ReplicationMessage::deserialize(&mut rstream)?;
+        let replication = match replication.upgrade() {
+            Some(replication) if replication.is_replica() => replication,
+            _ => return Ok(()),
+        };
+
+        match message {
+            ReplicationMessage::SnapshotStart { position } => {
+                // keys that are not part of the snapshot are removed when it ends
+                snapshot = Some((position, list_keys(engine)?));
+                epoch = Some(position.epoch);
+                let mut follower = replication.follower.lock().unwrap();
+                follower.applied = None;
+                follower.primary_seq = Some(position.seq);
+            }
+            ReplicationMessage::SnapshotChunk { pairs } => {
+                let (_, stale) = snapshot
+                    .as_mut()
+                    .ok_or_else(|| unexpected("SnapshotChunk"))?;
+                let ops = pairs
+                    .into_iter()
+                    .map(|(key, value)| {
+                        stale.remove(&key);
+                        WriteOp::Set { key, value }
+                    })
+                    .collect();
+                apply(engine, &replication, ops)?;
+            }
+            ReplicationMessage::SnapshotEnd => {
+                let (position, stale) = snapshot.take().ok_or_else(|| unexpected("SnapshotEnd"))?;
+                let ops = stale
+                    .into_iter()
+                    .map(|key| WriteOp::Remove { key })
+                    .collect();
+                apply(engine, &replication, ops)?;
+                set_position(&replication, Some(position), None);
+            }
+            ReplicationMessage::Change(change) => {
+                let epoch = epoch.ok_or_else(|| unexpected("Change"))?;
+                if snapshot.is_some() {
+                    return Err(unexpected("Change"));
+                }
+                apply(engine, &replication, change.ops)?;
+                let position = Position {
+                    epoch,
+                    seq: change.seq,
+                };
+                set_position(&replication, Some(position), Some(change.seq));
+            }
+            ReplicationMessage::Heartbeat { position } => {
+                set_position(&replication, None, Some(position.seq));
+            }
+            ReplicationMessage::Err(err) => return Err(Error::from(err)),
+        }
+    }
+}
+
+/// Applies the operations of the primary as one batch and records them in the replica's own
+/// feed, so that the replica can be followed once it is promoted
+fn apply<E>(engine: &E, replication: &Arc<Replication>, ops: Vec<WriteOp>) -> Result<()>
+where
+    E: KvsEngine,
+{
+    replication.feed.publish(|| {
+        let mut batch = WriteBatch::default();
+        let mut applied = Vec::with_capacity(ops.len());
+        let mut set_keys = HashSet::new();
+        for op in ops {
+            match &op {
+                WriteOp::Set { key, value } => {
+                    set_keys.insert(key.clone());
+                    batch.set(key.clone(), value.clone());
+                }
+                WriteOp::Remove { key } => {
+                    // a change can be applied again after a snapshot that already contains it
+                    if !set_keys.contains(key) && engine.get(key.clone())?.is_none() {
+                        continue;
+                    }
+                    batch.remove(key.clone());
+                }
+            }
+            applied.push(op);
+        }
+        if !batch.is_empty() {
+            engine.apply_batch(batch)?;
+        }
+        Ok(applied)
+    })
+}
+
+fn set_position(replication: &Replication, applied: Option<Position>, primary_seq: Option<u64>) {
+    let mut follower = replication.follower.lock().unwrap();
+    if applied.is_some() {
+        follower.applied = applied;
+    }
+    // changes can arrive after a heartbeat that announced them
+    if primary_seq > follower.primary_seq {
+        follower.primary_seq = primary_seq;
+    }
+}
+
+/// Lists every key of the replica, the set holds the whole keyspace while a snapshot is copied
+fn list_keys<E>(engine: &E) -> Result<BTreeSet<String>>
+where
+    E: KvsEngine,
+{
+    let mut keys = BTreeSet::new();
+    let mut start = String::new();
+    loop {
+        let page = engine.scan(start, SCAN_PAGE_SIZE)?;
+        match page.last() {
+            Some(last) => start = scan_successor(last),
+            None => return Ok(keys),
+        }
+        keys.extend(page);
+    }
+}
+
+fn unexpected(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidNetworkMessage,
+        format!("Primary sent {} out of order", message),
+    )
+}
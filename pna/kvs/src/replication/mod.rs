@@ -0,0 +1,165 @@
+//! Primary-backup replication between key-value store servers.
+//!
+//! A primary records every committed write in an in-memory change feed. A replica connects to
+//! the primary, copies a snapshot of its data and then applies the changes that are streamed from
+//! the feed. A replica only serves reads until it is promoted. Clients can watch keys through the
+//! same feed.
+//!
+//! The feed is not read from the log files of `KvStore`, which would let a replica resume from
+//! the `(gen, pos)` of the primary's active log, so that both sides can use any engine. The
+//! price is that the feed does not survive a restart of the primary: it starts a new epoch, and
+//! every replica copies a full snapshot again when it reconnects. While a snapshot is copied,
+//! the replica keeps the keys that it had before in memory, to remove the ones that the primary
+//! no longer has once the snapshot ends.
+mod engine;
+mod feed;
+mod follower;
+mod primary;
+mod watch;
+
+pub use self::engine::ReplicatedKvsEngine;
+
+pub(crate) use self::feed::ChangeFeed;
+pub(crate) use self::primary::serve_replica;
+pub(crate) use self::watch::serve_watcher;
+
+use crate::engines::WriteOp;
+use crate::{Error, ErrorKind, Result};
+use serde::{Deserialize, Serialize};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Position in the change feed of a primary. The epoch identifies the feed, it changes whenever
+/// the primary restarts, since the feed is only kept in memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    /// Identifier of the feed
+    pub epoch: u64,
+    /// Sequence number of the last change, `0` before the first change
+    pub seq: u64,
+}
+
+/// Writes that were committed together on the primary
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Change {
+    /// Sequence number of the change, it increases by one for every change
+    pub seq: u64,
+    /// Operations of the change, written as their effect so that they can be applied again
+    pub ops: Vec<WriteOp>,
+}
+
+/// Whether a server accepts writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    /// Accepts writes and streams them to its replicas
+    Primary,
+    /// Applies the changes of a primary and only serves reads
+    Replica,
+}
+
+/// Replication state of a server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicationStatus {
+    /// Role of the server
+    pub role: Role,
+    /// Address of the primary that a replica follows
+    pub primary: Option<SocketAddr>,
+    /// Whether a replica is connected to its primary
+    pub connected: bool,
+    /// Latest position of a primary, or the position in the primary's feed that a replica has
+    /// applied
+    pub position: Option<Position>,
+    /// Number of changes that a replica has not applied yet, as of the last message of its
+    /// primary
+    pub lag: Option<u64>,
+}
+
+/// Change feed and role of a server, it is shared by every clone of a `ReplicatedKvsEngine`.
+/// The feed is only kept in memory, see the module documentation for what that costs replicas
+/// when the primary restarts.
+#[derive(Debug)]
+pub struct Replication {
+    feed: ChangeFeed,
+    read_only: AtomicBool,
+    follower: Mutex<FollowerState>,
+}
+
+/// What a replica knows about its primary
+#[derive(Debug, Default)]
+struct FollowerState {
+    primary: Option<SocketAddr>,
+    stream: Option<TcpStream>,
+    applied: Option<Position>,
+    primary_seq: Option<u64>,
+}
+
+impl Replication {
+    fn new(primary: Option<SocketAddr>) -> Self {
+        Self {
+            feed: ChangeFeed::new(),
+            read_only: AtomicBool::new(primary.is_some()),
+            follower: Mutex::new(FollowerState {
+                primary,
+                ..FollowerState::default()
+            }),
+        }
+    }
+
+    /// Returns `true` if the server is a replica
+    pub fn is_replica(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+
+    /// Stops following the primary and starts accepting writes. Promoting a primary does nothing.
+    pub fn promote(&self) -> Result<()> {
+        let mut follower = self.follower.lock().unwrap();
+        self.read_only.store(false, Ordering::SeqCst);
+        follower.primary = None;
+        follower.primary_seq = None;
+        if let Some(stream) = follower.stream.take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        Ok(())
+    }
+
+    /// Returns the role of the server and how far a replica is behind its primary
+    pub fn status(&self) -> ReplicationStatus {
+        let follower = self.follower.lock().unwrap();
+        if !self.is_replica() {
+            return ReplicationStatus {
+                role: Role::Primary,
+                primary: None,
+                connected: false,
+                position: Some(self.feed.latest()),
+                lag: None,
+            };
+        }
+
+        let lag = match (follower.applied, follower.primary_seq) {
+            (Some(applied), Some(primary_seq)) => Some(primary_seq.saturating_sub(applied.seq)),
+            _ => None,
+        };
+        ReplicationStatus {
+            role: Role::Replica,
+            primary: follower.primary,
+            connected: follower.stream.is_some(),
+            position: follower.applied,
+            lag,
+        }
+    }
+
+    /// Runs a write of a client and records its operations, replicas refuse every write
+    fn write<F>(&self, write: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<Vec<WriteOp>>,
+    {
+        if self.is_replica() {
+            return Err(Error::new(
+                ErrorKind::ReadOnly,
+                "Replica does not accept writes until it is promoted",
+            ));
+        }
+        self.feed.publish(write)
+    }
+}
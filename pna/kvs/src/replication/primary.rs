@@ -0,0 +1,108 @@
+use crate::engines::scan_successor;
+use crate::networking::{peer_closed, RemoteError, ReplicationMessage, Stream};
+use crate::replication::{Position, Replication};
+use crate::{KvsEngine, Result};
+use std::io::Write;
+use std::time::Duration;
+
+/// Time without changes after which the primary sends a heartbeat
+pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of pairs in a message of a snapshot
+const SNAPSHOT_CHUNK_SIZE: usize = 1000;
+
+/// Number of changes that are sent before checking whether the replica is still connected
+const CHANGES_PER_CHECK: usize = 256;
+
+/// Streams the changes of the engine to a replica until the replica disconnects or the server
+/// stops reading from the connection. The stream starts with a snapshot unless the changes after
+/// `since` are still kept.
+pub(crate) fn serve_replica<E, W>(
+    engine: &E,
+    replication: &Replication,
+    since: Option<Position>,
+    stream: &Stream,
+    wstream: &mut W,
+) -> Result<()>
+where
+    E: KvsEngine,
+    W: Write,
+{
+    let feed = &replication.feed;
+    let mut subscription = match since.and_then(|since| feed.resume(since)) {
+        Some(subscription) => subscription,
+        None => {
+            // subscribing first means that no change is missed while the data is copied, changes
+            // that are already part of the snapshot are applied again with the same effect
+            let (position, subscription) = feed.subscribe();
+            send(wstream, &ReplicationMessage::SnapshotStart { position })?;
+            send_snapshot(engine, wstream)?;
+            send(wstream, &ReplicationMessage::SnapshotEnd)?;
+            wstream.flush()?;
+            subscription
+        }
+    };
+
+    let mut unchecked = 0;
+    loop {
+        let message = match subscription.next(HEARTBEAT_INTERVAL) {
+            Ok(Some(change)) => {
+                unchecked += 1;
+                ReplicationMessage::Change((*change).clone())
+            }
+            Ok(None) => {
+                unchecked = CHANGES_PER_CHECK;
+                ReplicationMessage::Heartbeat {
+                    position: feed.latest(),
+                }
+            }
+            Err(err) => {
+                send(wstream, &ReplicationMessage::Err(RemoteError::from(&err)))?;
+                wstream.flush()?;
+                return Err(err);
+            }
+        };
+        send(wstream, &message)?;
+        wstream.flush()?;
+
+        if unchecked >= CHANGES_PER_CHECK {
+            unchecked = 0;
+            if peer_closed(stream) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn send_snapshot<E, W>(engine: &E, wstream: &mut W) -> Result<()>
+where
+    E: KvsEngine,
+    W: Write,
+{
+    let mut start = String::new();
+    loop {
+        let keys = engine.scan(start, SNAPSHOT_CHUNK_SIZE)?;
+        let last = match keys.last() {
+            Some(last) => scan_successor(last),
+            None => return Ok(()),
+        };
+
+        let mut pairs = Vec::with_capacity(keys.len());
+        for key in keys {
+            // the key may have been removed since it was listed
+            if let Some(value) = engine.get(key.clone())? {
+                pairs.push((key, value));
+            }
+        }
+        send(wstream, &ReplicationMessage::SnapshotChunk { pairs })?;
+        start = last;
+    }
+}
+
+fn send<W>(wstream: &mut W, message: &ReplicationMessage) -> Result<()>
+where
+    W: Write,
+{
+    serde_json::to_writer(wstream, message)?;
+    Ok(())
+}
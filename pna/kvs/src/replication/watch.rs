@@ -0,0 +1,80 @@
+use crate::engines::WriteOp;
+use crate::networking::{peer_closed, RemoteError, Stream, WatchMessage};
+use crate::replication::primary::HEARTBEAT_INTERVAL;
+use crate::replication::Replication;
+use crate::Result;
+use std::io::Write;
+
+/// Number of changes that are looked at before checking whether the watcher is still connected
+const CHANGES_PER_CHECK: usize = 256;
+
+/// Streams the operations on the keys that start with `prefix` to a watcher, until the watcher
+/// disconnects or the server stops reading from the connection. Changes are read from the same
+/// feed that replicas follow, so a watcher sees them in the order that they were committed.
+pub(crate) fn serve_watcher<W>(
+    replication: &Replication,
+    prefix: &str,
+    stream: &Stream,
+    wstream: &mut W,
+) -> Result<()>
+where
+    W: Write,
+{
+    let (_, mut subscription) = replication.feed.subscribe();
+    send(wstream, &WatchMessage::Started)?;
+    wstream.flush()?;
+
+    let mut unchecked = 0;
+    loop {
+        let message = match subscription.next(HEARTBEAT_INTERVAL) {
+            Ok(Some(change)) => {
+                unchecked += 1;
+                let ops: Vec<_> = change
+                    .ops
+                    .iter()
+                    .filter(|op| key(op).starts_with(prefix))
+                    .cloned()
+                    .collect();
+                if ops.is_empty() {
+                    None
+                } else {
+                    Some(WatchMessage::Change(ops))
+                }
+            }
+            Ok(None) => {
+                unchecked = CHANGES_PER_CHECK;
+                Some(WatchMessage::Heartbeat)
+            }
+            Err(err) => {
+                send(wstream, &WatchMessage::Err(RemoteError::from(&err)))?;
+                wstream.flush()?;
+                return Err(err);
+            }
+        };
+        if let Some(message) = message {
+            send(wstream, &message)?;
+            wstream.flush()?;
+        }
+
+        if unchecked >= CHANGES_PER_CHECK {
+            unchecked = 0;
+            if peer_closed(stream) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn key(op: &WriteOp) -> &str {
+    match op {
+        WriteOp::Set { key, .. } | WriteOp::Remove { key } => key,
+    }
+}
+
+fn send<W>(wstream: &mut W, message: &WatchMessage) -> Result<()>
+where
+    W: Write,
+{
+    serde_json::to_writer(wstream, message)?;
+    Ok(())
+}
@@ -3,12 +3,16 @@
 mod naive;
 mod rayon;
 mod shared_queue;
+mod work_stealing;
 
 pub use self::naive::NaiveThreadPool;
 pub use self::rayon::RayonThreadPool;
 pub use self::shared_queue::SharedQueueThreadPool;
+pub use self::work_stealing::WorkStealingThreadPool;
 
-use crate::Result;
+use crate::{Error, ErrorKind, Result};
+use std::str::FromStr;
+use std::sync::Arc;
 
 /// Interface of a threads manager that queues threads and executes the queued threads when
 /// possible
@@ -23,7 +27,77 @@ pub trait ThreadPool {
     fn spawn<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static;
+
+    /// Returns the number of tasks that wait for a thread, or `None` if the pool can not tell
+    fn queued_jobs(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// A shared pool runs the tasks of all of its owners on the same threads, so that several servers
+/// can be served by one pool
+impl<P> ThreadPool for Arc<P>
+where
+    P: ThreadPool,
+{
+    fn new(threads: u32) -> Result<Self> {
+        P::new(threads).map(Arc::new)
+    }
+
+    fn spawn<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        (**self).spawn(f)
+    }
+
+    fn queued_jobs(&self) -> Option<usize> {
+        (**self).queued_jobs()
+    }
 }
 
 /// Heap-allocated thread's closure
 pub type Thunk<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+/// Different thread pools that a server can run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadPoolKind {
+    /// `NaiveThreadPool`, a new thread for each task
+    Naive,
+    /// `SharedQueueThreadPool`
+    SharedQueue,
+    /// `RayonThreadPool`
+    Rayon,
+    /// `WorkStealingThreadPool`
+    WorkStealing,
+}
+
+impl ThreadPoolKind {
+    /// Get the string representation of the thread pool
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Self::Naive => "naive",
+            Self::SharedQueue => "shared-queue",
+            Self::Rayon => "rayon",
+            Self::WorkStealing => "work-stealing",
+        }
+    }
+}
+
+impl FromStr for ThreadPoolKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ThreadPoolKind> {
+        let name = s.to_lowercase();
+        match name.as_str() {
+            "naive" => Ok(Self::Naive),
+            "shared-queue" => Ok(Self::SharedQueue),
+            "rayon" => Ok(Self::Rayon),
+            "work-stealing" => Ok(Self::WorkStealing),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Could not found thread pool named '{}'", name),
+            )),
+        }
+    }
+}
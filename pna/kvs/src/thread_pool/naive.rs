@@ -16,4 +16,9 @@ impl ThreadPool for NaiveThreadPool {
     {
         std::thread::spawn(f);
     }
+
+    fn queued_jobs(&self) -> Option<usize> {
+        // every task gets a thread right away
+        Some(0)
+    }
 }
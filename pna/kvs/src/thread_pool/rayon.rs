@@ -22,4 +22,3 @@ impl ThreadPool for RayonThreadPool {
         self.pool.spawn(f);
     }
 }
-
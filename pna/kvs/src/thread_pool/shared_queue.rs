@@ -1,5 +1,6 @@
 use crate::thread_pool::{ThreadPool, Thunk};
 use crate::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
@@ -27,7 +28,14 @@ impl ThreadPool for SharedQueueThreadPool {
     where
         F: FnOnce() + Send + 'static,
     {
-        self.job_tx.send(Box::new(f)).ok();
+        self._context.queued.fetch_add(1, Ordering::Relaxed);
+        if self.job_tx.send(Box::new(f)).is_err() {
+            self._context.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    fn queued_jobs(&self) -> Option<usize> {
+        Some(self._context.queued.load(Ordering::Relaxed))
     }
 }
 
@@ -42,7 +50,10 @@ fn spawn_in_pool(context: Arc<Context>) -> JoinHandle<()> {
 
             match job {
                 // execute the queued job
-                Ok(job) => job(),
+                Ok(job) => {
+                    context.queued.fetch_sub(1, Ordering::Relaxed);
+                    job()
+                }
                 // stop the thread, the receive channel was closed
                 Err(_) => break,
             }
@@ -54,12 +65,15 @@ fn spawn_in_pool(context: Arc<Context>) -> JoinHandle<()> {
 /// Data structure holding the shared state between all threads in the pool
 struct Context {
     job_rx: Mutex<Receiver<Thunk<'static>>>,
+    /// Number of jobs that were sent and not yet received by a thread
+    queued: AtomicUsize,
 }
 
 impl Context {
     fn new(job_rx: Receiver<Thunk<'static>>) -> Self {
         Self {
             job_rx: Mutex::new(job_rx),
+            queued: AtomicUsize::new(0),
         }
     }
 }
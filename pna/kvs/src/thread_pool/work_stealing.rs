@@ -0,0 +1,161 @@
+use crate::thread_pool::{ThreadPool, Thunk};
+use crate::Result;
+use crossbeam_deque::{Injector, Stealer, Worker};
+use std::cell::RefCell;
+use std::iter;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+thread_local! {
+    /// Deque of the worker that runs on this thread, with the address of the context of its pool
+    static LOCAL: RefCell<Option<(usize, Worker<Thunk<'static>>)>> = RefCell::new(None);
+}
+
+/// A threadpool that spawns a fix number of threads on startup, each with its own deque of jobs.
+/// Jobs that are spawned from outside the pool go to a global injector queue, jobs that a job
+/// spawns go to the deque of its thread. A thread whose deque is empty takes a batch of jobs from
+/// the injector, or steals from the other threads, so no lock is shared by every job. Threads
+/// keep running after a job panicked. Dropping the pool lets the threads finish the queued jobs
+/// before they exit.
+#[allow(missing_debug_implementations)]
+pub struct WorkStealingThreadPool {
+    context: Arc<Context>,
+}
+
+impl ThreadPool for WorkStealingThreadPool {
+    fn new(threads: u32) -> Result<Self> {
+        let workers: Vec<Worker<Thunk<'static>>> =
+            (0..threads).map(|_| Worker::new_fifo()).collect();
+        let context = Arc::new(Context {
+            injector: Injector::new(),
+            stealers: workers.iter().map(Worker::stealer).collect(),
+            queued: AtomicUsize::new(0),
+            sleeping: AtomicUsize::new(0),
+            stopping: AtomicBool::new(false),
+            lock: Mutex::new(()),
+            wakeup: Condvar::new(),
+        });
+        for worker in workers {
+            let context = Arc::clone(&context);
+            std::thread::spawn(move || run(context, worker));
+        }
+        Ok(Self { context })
+    }
+
+    fn spawn<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // counted before it is pushed, so that a thread that takes the job right away does not
+        // count below zero
+        self.context.queued.fetch_add(1, Ordering::SeqCst);
+        let pool = self.context.id();
+        let job = LOCAL.with(|local| match &*local.borrow() {
+            Some((id, worker)) if *id == pool => {
+                worker.push(Box::new(f));
+                None
+            }
+            _ => Some(Box::new(f)),
+        });
+        if let Some(job) = job {
+            self.context.injector.push(job);
+        }
+        self.context.wake_one();
+    }
+
+    fn queued_jobs(&self) -> Option<usize> {
+        Some(self.context.queued.load(Ordering::Relaxed))
+    }
+}
+
+impl Drop for WorkStealingThreadPool {
+    fn drop(&mut self) {
+        let _lock = self.context.lock.lock().unwrap();
+        self.context.stopping.store(true, Ordering::SeqCst);
+        self.context.wakeup.notify_all();
+    }
+}
+
+fn run(context: Arc<Context>, worker: Worker<Thunk<'static>>) {
+    LOCAL.with(|local| *local.borrow_mut() = Some((context.id(), worker)));
+    loop {
+        // the deque must not be borrowed while the job runs, the job may spawn more jobs
+        let job = LOCAL.with(|local| {
+            let local = local.borrow();
+            let (_, worker) = local.as_ref().unwrap();
+            context.find_job(worker)
+        });
+        match job {
+            Some(job) => {
+                context.queued.fetch_sub(1, Ordering::SeqCst);
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+            None => {
+                if !context.sleep() {
+                    break;
+                }
+            }
+        }
+    }
+    LOCAL.with(|local| local.borrow_mut().take());
+}
+
+/// Data structure holding the shared state between all threads in the pool
+struct Context {
+    injector: Injector<Thunk<'static>>,
+    stealers: Vec<Stealer<Thunk<'static>>>,
+    /// Number of jobs that were spawned and not yet taken by a thread
+    queued: AtomicUsize,
+    /// Number of threads that wait for a job
+    sleeping: AtomicUsize,
+    /// Set once the pool is dropped
+    stopping: AtomicBool,
+    lock: Mutex<()>,
+    wakeup: Condvar,
+}
+
+impl Context {
+    /// Identifies the pool of a thread's deque
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Takes a job from the thread's own deque, then from the injector, then from the other
+    /// threads
+    fn find_job(&self, worker: &Worker<Thunk<'static>>) -> Option<Thunk<'static>> {
+        worker.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(worker)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(|steal| steal.success())
+        })
+    }
+
+    /// Waits until a job is queued. Returns `false` if the pool was dropped and no job is left.
+    fn sleep(&self) -> bool {
+        let lock = self.lock.lock().unwrap();
+        // a spawner that does not see this thread sleeping queued its job before, which the
+        // check below sees
+        self.sleeping.fetch_add(1, Ordering::SeqCst);
+        let _lock = self
+            .wakeup
+            .wait_while(lock, |_| {
+                self.queued.load(Ordering::SeqCst) == 0 && !self.stopping.load(Ordering::SeqCst)
+            })
+            .unwrap();
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        self.queued.load(Ordering::SeqCst) > 0
+    }
+
+    /// Wakes a sleeping thread up for a job that was just queued
+    fn wake_one(&self) {
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let _lock = self.lock.lock().unwrap();
+            self.wakeup.notify_one();
+        }
+    }
+}
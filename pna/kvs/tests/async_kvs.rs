@@ -0,0 +1,145 @@
+use kvs::engines::{AsyncKvsEngine, OffloadKvsEngine};
+use kvs::networking::{
+    AsyncJsonKvsClient, AsyncJsonKvsServer, GetResponse, JsonKvsClient, Request, Response,
+};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{ErrorKind, KvStore, KvsClient, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn prep_engine(temp_dir: &TempDir) -> Result<OffloadKvsEngine<KvStore, SharedQueueThreadPool>> {
+    let pool = SharedQueueThreadPool::new(4)?;
+    Ok(OffloadKvsEngine::new(KvStore::open(temp_dir.path())?, pool))
+}
+
+// Should run the operations of a blocking engine without blocking the executor
+#[test]
+fn offload_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = prep_engine(&temp_dir)?;
+
+    futures::executor::block_on(async {
+        let sets: Vec<_> = (0..100)
+            .map(|i| engine.set(format!("key{}", i), format!("value{}", i)))
+            .collect();
+        for result in futures::future::join_all(sets).await {
+            result?;
+        }
+        let increments: Vec<_> = (0..100)
+            .map(|_| engine.increment("counter".to_owned(), 1))
+            .collect();
+        for result in futures::future::join_all(increments).await {
+            result?;
+        }
+
+        for i in 0..100 {
+            let val = engine.get(format!("key{}", i)).await?;
+            assert_eq!(val, Some(format!("value{}", i)));
+        }
+        assert_eq!(
+            engine.get("counter".to_owned()).await?,
+            Some("100".to_owned())
+        );
+        engine.remove("key0".to_owned()).await?;
+        assert!(engine.remove("key0".to_owned()).await.is_err());
+        Ok(())
+    })
+}
+
+// Should serve both asynchronous and blocking clients
+#[test]
+fn async_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = prep_engine(&temp_dir)?;
+    let addr: SocketAddr = "127.0.0.1:4010".parse().unwrap();
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.spawn(async move {
+        let server = AsyncJsonKvsServer::new(engine, None);
+        server.serve(addr).await.unwrap();
+    });
+    std::thread::sleep(Duration::from_secs(1));
+
+    runtime.block_on(async {
+        let clients: Vec<_> = (0..10)
+            .map(|client_id| async move {
+                let mut client = AsyncJsonKvsClient::connect(addr).await?;
+                for i in 0..10 {
+                    let key = format!("key{}-{}", client_id, i);
+                    client.set(key.clone(), format!("value{}", i)).await?;
+                    assert_eq!(client.get(key).await?, Some(format!("value{}", i)));
+                    client.increment("counter".to_owned(), 1).await?;
+                }
+                Ok::<_, kvs::Error>(())
+            })
+            .collect();
+        for result in futures::future::join_all(clients).await {
+            result?;
+        }
+
+        let mut client = AsyncJsonKvsClient::connect(addr).await?;
+        client
+            .append("key0-0".to_owned(), "-suffix".to_owned())
+            .await?;
+        client.remove("key0-1".to_owned()).await?;
+        assert!(client.remove("key0-1".to_owned()).await.is_err());
+        Ok::<_, kvs::Error>(())
+    })?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    assert_eq!(client.get("counter".to_owned())?, Some("100".to_owned()));
+    assert_eq!(
+        client.get("key0-0".to_owned())?,
+        Some("value0-suffix".to_owned())
+    );
+    assert_eq!(client.get("key0-1".to_owned())?, None);
+
+    // Pipelined requests
+    let gets = vec![
+        Request::Get {
+            key: "counter".to_owned(),
+        },
+        Request::Get {
+            key: "key0-1".to_owned(),
+        },
+    ];
+    let responses = client.pipeline(gets)?;
+    assert!(matches!(&responses[0], Response::Get(GetResponse::Ok(Some(v))) if v == "100"));
+    assert!(matches!(
+        &responses[1],
+        Response::Get(GetResponse::Ok(None))
+    ));
+    Ok(())
+}
+
+// Should read values that span many reads, and close connections that send oversized requests
+#[test]
+fn async_server_max_request_size() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = prep_engine(&temp_dir)?;
+    let addr: SocketAddr = "127.0.0.1:4046".parse().unwrap();
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.spawn(async move {
+        let server = AsyncJsonKvsServer::new(engine, None).max_request_size(256 * 1024);
+        server.serve(addr).await.unwrap();
+    });
+    std::thread::sleep(Duration::from_secs(1));
+
+    runtime.block_on(async {
+        let mut client = AsyncJsonKvsClient::connect(addr).await?;
+        let value = "\"x\\\"".repeat(32 * 1024);
+        client.set("key".to_owned(), value.clone()).await?;
+        assert_eq!(client.get("key".to_owned()).await?, Some(value));
+
+        let err = client
+            .set("large".to_owned(), "x".repeat(512 * 1024))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidNetworkMessage);
+        assert!(client.get("key".to_owned()).await.is_err());
+        Ok::<_, kvs::Error>(())
+    })?;
+    Ok(())
+}
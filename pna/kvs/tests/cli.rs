@@ -1,4 +1,5 @@
 use assert_cmd::prelude::*;
+use predicates::prelude::*;
 use predicates::str::{contains, is_empty};
 use std::fs::{self, File};
 use std::process::Command;
@@ -20,28 +21,36 @@ fn client_cli_invalid_get() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get"])
+        .args(["get"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "extra", "field"])
+        .args(["get", "extra", "field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--addr", "invalid-addr"])
+        .args(["get", "key", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
+    // nothing listens on this address
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--unknown-flag"])
+        .args(["get", "key", "--addr", "127.0.0.1:4007"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(4);
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -52,35 +61,35 @@ fn client_cli_invalid_set() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set"])
+        .args(["set"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "missing_field"])
+        .args(["set", "missing_field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key", "value", "extra_field"])
+        .args(["set", "key", "value", "extra_field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key", "value", "--addr", "invalid-addr"])
+        .args(["set", "key", "value", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--unknown-flag"])
+        .args(["get", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -91,28 +100,28 @@ fn client_cli_invalid_rm() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm"])
+        .args(["rm"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "extra", "field"])
+        .args(["rm", "extra", "field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key", "--addr", "invalid-addr"])
+        .args(["rm", "key", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key", "--unknown-flag"])
+        .args(["rm", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -123,7 +132,7 @@ fn client_cli_invalid_subcommand() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["unknown"])
+        .args(["unknown"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -134,7 +143,7 @@ fn client_cli_invalid_subcommand() {
 fn client_cli_version() {
     let temp_dir = TempDir::new().unwrap();
     let mut cmd = Command::cargo_bin("kvs-client").unwrap();
-    cmd.args(&["-V"])
+    cmd.args(["-V"])
         .current_dir(&temp_dir)
         .assert()
         .stdout(contains(env!("CARGO_PKG_VERSION")));
@@ -145,7 +154,7 @@ fn client_cli_version() {
 fn server_cli_version() {
     let temp_dir = TempDir::new().unwrap();
     let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-    cmd.args(&["-V"])
+    cmd.args(["-V"])
         .current_dir(&temp_dir)
         .assert()
         .stdout(contains(env!("CARGO_PKG_VERSION")));
@@ -157,13 +166,14 @@ fn cli_log_configuration() {
     let stderr_path = temp_dir.path().join("stderr");
     let mut cmd = Command::cargo_bin("kvs-server").unwrap();
     let mut child = cmd
-        .args(&["--engine", "kvs", "--addr", "127.0.0.1:4001"])
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4001"])
         .current_dir(&temp_dir)
         .stderr(File::create(&stderr_path).unwrap())
         .spawn()
         .unwrap();
     thread::sleep(Duration::from_secs(1));
     child.kill().expect("server exited before killed");
+    child.wait().unwrap();
 
     let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
     assert!(content.contains(env!("CARGO_PKG_VERSION")));
@@ -178,15 +188,16 @@ fn cli_wrong_engine() {
         let temp_dir = TempDir::new().unwrap();
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
         let mut child = cmd
-            .args(&["--engine", "sled", "--addr", "127.0.0.1:4002"])
+            .args(["--engine", "sled", "--addr", "127.0.0.1:4002"])
             .current_dir(&temp_dir)
             .spawn()
             .unwrap();
         thread::sleep(Duration::from_secs(1));
         child.kill().expect("server exited before killed");
+        child.wait().unwrap();
 
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-        cmd.args(&["--engine", "kvs", "--addr", "127.0.0.1:4003"])
+        cmd.args(["--engine", "kvs", "--addr", "127.0.0.1:4003"])
             .current_dir(&temp_dir)
             .assert()
             .failure();
@@ -197,39 +208,448 @@ fn cli_wrong_engine() {
         let temp_dir = TempDir::new().unwrap();
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
         let mut child = cmd
-            .args(&["--engine", "kvs", "--addr", "127.0.0.1:4002"])
+            .args(["--engine", "kvs", "--addr", "127.0.0.1:4002"])
             .current_dir(&temp_dir)
             .spawn()
             .unwrap();
         thread::sleep(Duration::from_secs(1));
         child.kill().expect("server exited before killed");
+        child.wait().unwrap();
 
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-        cmd.args(&["--engine", "sled", "--addr", "127.0.0.1:4003"])
+        cmd.args(["--engine", "sled", "--addr", "127.0.0.1:4003"])
             .current_dir(&temp_dir)
             .assert()
             .failure();
     }
 }
 
+// `kvs-server` should shut down gracefully and exit successfully on SIGTERM
+#[test]
+fn cli_graceful_shutdown() {
+    let temp_dir = TempDir::new().unwrap();
+    let stderr_path = temp_dir.path().join("stderr");
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .assert()
+        .success();
+    let status = child.wait().unwrap();
+    assert!(status.success());
+
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    assert!(content.contains("stopped"));
+}
+
+// `kvs-server --config` should read its settings from a TOML file, options on the command line
+// take precedence
+#[test]
+fn cli_config_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let stderr_path = temp_dir.path().join("stderr");
+    let config_path = temp_dir.path().join("server.toml");
+    fs::write(
+        &config_path,
+        "addr = \"127.0.0.1:4009\"\n\
+         engine = \"sled\"\n\
+         thread_pool = \"shared-queue\"\n\
+         threads = 2\n\
+         idle_timeout_secs = 60\n\
+         \n\
+         [sled]\n\
+         flush_every_ms = 100\n",
+    )
+    .unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap()])
+        .args(["--addr", "127.0.0.1:4008", "--thread-pool", "rayon"])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4008"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    let engine = fs::read_to_string(temp_dir.path().join("KVS_ENGINE")).unwrap();
+    assert_eq!(engine, "sled");
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    assert!(content.contains("rayon"));
+    assert!(content.contains("127.0.0.1:4008"));
+
+    // unknown settings are rejected instead of being ignored
+    let other_dir = TempDir::new().unwrap();
+    fs::write(&config_path, "thread_count = 2\n").unwrap();
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap()])
+        .current_dir(&other_dir)
+        .assert()
+        .failure()
+        .stderr(contains("thread_count"));
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--thread-pool", "shared-queue", "--threads", "0"])
+        .current_dir(&other_dir)
+        .assert()
+        .failure();
+}
+
+// `kvs-server` should read its users from the config file and `kvs-client --token` should
+// authenticate as one of them
+#[test]
+fn cli_auth() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("server.toml");
+    fs::write(
+        &config_path,
+        "[auth]\n\
+         method = \"hmac\"\n\
+         \n\
+         [[auth.users]]\n\
+         name = \"app\"\n\
+         token = \"secret\"\n\
+         read = [\"app/\"]\n\
+         write = [\"app/\"]\n",
+    )
+    .unwrap();
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap()])
+        .args(["--addr", "127.0.0.1:4012"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let client = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("kvs-client").unwrap();
+        cmd.args(args)
+            .args(["--addr", "127.0.0.1:4012"])
+            .current_dir(&temp_dir);
+        cmd
+    };
+    client(&["set", "app/key", "value"])
+        .assert()
+        .code(9)
+        .stderr(contains("authentication"));
+    client(&["set", "app/key", "value", "--token", "wrong"])
+        .assert()
+        .code(9);
+    client(&["set", "app/key", "value", "--token", "secret"])
+        .assert()
+        .success();
+    client(&["get", "app/key", "--token", "secret"])
+        .assert()
+        .success()
+        .stdout("value\n");
+    client(&["set", "other", "value", "--token", "secret"])
+        .assert()
+        .code(9)
+        .stderr(contains("Permission denied"));
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    // only JSON connections can authenticate
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--config", config_path.to_str().unwrap()])
+        .args(["--protocol", "resp"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Authentication"));
+}
+
+// `kvs-server` and `kvs-client` should talk over a Unix domain socket, and a restarted server
+// should replace the socket file of a killed one
+#[cfg(unix)]
+#[test]
+fn cli_unix_socket() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = format!("unix:{}", temp_dir.path().join("kvs.sock").display());
+    let server = || {
+        let child = Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--engine", "kvs", "--addr", &addr])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+        child
+    };
+    let client = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("kvs-client").unwrap();
+        cmd.args(args)
+            .args(["--addr", &addr])
+            .current_dir(&temp_dir);
+        cmd
+    };
+
+    let mut child = server();
+    client(&["set", "key", "value"]).assert().success();
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    let mut child = server();
+    client(&["get", "key"]).assert().success().stdout("value\n");
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key", "--addr", "unix:"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// `kvs-client admin` should run maintenance commands on a server started with an admin token
+#[test]
+fn cli_admin() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4013";
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", addr, "--admin-token", "secret"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    let client = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("kvs-client").unwrap();
+        cmd.args(args).args(["--addr", addr]).current_dir(&temp_dir);
+        cmd
+    };
+    let admin = |args: &[&str]| {
+        let mut cmd = client(&["admin"]);
+        cmd.args(args).args(["--admin-token", "secret"]);
+        cmd
+    };
+
+    client(&["set", "key", "value"]).assert().success();
+    client(&["admin", "stats"]).assert().code(9);
+    admin(&["compact"]).assert().success().stdout(is_empty());
+    admin(&["flush"]).assert().success().stdout(is_empty());
+    admin(&["stats"])
+        .assert()
+        .success()
+        .stdout(contains("keys\t1\n").and(contains("merges\t1\n")));
+    admin(&["clients"])
+        .assert()
+        .success()
+        .stdout(contains("\t127.0.0.1:"));
+    admin(&["log-level", "debug"]).assert().success();
+    admin(&["log-level"]).assert().code(3);
+    admin(&["stats", "extra"]).assert().code(3);
+    admin(&["checkpoint", "checkpoint"]).assert().success();
+    admin(&["checkpoint", "checkpoint"]).assert().code(5);
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(temp_dir.path().join("checkpoint"))
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    client(&["get", "key"]).assert().success().stdout("value\n");
+    client(&["admin", "stats"]).assert().failure();
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+}
+
+// `kvs-server` should only record its changes for replicas and watchers with `--replication`
+#[test]
+fn cli_replication() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4014";
+    let status = || {
+        let mut cmd = Command::cargo_bin("kvs-client").unwrap();
+        cmd.args(["replication-status", "--addr", addr])
+            .current_dir(&temp_dir);
+        cmd
+    };
+
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    status().assert().failure();
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", addr, "--replication"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    status()
+        .assert()
+        .success()
+        .stdout(contains("role: primary"));
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+}
+
+// `--no-<flag>` should turn off a flag that the config file turns on
+#[test]
+fn cli_flags_override_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4015";
+    let config_path = temp_dir.path().join("server.toml");
+    fs::write(&config_path, "admin = true\nreplication = true\n").unwrap();
+    let server = |args: &[&str]| {
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--config", config_path.to_str().unwrap()])
+            .args(["--engine", "kvs", "--addr", addr])
+            .args(args)
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap()
+    };
+    let client = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("kvs-client").unwrap();
+        cmd.args(args).args(["--addr", addr]).current_dir(&temp_dir);
+        cmd
+    };
+
+    let mut child = server(&[]);
+    thread::sleep(Duration::from_secs(1));
+    client(&["admin", "stats"]).assert().success();
+    client(&["replication-status"]).assert().success();
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    let mut child = server(&["--no-admin", "--no-replication"]);
+    thread::sleep(Duration::from_secs(1));
+    client(&["admin", "stats"]).assert().failure();
+    client(&["replication-status"]).assert().failure();
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    // the last of a flag and its negation wins
+    let mut child = server(&["--no-admin", "--admin"]);
+    thread::sleep(Duration::from_secs(1));
+    client(&["admin", "stats"]).assert().success();
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+// `kvs-client exec` and `kvs-client shell` should run many commands on one connection and report
+// the result of each of them
+#[test]
+fn cli_sessions() {
+    let temp_dir = TempDir::new().unwrap();
+    let addr = "127.0.0.1:4011";
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let script_path = temp_dir.path().join("script.txt");
+    fs::write(
+        &script_path,
+        "# comments and blank lines are skipped\n\
+         set key1 \"value 1\"\n\
+         \n\
+         get key1\n\
+         rm missing\n\
+         incr counter 3\n\
+         unknown\n\
+         mset key2 value2 key3 value3\n\
+         mget key1 key2 key4\n",
+    )
+    .unwrap();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["exec", "-f", script_path.to_str().unwrap(), "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(2)
+        .stdout("OK\nvalue 1\n3\nOK\nvalue 1\nvalue2\nKey not found\n")
+        .stderr(contains("line 5: Key 'missing' does not exist").and(contains("line 7: ")));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["exec", "--addr", addr])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("mdel key2 key3\nget key2\n")
+        .assert()
+        .success()
+        .stdout("2\nKey not found\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["shell", "--addr", addr])
+        .env("HOME", temp_dir.path())
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("append key1 !\nget key1\nquit\nget key1\n")
+        .assert()
+        .success()
+        .stdout(contains("OK\nvalue 1!\n"))
+        .stdout(contains("value 1!\nvalue 1!").not());
+    let history = fs::read_to_string(temp_dir.path().join(".kvs_history")).unwrap();
+    assert!(history.contains("append key1 !"));
+
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+}
+
 fn cli_access_server(engine: &str, addr: &str) {
     let (sender, receiver) = mpsc::sync_channel(0);
     let temp_dir = TempDir::new().unwrap();
     let mut server = Command::cargo_bin("kvs-server").unwrap();
     let mut child = server
-        .args(&["--engine", engine, "--addr", addr])
+        .args(["--engine", engine, "--addr", addr])
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
     let handle = thread::spawn(move || {
         let _ = receiver.recv(); // wait for main thread to finish
         child.kill().expect("server exited before killed");
+        child.wait().unwrap();
     });
     thread::sleep(Duration::from_secs(1));
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key1", "value1", "--addr", addr])
+        .args(["set", "key1", "value1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -237,15 +657,24 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key1", "--addr", addr])
+        .args(["get", "key1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
         .stdout("value1\n");
 
+    // the first server can not be reached
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key1", "value2", "--addr", addr])
+        .args(["get", "key1", "--addr", "127.0.0.1:4007", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value2", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -253,7 +682,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key1", "--addr", addr])
+        .args(["get", "key1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -261,7 +690,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key2", "--addr", addr])
+        .args(["get", "key2", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -269,15 +698,15 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key2", "--addr", addr])
+        .args(["rm", "key2", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
-        .failure()
+        .code(2)
         .stderr(contains("Key not found"));
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key2", "value3", "--addr", addr])
+        .args(["set", "key2", "value3", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -285,12 +714,82 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key1", "--addr", addr])
+        .args(["rm", "key1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
         .stdout(is_empty());
 
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["incr", "counter", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["incr", "counter", "-5", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("-4\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["incr", "key2", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(3);
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["append", "key2", "_suffix", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["mset", "--batch-size", "2", "--addr", addr])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("mkey1\tmvalue1\nmkey2\tmvalue 2\nmkey3\tmvalue3\n")
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["mget", "--addr", addr])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("mkey1\nmissing\nmkey2\n")
+        .assert()
+        .success()
+        .stdout("mvalue1\nKey not found\nmvalue 2\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["mdel", "--addr", addr])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("mkey1\nmissing\nmkey3\n")
+        .assert()
+        .success()
+        .stdout("2\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["mset", "--addr", addr])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("no-tab\n")
+        .assert()
+        .code(3);
+
     thread::sleep(Duration::from_secs(2));
     sender.send(()).unwrap();
     handle.join().unwrap();
@@ -299,30 +798,71 @@ fn cli_access_server(engine: &str, addr: &str) {
     let (sender, receiver) = mpsc::sync_channel(0);
     let mut server = Command::cargo_bin("kvs-server").unwrap();
     let mut child = server
-        .args(&["--engine", engine, "--addr", addr])
+        .args(["--engine", engine, "--addr", addr])
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
     let handle = thread::spawn(move || {
         let _ = receiver.recv(); // wait for main thread to finish
         child.kill().expect("server exited before killed");
+        child.wait().unwrap();
     });
     thread::sleep(Duration::from_secs(1));
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key2", "--addr", addr])
+        .args(["get", "key2", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
-        .stdout(contains("value3"));
+        .stdout(contains("value3_suffix"));
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key1", "--addr", addr])
+        .args(["get", "counter", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("-4\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", addr])
         .current_dir(&temp_dir)
         .assert()
         .success()
         .stdout(contains("Key not found"));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["mget", "--addr", addr])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("mkey1\nmkey2\nmkey3\n")
+        .assert()
+        .success()
+        .stdout("Key not found\nmvalue 2\nKey not found\n");
+
+    // a cluster with a single node stores every key on it
+    let cluster_path = temp_dir.path().join("cluster.toml");
+    fs::write(&cluster_path, format!("nodes = [\"{}\"]\n", addr)).unwrap();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--cluster", cluster_path.to_str().unwrap(), "get", "mkey2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("mvalue 2\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rebalance", "--cluster", cluster_path.to_str().unwrap()])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("0\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rebalance"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(3);
     sender.send(()).unwrap();
     handle.join().unwrap();
 }
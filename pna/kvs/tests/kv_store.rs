@@ -1,4 +1,6 @@
-use kvs::{KvStore, KvsEngine, Result};
+use kvs::engines::{scan_successor, Codec, KvStoreConfig, WriteBatch};
+use kvs::{ErrorKind, KvStore, KvsEngine, Result};
+use std::fs::OpenOptions;
 use std::sync::{Arc, Barrier};
 use std::thread;
 use tempfile::TempDir;
@@ -209,3 +211,299 @@ fn concurrent_get() -> Result<()> {
 
     Ok(())
 }
+
+// Should read values from memory-mapped sealed logs, and keep reading the correct values while
+// compaction removes those logs
+#[test]
+fn mmap_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open_with_config(temp_dir.path(), KvStoreConfig::default().mmap(true))?;
+    for key_id in 0..1000 {
+        store.set(format!("key{}", key_id), "init".to_owned())?;
+    }
+
+    // Open from disk again so that all values live in sealed logs
+    drop(store);
+    let store = KvStore::open_with_config(temp_dir.path(), KvStoreConfig::default().mmap(true))?;
+    for key_id in 0..1000 {
+        assert_eq!(
+            store.get(format!("key{}", key_id))?,
+            Some("init".to_owned())
+        );
+    }
+
+    let mut handles = Vec::new();
+    for thread_id in 0..4 {
+        let store = store.clone();
+        let handle = thread::spawn(move || {
+            for i in 0..10000 {
+                let key_id = (i + thread_id * 250) % 1000;
+                let val = store.get(format!("key{}", key_id)).unwrap();
+                assert!(val.is_some());
+            }
+        });
+        handles.push(handle);
+    }
+    // Overwrite enough data to trigger a few compactions while reading
+    for iter in 0..300 {
+        for key_id in 0..1000 {
+            store.set(format!("key{}", key_id), format!("{}", iter))?;
+        }
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    for key_id in 0..1000 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some("299".to_owned()));
+    }
+
+    drop(store);
+    let store = KvStore::open_with_config(temp_dir.path(), KvStoreConfig::default().mmap(true))?;
+    for key_id in 0..1000 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some("299".to_owned()));
+    }
+    Ok(())
+}
+
+// Should read back compressed values, including logs that mix records written with different
+// compression settings
+#[test]
+fn compressed_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let large_value = "{\"field\": \"value\"}".repeat(100);
+
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("plain".to_owned(), large_value.clone())?;
+    drop(store);
+
+    for &codec in &[Codec::Snappy, Codec::Deflate] {
+        let config = KvStoreConfig::default()
+            .compression(Some(codec))
+            .compression_threshold(64);
+        let store = KvStore::open_with_config(temp_dir.path(), config)?;
+        store.set(codec.to_string(), large_value.clone())?;
+        store.set(format!("{}-small", codec), "small".to_owned())?;
+        assert_eq!(store.get(codec.to_string())?, Some(large_value.clone()));
+        assert_eq!(store.get("plain".to_owned())?, Some(large_value.clone()));
+
+        let stats = store.stats();
+        assert!(stats.compression_ratio() > 5.0);
+    }
+
+    // Open from disk again without compression and check persistent data
+    let store = KvStore::open(temp_dir.path())?;
+    for key in &["plain", "snappy", "deflate"] {
+        assert_eq!(store.get(key.to_string())?, Some(large_value.clone()));
+    }
+    assert_eq!(
+        store.get("snappy-small".to_owned())?,
+        Some("small".to_owned())
+    );
+    assert_eq!(
+        store.get("deflate-small".to_owned())?,
+        Some("small".to_owned())
+    );
+    Ok(())
+}
+
+// Should only count the compressed values that are still in the index
+#[test]
+fn compression_stats_follow_overwrites() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let value = "{\"field\": \"value\"}".repeat(100);
+    let config = KvStoreConfig::default().compression(Some(Codec::Snappy));
+
+    let store = KvStore::open_with_config(temp_dir.path(), config.clone())?;
+    store.set("key1".to_owned(), value.clone())?;
+    let stats = store.stats();
+    assert_eq!(stats.uncompressed_bytes, value.len() as u64);
+
+    store.set("key1".to_owned(), value.clone())?;
+    store.set("key2".to_owned(), value.clone())?;
+    store.remove("key2".to_owned())?;
+    assert_eq!(store.stats().uncompressed_bytes, stats.uncompressed_bytes);
+    assert_eq!(store.stats().compressed_bytes, stats.compressed_bytes);
+    drop(store);
+
+    let store = KvStore::open_with_config(temp_dir.path(), config)?;
+    assert_eq!(store.stats().uncompressed_bytes, stats.uncompressed_bytes);
+    assert_eq!(store.stats().compressed_bytes, stats.compressed_bytes);
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.stats().uncompressed_bytes, 0);
+    assert_eq!(store.stats().compressed_bytes, 0);
+    Ok(())
+}
+
+// Should rewrite records with the configured compression settings during compaction
+#[test]
+fn recompress_on_merge() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let value = "{\"field\": \"value\"}".repeat(100);
+
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..1000 {
+        store.set(format!("key{}", key_id), value.clone())?;
+    }
+    drop(store);
+
+    let config = KvStoreConfig::default()
+        .compression(Some(Codec::Snappy))
+        .recompress_on_merge(true);
+    let store = KvStore::open_with_config(temp_dir.path(), config)?;
+    assert_eq!(store.stats().compressed_bytes, 0);
+    // Overwrite a single key until compaction is triggered
+    while store.stats().merges == 0 {
+        store.set("key0".to_owned(), value.clone())?;
+    }
+
+    let stats = store.stats();
+    assert_eq!(stats.keys, 1000);
+    assert_eq!(stats.uncompressed_bytes, 1000 * value.len() as u64);
+    assert!(stats.compression_ratio() > 5.0);
+    for key_id in 0..1000 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some(value.clone()));
+    }
+    Ok(())
+}
+
+// Should update values in place with increment and append
+#[test]
+fn increment_and_append() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    assert_eq!(store.increment("counter".to_owned(), 5)?, 5);
+    assert_eq!(store.increment("counter".to_owned(), -7)?, -2);
+    store.append("greeting".to_owned(), "hello".to_owned())?;
+    store.append("greeting".to_owned(), " world".to_owned())?;
+    assert!(store.increment("greeting".to_owned(), 1).is_err());
+
+    store.set("max".to_owned(), i64::MAX.to_string())?;
+    assert!(store.increment("max".to_owned(), 1).is_err());
+    assert_eq!(store.get("max".to_owned())?, Some(i64::MAX.to_string()));
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("counter".to_owned())?, Some("-2".to_owned()));
+    assert_eq!(
+        store.get("greeting".to_owned())?,
+        Some("hello world".to_owned())
+    );
+    Ok(())
+}
+
+#[test]
+fn concurrent_increment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let mut handles = Vec::new();
+    for _ in 0..10 {
+        let store = store.clone();
+        let handle = thread::spawn(move || {
+            for _ in 0..100 {
+                store.increment("counter".to_owned(), 1).unwrap();
+                store.append("log".to_owned(), ".".to_owned()).unwrap();
+            }
+        });
+        handles.push(handle);
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(store.get("counter".to_owned())?, Some("1000".to_owned()));
+    assert_eq!(store.get("log".to_owned())?.map(|v| v.len()), Some(1000));
+    Ok(())
+}
+
+// Should apply every operation of a batch or none of them
+#[test]
+fn apply_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    let mut batch = WriteBatch::default();
+    batch.set("key2".to_owned(), "value2".to_owned());
+    batch.remove("key1".to_owned());
+    batch.remove("key1".to_owned());
+    let err = store.apply_batch(batch).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::KeyNotFound);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    let mut batch = WriteBatch::default();
+    batch.set("key2".to_owned(), "value2".to_owned());
+    batch.remove("key1".to_owned());
+    batch.set("key3".to_owned(), "value3".to_owned());
+    batch.remove("key3".to_owned());
+    store.apply_batch(batch)?;
+    store.flush()?;
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+    Ok(())
+}
+
+// Should lose a whole batch whose record was only partially written, and keep the entries of
+// complete batches through compaction
+#[test]
+fn torn_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let mut batch = WriteBatch::default();
+    batch.set("key1".to_owned(), "value1".to_owned());
+    batch.set("key2".to_owned(), "value2".to_owned());
+    store.apply_batch(batch)?;
+    store.compact()?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    let mut batch = WriteBatch::default();
+    batch.set("key2".to_owned(), "new-value2".to_owned());
+    batch.remove("key1".to_owned());
+    batch.set("key3".to_owned(), "value3".to_owned());
+    store.apply_batch(batch)?;
+    store.flush()?;
+    drop(store);
+
+    // Cut the last byte of the batch, as if the store crashed while writing it
+    let last_gen = (0..10)
+        .filter(|gen| temp_dir.path().join(format!("gen-{}.log", gen)).exists())
+        .max()
+        .expect("no log file was written");
+    let last_log = temp_dir.path().join(format!("gen-{}.log", last_gen));
+    let log = OpenOptions::new().write(true).open(&last_log)?;
+    log.set_len(log.metadata()?.len() - 1)?;
+    drop(log);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+    Ok(())
+}
+
+// Should list keys in order, and continue a scan from the successor of the last key
+#[test]
+fn scan_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    for key in &["b", "a", "d", "c", "ca"] {
+        store.set(key.to_string(), "value".to_owned())?;
+    }
+    store.remove("d".to_owned())?;
+
+    assert_eq!(store.scan(String::new(), 3)?, vec!["a", "b", "c"]);
+    assert_eq!(store.scan(scan_successor("c"), 3)?, vec!["ca".to_owned()]);
+    assert_eq!(store.scan("b".to_owned(), 10)?, vec!["b", "c", "ca"]);
+    assert!(store.scan("z".to_owned(), 10)?.is_empty());
+    Ok(())
+}
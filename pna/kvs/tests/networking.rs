@@ -0,0 +1,1141 @@
+use kvs::networking::{
+    Addr, AdminCommand, AdminConfig, AdminReply, AuthConfig, AuthMethod, AutoKvsServer,
+    BinaryKvsClient, BinaryKvsServer, ClusterConfig, Encoding, FailoverConfig, FailoverKvsClient,
+    GetResponse, HttpKvsServer, JsonKvsClient, JsonKvsServer, LogFormat, LogLevel, Metrics,
+    MetricsServer, RemoveResponse, Request, RequestLog, RespKvsServer, Response, ServerConfig,
+    SetResponse, ShardedKvsClient, User,
+};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{ErrorKind, KvStore, KvsClient, KvsEngine, KvsServer, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn start_auto_server(temp_dir: &TempDir, addr: SocketAddr) -> Result<()> {
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    AutoKvsServer::new(engine, pool, None).serve(addr)?;
+    Ok(())
+}
+
+fn access_server<C>(client: &mut C, prefix: &str) -> Result<()>
+where
+    C: KvsClient,
+{
+    let key = |name: &str| format!("{}-{}", prefix, name);
+    client.set(key("key1"), "value1".to_owned())?;
+    assert_eq!(client.get(key("key1"))?, Some("value1".to_owned()));
+    assert_eq!(client.get(key("key2"))?, None);
+    assert_eq!(client.increment(key("counter"), 2)?, 2);
+    client.append(key("key1"), "-suffix".to_owned())?;
+    assert_eq!(client.get(key("key1"))?, Some("value1-suffix".to_owned()));
+    client.remove(key("key1"))?;
+    let err = client.remove(key("key1")).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::KeyNotFound);
+    client.multi_set(vec![
+        (key("m1"), "v1".to_owned()),
+        (key("m2"), "v2".to_owned()),
+    ])?;
+    assert_eq!(
+        client.multi_get(vec![key("m1"), key("missing"), key("m2")])?,
+        vec![Some("v1".to_owned()), None, Some("v2".to_owned())]
+    );
+    assert_eq!(client.multi_remove(vec![key("m1"), key("missing")])?, 1);
+    assert_eq!(client.get(key("m1"))?, None);
+    client.set(key("text"), "text".to_owned())?;
+    let err = client.increment(key("text"), 1).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidValue);
+    Ok(())
+}
+
+// Should serve JSON clients and binary clients with every encoding on the same address
+#[test]
+fn auto_server_serves_all_protocols() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4020".parse().unwrap();
+    start_auto_server(&temp_dir, addr)?;
+
+    let mut json_client = JsonKvsClient::connect(addr)?;
+    access_server(&mut json_client, "json")?;
+
+    let mut bincode_client = BinaryKvsClient::connect(addr)?;
+    assert_eq!(bincode_client.encoding(), Encoding::Bincode);
+    access_server(&mut bincode_client, "bincode")?;
+
+    let mut binary_json_client = BinaryKvsClient::connect_with(addr, &[Encoding::Json])?;
+    assert_eq!(binary_json_client.encoding(), Encoding::Json);
+    access_server(&mut binary_json_client, "binary-json")?;
+
+    // Data written with one protocol is visible with another
+    json_client.set("shared".to_owned(), "value".to_owned())?;
+    assert_eq!(
+        bincode_client.get("shared".to_owned())?,
+        Some("value".to_owned())
+    );
+    Ok(())
+}
+
+// Should refuse the connection when no encoding is in common
+#[test]
+fn binary_handshake_without_common_encoding() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4021".parse().unwrap();
+    start_auto_server(&temp_dir, addr)?;
+
+    let err = BinaryKvsClient::connect_with(addr, &[]).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    // the number of encodings must fit in the handshake
+    let encodings = vec![Encoding::Bincode; 256];
+    let err = BinaryKvsClient::connect_with(addr, &encodings)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidValue);
+    Ok(())
+}
+
+// Should answer pipelined requests in order, without mixing them up with blocking calls
+#[test]
+fn json_client_pipelining() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4023".parse().unwrap();
+    start_auto_server(&temp_dir, addr)?;
+    let mut client = JsonKvsClient::connect(addr)?;
+
+    // More requests than can be in flight at once
+    let sets = (0..1000).map(|i| Request::Set {
+        key: format!("key{}", i),
+        value: format!("value{}", i),
+    });
+    let responses = client.pipeline(sets)?;
+    assert_eq!(responses.len(), 1000);
+    assert!(responses
+        .iter()
+        .all(|r| matches!(r, Response::Set(SetResponse::Ok))));
+
+    let gets = (0..1000).map(|i| Request::Get {
+        key: format!("key{}", i),
+    });
+    for (i, response) in client.pipeline(gets)?.into_iter().enumerate() {
+        match response {
+            Response::Get(GetResponse::Ok(Some(val))) => assert_eq!(val, format!("value{}", i)),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    let first = client.send(Request::Get {
+        key: "key1".to_owned(),
+    })?;
+    let second = client.send(Request::Remove {
+        key: "missing".to_owned(),
+    })?;
+    // A blocking call while requests are in flight
+    assert_eq!(client.get("key2".to_owned())?, Some("value2".to_owned()));
+    match client.recv()? {
+        (id, Response::Get(GetResponse::Ok(Some(val)))) => {
+            assert_eq!(id, first);
+            assert_eq!(val, "value1");
+        }
+        other => panic!("unexpected response {:?}", other),
+    }
+    match client.recv()? {
+        (id, Response::Remove(RemoveResponse::Err(_))) => assert_eq!(id, second),
+        other => panic!("unexpected response {:?}", other),
+    }
+    Ok(())
+}
+
+// Should stop accepting connections, close idle connections and flush the engine
+#[test]
+fn graceful_shutdown() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4024".parse().unwrap();
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = JsonKvsServer::new(engine, pool, None).serve(addr)?;
+    assert_eq!(handle.local_addr(), addr);
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    // a connection that has not sent anything yet
+    let _idle = JsonKvsClient::connect(addr)?;
+
+    let start = Instant::now();
+    handle.shutdown_within(Duration::from_secs(5))?;
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    assert!(client.get("key".to_owned()).is_err());
+    assert!(JsonKvsClient::connect(addr).is_err());
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// Should close idle connections, refuse connections over the limit and reject large requests
+#[test]
+fn server_limits() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4025".parse().unwrap();
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let config = ServerConfig::default()
+        .idle_timeout(Some(Duration::from_millis(500)))
+        .max_connections(Some(1))
+        .max_request_size(1024);
+    let handle = JsonKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+
+    // the only connection slot is taken
+    let mut rejected = JsonKvsClient::connect(addr)?;
+    let err = rejected.get("key".to_owned()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ServerBusy);
+
+    // idle connections are closed and free their slot
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(client.get("key".to_owned()).is_err());
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    let err = client
+        .set("large".to_owned(), "x".repeat(4096))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidNetworkMessage);
+    assert!(client.get("key".to_owned()).is_err());
+    // the slot is freed once the server sees the connection close
+    drop(client);
+    std::thread::sleep(Duration::from_millis(200));
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    assert_eq!(client.get("large".to_owned())?, None);
+
+    handle.shutdown()
+}
+
+// Should answer binary clients over the limits with errors that they can decode
+#[test]
+fn binary_server_limits() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4049".parse().unwrap();
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let config = ServerConfig::default()
+        .max_connections(Some(1))
+        .max_request_size(1024);
+    let handle = BinaryKvsServer::with_config(engine.clone(), pool, config, None).serve(addr)?;
+
+    let mut client = BinaryKvsClient::connect(addr)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+
+    // the only connection slot is taken
+    let err = BinaryKvsClient::connect(addr).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::ServerBusy);
+
+    let err = client
+        .set("large".to_owned(), "x".repeat(4096))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidNetworkMessage);
+    assert!(client.get("key".to_owned()).is_err());
+    handle.shutdown()?;
+
+    // a server that detects the protocol replies in the protocol of the rejected client
+    let addr: SocketAddr = "127.0.0.1:4050".parse().unwrap();
+    let pool = SharedQueueThreadPool::new(4)?;
+    let config = ServerConfig::default().max_connections(Some(1));
+    let handle = AutoKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    let err = BinaryKvsClient::connect(addr).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::ServerBusy);
+    let mut rejected = JsonKvsClient::connect(addr)?;
+    let err = rejected.get("key".to_owned()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ServerBusy);
+
+    handle.shutdown()
+}
+
+#[test]
+fn failover_client() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let other_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4026".parse().unwrap();
+    let other_addr: SocketAddr = "127.0.0.1:4027".parse().unwrap();
+    let start = |dir: &Path, addr| -> Result<_> {
+        let engine = KvStore::open(dir)?;
+        let pool = SharedQueueThreadPool::new(4)?;
+        JsonKvsServer::new(engine, pool, None).serve(addr)
+    };
+
+    // the second server is not running yet
+    let handle = start(temp_dir.path(), addr)?;
+    let config = FailoverConfig::default().deadline(Duration::from_secs(5));
+    let mut client = FailoverKvsClient::connect_with_config(vec![other_addr, addr], config)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let mut client = client.clone();
+            std::thread::spawn(move || client.set(format!("key{}", i), format!("value{}", i)))
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap()?;
+    }
+
+    // pooled connections to the stopped server are replaced
+    handle.shutdown()?;
+    let handle = start(temp_dir.path(), addr)?;
+    assert_eq!(client.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    // reads move on to the next server
+    handle.shutdown()?;
+    let other_handle = start(other_dir.path(), other_addr)?;
+    client.set("other".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, None);
+    assert_eq!(client.get("other".to_owned())?, Some("value".to_owned()));
+
+    // reads are retried until a server comes back
+    other_handle.shutdown()?;
+    let path = temp_dir.path().to_owned();
+    let restart = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        start(&path, addr)
+    });
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    restart.join().unwrap()?.shutdown()?;
+
+    // a client can not be created when no server is reachable
+    let config = FailoverConfig::default().deadline(Duration::from_millis(200));
+    let err = FailoverKvsClient::connect_with_config(vec![addr, other_addr], config).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Io);
+    Ok(())
+}
+
+// Should spread keys over the nodes of a cluster and move them when a node is added or removed
+#[test]
+fn sharded_client() -> Result<()> {
+    let dirs: Vec<_> = (0..3)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let addrs: Vec<SocketAddr> = (4033..4036)
+        .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+        .collect();
+    let engines: Vec<_> = dirs
+        .iter()
+        .map(|dir| KvStore::open(dir.path()))
+        .collect::<Result<_>>()?;
+    let handles: Vec<_> = engines
+        .iter()
+        .zip(&addrs)
+        .map(|(engine, &addr)| {
+            let pool = SharedQueueThreadPool::new(2)?;
+            JsonKvsServer::new(engine.clone(), pool, None).serve(addr)
+        })
+        .collect::<Result<_>>()?;
+
+    let mut client = ShardedKvsClient::connect_with_config(ClusterConfig::new(addrs.clone()))?;
+    let pairs: Vec<_> = (0..300)
+        .map(|i| (format!("key{}", i), format!("value{}", i)))
+        .collect();
+    client.multi_set(pairs.clone())?;
+    client.set("single".to_owned(), "value".to_owned())?;
+    assert_eq!(client.increment("counter".to_owned(), 3)?, 3);
+
+    // every key is stored on exactly the node that owns it, and each node has some
+    for (engine, &addr) in engines.iter().zip(&addrs) {
+        let keys = engine.scan(String::new(), 1000)?;
+        assert!(keys.len() > 30);
+        assert!(keys.iter().all(|key| client.node_of(key) == addr));
+    }
+    let keys: Vec<_> = pairs.iter().map(|(key, _)| key.clone()).collect();
+    let vals = client.multi_get(keys.clone())?;
+    assert!(vals
+        .iter()
+        .zip(&pairs)
+        .all(|(val, (_, expected))| val.as_deref() == Some(expected.as_str())));
+    assert_eq!(
+        client.scan("key1".to_owned(), 3)?,
+        vec!["key1", "key10", "key100"]
+    );
+
+    // the same ring on a smaller cluster keeps most keys where they are
+    let mut smaller =
+        ShardedKvsClient::connect_with_config(ClusterConfig::new(addrs[..2].to_vec()))?;
+    let removed_keys = engines[2].scan(String::new(), 1000)?.len() as u64;
+    let moved = smaller.rebalance(&addrs)?;
+    assert_eq!(moved, removed_keys);
+    assert!(engines[2].scan(String::new(), 1000)?.is_empty());
+    assert_eq!(smaller.get("single".to_owned())?, Some("value".to_owned()));
+    assert_eq!(smaller.get("counter".to_owned())?, Some("3".to_owned()));
+    assert_eq!(smaller.multi_get(keys.clone())?, vals);
+
+    // rebalancing again finds nothing to move, growing the cluster moves keys back
+    assert_eq!(smaller.rebalance(&[])?, 0);
+    assert_eq!(client.rebalance(&[])?, moved);
+    assert_eq!(client.multi_get(keys.clone())?, vals);
+    assert_eq!(client.multi_remove(keys)?, 300);
+
+    for handle in handles {
+        handle.shutdown()?;
+    }
+    Ok(())
+}
+
+fn resp_command(stream: &mut BufReader<TcpStream>, args: &[&str]) -> String {
+    let mut command = format!("*{}\r\n", args.len());
+    for arg in args {
+        command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    stream.get_mut().write_all(command.as_bytes()).unwrap();
+    read_resp_reply(stream)
+}
+
+fn read_resp_reply(stream: &mut BufReader<TcpStream>) -> String {
+    let mut line = String::new();
+    stream.read_line(&mut line).unwrap();
+    let mut reply = line.clone();
+    let count: i64 = line[1..].trim_end().parse().unwrap_or(-1);
+    match line.as_bytes()[0] {
+        b'$' if count >= 0 => {
+            let mut bulk = vec![0u8; count as usize + 2];
+            stream.read_exact(&mut bulk).unwrap();
+            reply.push_str(&String::from_utf8(bulk).unwrap());
+        }
+        b'*' => (0..count).for_each(|_| reply.push_str(&read_resp_reply(stream))),
+        _ => {}
+    }
+    reply
+}
+
+// Should answer commands over the maximum request size with an error and close the connection
+#[test]
+fn resp_server_max_request_size() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4047".parse().unwrap();
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let config = ServerConfig::default().max_request_size(1024);
+    let handle = RespKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+
+    let mut stream = BufReader::new(TcpStream::connect(addr)?);
+    let value = "x".repeat(512);
+    assert_eq!(
+        resp_command(&mut stream, &["SET", "key", &value]),
+        "+OK\r\n"
+    );
+    let value = "x".repeat(4096);
+    assert!(resp_command(&mut stream, &["SET", "large", &value])
+        .starts_with("-ERR Protocol error: command exceeds the maximum size of 1024 bytes"));
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest)?;
+    assert!(rest.is_empty());
+
+    // inline commands count against the same limit
+    let mut stream = BufReader::new(TcpStream::connect(addr)?);
+    stream
+        .get_mut()
+        .write_all(format!("SET large {}\r\n", value).as_bytes())?;
+    assert!(read_resp_reply(&mut stream).starts_with("-ERR Protocol error"));
+
+    let mut stream = BufReader::new(TcpStream::connect(addr)?);
+    assert_eq!(resp_command(&mut stream, &["GET", "large"]), "$-1\r\n");
+    handle.shutdown()
+}
+
+// Should serve Redis clients
+#[test]
+fn resp_server_commands() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4022".parse().unwrap();
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    RespKvsServer::new(engine, pool, None).serve(addr)?;
+
+    let mut stream = BufReader::new(TcpStream::connect(addr)?);
+    assert_eq!(resp_command(&mut stream, &["PING"]), "+PONG\r\n");
+    assert_eq!(resp_command(&mut stream, &["set", "k1", "v1"]), "+OK\r\n");
+    assert_eq!(resp_command(&mut stream, &["GET", "k1"]), "$2\r\nv1\r\n");
+    assert_eq!(resp_command(&mut stream, &["GET", "k2"]), "$-1\r\n");
+    assert_eq!(
+        resp_command(&mut stream, &["MSET", "k2", "v2", "k3", "v3"]),
+        "+OK\r\n"
+    );
+    assert_eq!(
+        resp_command(&mut stream, &["MGET", "k1", "missing", "k3"]),
+        "*3\r\n$2\r\nv1\r\n$-1\r\n$2\r\nv3\r\n"
+    );
+    assert_eq!(
+        resp_command(&mut stream, &["EXISTS", "k1", "k2", "missing"]),
+        ":2\r\n"
+    );
+    assert_eq!(resp_command(&mut stream, &["INCR", "counter"]), ":1\r\n");
+    assert_eq!(resp_command(&mut stream, &["INCR", "counter"]), ":2\r\n");
+    assert!(resp_command(&mut stream, &["INCR", "k1"]).starts_with("-ERR"));
+    assert_eq!(
+        resp_command(&mut stream, &["DEL", "k1", "missing"]),
+        ":1\r\n"
+    );
+    assert!(resp_command(&mut stream, &["GET"]).starts_with("-ERR wrong number"));
+    assert!(resp_command(&mut stream, &["FLUSHALL"]).starts_with("-ERR unknown command"));
+
+    // Scan every key two at a time, and with a pattern
+    let mut cursor = "0".to_owned();
+    let mut scanned = String::new();
+    loop {
+        let reply = resp_command(&mut stream, &["SCAN", &cursor, "COUNT", "2"]);
+        let lines: Vec<&str> = reply.split("\r\n").collect();
+        cursor = lines[2].to_owned();
+        scanned.push_str(&reply);
+        if cursor == "0" {
+            break;
+        }
+    }
+    for key in &["counter", "k2", "k3"] {
+        assert!(scanned.contains(key));
+    }
+    assert!(!scanned.contains("k1"));
+    assert_eq!(
+        resp_command(
+            &mut stream,
+            &["SCAN", "0", "MATCH", "k[2-9]", "COUNT", "100"]
+        ),
+        "*2\r\n$1\r\n0\r\n*2\r\n$2\r\nk2\r\n$2\r\nk3\r\n"
+    );
+
+    // Patterns with many stars match in linear time, and long patterns are rejected
+    let pattern = format!("{}b", "*a".repeat(100));
+    assert_eq!(
+        resp_command(&mut stream, &["SET", &"a".repeat(200), "v"]),
+        "+OK\r\n"
+    );
+    assert_eq!(
+        resp_command(
+            &mut stream,
+            &["SCAN", "0", "MATCH", &pattern, "COUNT", "100"]
+        ),
+        "*2\r\n$1\r\n0\r\n*0\r\n"
+    );
+    let pattern = "*".repeat(1000);
+    assert!(resp_command(&mut stream, &["SCAN", "0", "MATCH", &pattern])
+        .starts_with("-ERR pattern is too long"));
+
+    // Inline and pipelined commands
+    stream.get_mut().write_all(b"PING\r\nGET k2\r\n")?;
+    assert_eq!(read_resp_reply(&mut stream), "+PONG\r\n");
+    assert_eq!(read_resp_reply(&mut stream), "$2\r\nv2\r\n");
+
+    // Lengths beyond the limits are rejected before anything is buffered, and close the
+    // connection
+    stream.get_mut().write_all(b"*1\r\n$536870912\r\n")?;
+    assert!(read_resp_reply(&mut stream).starts_with("-ERR Protocol error: command exceeds"));
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest)?;
+    assert!(rest.is_empty());
+    Ok(())
+}
+
+/// Sends a raw HTTP request and returns the status and the body of the response
+fn http_request(stream: &mut BufReader<TcpStream>, request: &str) -> (u16, String) {
+    stream.get_mut().write_all(request.as_bytes()).unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).unwrap();
+    let status = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let mut length = 0;
+    loop {
+        line.clear();
+        stream.read_line(&mut line).unwrap();
+        if line.trim_end().is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body).unwrap();
+    (status, String::from_utf8(body).unwrap())
+}
+
+// Should serve the engine of a TCP server over HTTP with the same thread pool
+#[test]
+fn http_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4036".parse().unwrap();
+    let http_addr: SocketAddr = "127.0.0.1:4037".parse().unwrap();
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = Arc::new(SharedQueueThreadPool::new(4)?);
+    let handle = JsonKvsServer::new(engine.clone(), Arc::clone(&pool), None).serve(addr)?;
+    let http_handle = HttpKvsServer::new(engine, pool, None).serve(http_addr)?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+
+    let mut stream = BufReader::new(TcpStream::connect(http_addr)?);
+    assert_eq!(
+        http_request(&mut stream, "GET /keys/key1 HTTP/1.1\r\nHost: kvs\r\n\r\n"),
+        (200, "value1".to_owned())
+    );
+    assert_eq!(
+        http_request(
+            &mut stream,
+            "PUT /keys/a%20key HTTP/1.1\r\nContent-Length: 9\r\n\r\nsome text"
+        ),
+        (204, String::new())
+    );
+    assert_eq!(
+        client.get("a key".to_owned())?,
+        Some("some text".to_owned())
+    );
+    assert_eq!(
+        http_request(
+            &mut stream,
+            "PUT /keys/key2 HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nval\r\n3\r\nue2\r\n0\r\n\r\n"
+        ),
+        (204, String::new())
+    );
+    assert_eq!(client.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(
+        http_request(&mut stream, "DELETE /keys/key1 HTTP/1.1\r\n\r\n"),
+        (204, String::new())
+    );
+    let (status, body) = http_request(&mut stream, "GET /keys/key1 HTTP/1.1\r\n\r\n");
+    assert_eq!(status, 404);
+    assert!(body.contains("\"code\":1"));
+    assert_eq!(
+        http_request(&mut stream, "DELETE /keys/key1 HTTP/1.1\r\n\r\n").0,
+        404
+    );
+
+    // listings are paged with `next`
+    client.multi_set(
+        (0..5)
+            .map(|i| (format!("page{}", i), "v".to_owned()))
+            .collect(),
+    )?;
+    assert_eq!(
+        http_request(
+            &mut stream,
+            "GET /keys?prefix=page&limit=3 HTTP/1.1\r\n\r\n"
+        ),
+        (
+            200,
+            r#"{"keys":["page0","page1","page2"],"next":"page2\u0000"}"#.to_owned()
+        )
+    );
+    assert_eq!(
+        http_request(
+            &mut stream,
+            "GET /keys?start=page2%00&end=page4 HTTP/1.1\r\n\r\n"
+        ),
+        (200, r#"{"keys":["page3"],"next":null}"#.to_owned())
+    );
+    assert_eq!(
+        http_request(&mut stream, "GET /keys?limit=0 HTTP/1.1\r\n\r\n").0,
+        400
+    );
+
+    assert_eq!(
+        http_request(&mut stream, "GET /health HTTP/1.1\r\n\r\n"),
+        (200, r#"{"status":"ok"}"#.to_owned())
+    );
+    let (status, body) = http_request(&mut stream, "GET /stats HTTP/1.1\r\n\r\n");
+    assert_eq!(status, 200);
+    assert!(body.contains("\"open_connections\":1"));
+    assert!(body.contains("\"client_errors\":3"));
+    assert_eq!(
+        http_request(&mut stream, "POST /health HTTP/1.1\r\n\r\n").0,
+        405
+    );
+    assert_eq!(
+        http_request(&mut stream, "GET /other HTTP/1.1\r\n\r\n").0,
+        404
+    );
+
+    // the connection is closed when the client asks for it
+    http_request(
+        &mut stream,
+        "GET /health HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(stream.read(&mut [0u8; 1])?, 0);
+
+    // bodies whose framing is ambiguous or too large are refused, and close the connection
+    let mut stream = BufReader::new(TcpStream::connect(http_addr)?);
+    assert_eq!(
+        http_request(
+            &mut stream,
+            "PUT /keys/key3 HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nv3"
+        ),
+        (204, String::new())
+    );
+    for request in &[
+        "PUT /keys/key3 HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nv3",
+        "PUT /keys/key3 HTTP/1.1\r\nContent-Length: 2\r\nTransfer-Encoding: chunked\r\n\r\n",
+    ] {
+        let mut stream = BufReader::new(TcpStream::connect(http_addr)?);
+        assert_eq!(http_request(&mut stream, request).0, 400);
+        assert_eq!(stream.read(&mut [0u8; 1])?, 0);
+    }
+    let mut stream = BufReader::new(TcpStream::connect(http_addr)?);
+    let request = "PUT /keys/key3 HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nv\r\nffffffffffffffff\r\n";
+    assert_eq!(http_request(&mut stream, request).0, 413);
+    assert_eq!(client.get("key3".to_owned())?, Some("v3".to_owned()));
+
+    http_handle.shutdown()?;
+    handle.shutdown()
+}
+
+// Should count the requests and connections of a server and serve them in the Prometheus format
+#[test]
+fn metrics_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4038".parse().unwrap();
+    let metrics_addr: SocketAddr = "127.0.0.1:4039".parse().unwrap();
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = Arc::new(SharedQueueThreadPool::new(4)?);
+    let metrics = Arc::new(Metrics::new());
+    metrics.observe_pool(Arc::clone(&pool));
+    metrics.observe_kv_store(engine.clone());
+    let config = ServerConfig::default().metrics(Arc::clone(&metrics));
+    let handle = JsonKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+    let metrics_handle = MetricsServer::new(metrics, None).serve(metrics_addr)?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    client.set("key1".to_owned(), "value2".to_owned())?;
+    client.get("key1".to_owned())?;
+    client.remove("missing".to_owned()).unwrap_err();
+
+    let mut stream = BufReader::new(TcpStream::connect(metrics_addr)?);
+    let (status, body) = http_request(&mut stream, "GET /metrics HTTP/1.1\r\n\r\n");
+    assert_eq!(status, 200);
+    let lines: Vec<_> = body.lines().collect();
+    for line in &[
+        "kvs_requests_total{op=\"set\"} 2",
+        "kvs_requests_total{op=\"get\"} 1",
+        "kvs_requests_total{op=\"remove\"} 1",
+        "kvs_request_errors_total{op=\"set\"} 0",
+        "kvs_request_errors_total{op=\"remove\"} 1",
+        "kvs_request_duration_seconds_bucket{op=\"set\",le=\"+Inf\"} 2",
+        "kvs_request_duration_seconds_count{op=\"get\"} 1",
+        "kvs_connections 1",
+        "kvs_thread_pool_queued_jobs 0",
+        "kvs_engine_merges_total 0",
+        "# TYPE kvs_engine_garbage_bytes gauge",
+    ] {
+        assert!(lines.contains(line), "{} is missing from:\n{}", line, body);
+    }
+    let garbage = lines
+        .iter()
+        .find_map(|line| line.strip_prefix("kvs_engine_garbage_bytes "))
+        .unwrap();
+    assert!(garbage.parse::<u64>().unwrap() > 0);
+
+    assert_eq!(
+        http_request(&mut stream, "GET /other HTTP/1.1\r\n\r\n").0,
+        404
+    );
+
+    drop(client);
+    metrics_handle.shutdown()?;
+    handle.shutdown()
+}
+
+// Should only serve authenticated connections, and only on the keys that their user can access
+#[test]
+fn json_server_auth() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4042".parse().unwrap();
+    let auth = AuthConfig::new(AuthMethod::Hmac)
+        .user(User::new("admin", "admin-secret").read("").write(""))
+        .user(
+            User::new("app", "app-secret")
+                .read("app/")
+                .read("shared/")
+                .write("app/"),
+        );
+    let config = ServerConfig::default().auth(auth);
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = AutoKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    let err = client.get("app/key".to_owned()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unauthenticated);
+    let err = client.authenticate("wrong").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unauthenticated);
+
+    let mut admin = JsonKvsClient::connect_with_token(addr, "admin-secret")?;
+    for i in 0..5 {
+        admin.set(format!("a{}", i), "value".to_owned())?;
+    }
+    admin.set("shared/key".to_owned(), "value".to_owned())?;
+
+    assert_eq!(client.authenticate("app-secret")?, "app");
+    client.set("app/key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.increment("app/counter".to_owned(), 2)?, 2);
+    assert_eq!(
+        client.get("shared/key".to_owned())?,
+        Some("value".to_owned())
+    );
+    for err in [
+        client.set("shared/key".to_owned(), "other".to_owned()),
+        client.get("a0".to_owned()).map(|_| ()),
+        client.increment("shared/counter".to_owned(), 1).map(|_| ()),
+        client.multi_set(vec![
+            ("app/key2".to_owned(), "value".to_owned()),
+            ("a0".to_owned(), "other".to_owned()),
+        ]),
+    ] {
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+    let responses = client.pipeline(vec![Request::Remove {
+        key: "a0".to_owned(),
+    }])?;
+    assert!(
+        matches!(&responses[0], Response::Remove(RemoveResponse::Err(err))
+        if err.kind() == ErrorKind::PermissionDenied)
+    );
+    assert_eq!(admin.get("a0".to_owned())?, Some("value".to_owned()));
+
+    // keys that can not be read are skipped without ending the scan early
+    assert_eq!(
+        client.scan(String::new(), 2)?,
+        vec!["app/counter".to_owned(), "app/key".to_owned()]
+    );
+    assert_eq!(
+        admin.scan(String::new(), 2)?,
+        vec!["a0".to_owned(), "a1".to_owned()]
+    );
+
+    let config = FailoverConfig::default().token(Some("app-secret".to_owned()));
+    let mut failover = FailoverKvsClient::connect_with_config(vec![addr], config)?;
+    assert_eq!(
+        failover.get("app/key".to_owned())?,
+        Some("value".to_owned())
+    );
+    let config = FailoverConfig::default().token(Some("wrong".to_owned()));
+    let err = FailoverKvsClient::connect_with_config(vec![addr], config).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unauthenticated);
+
+    // the binary protocol can not authenticate
+    assert!(BinaryKvsClient::connect(addr).is_err());
+
+    drop((client, admin, failover));
+    handle.shutdown()
+}
+
+// Should send the token itself with the token method, and refuse authentication on a server
+// without users
+#[test]
+fn json_server_token_auth() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4043".parse().unwrap();
+    let auth = AuthConfig::new(AuthMethod::Token).user(User::new("app", "secret").read("app/"));
+    let config = ServerConfig::default().auth(auth);
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = JsonKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+
+    let mut client = JsonKvsClient::connect_with_token(addr, "secret")?;
+    assert_eq!(client.get("app/key".to_owned())?, None);
+    let err = client
+        .set("app/key".to_owned(), "value".to_owned())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    drop(client);
+    handle.shutdown()?;
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = JsonKvsServer::new(engine, pool, None).serve(addr)?;
+    let err = JsonKvsClient::connect_with_token(addr, "secret")
+        .map(|_| ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    handle.shutdown()
+}
+
+// Should serve JSON and binary clients on a Unix domain socket, replace a socket file that was
+// left behind and remove the file on shutdown
+#[cfg(unix)]
+#[test]
+fn unix_socket_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let socket_dir = TempDir::new().expect("unable to create temporary socket directory");
+    let path = socket_dir.path().join("kvs.sock");
+    let addr: Addr = format!("unix:{}", path.display()).parse()?;
+    assert_eq!(addr, Addr::Unix(path.clone()));
+    assert_eq!(addr.to_string(), format!("unix:{}", path.display()));
+
+    // the socket file of a server that was killed
+    drop(std::os::unix::net::UnixListener::bind(&path)?);
+    assert!(path.exists());
+
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = AutoKvsServer::new(engine, pool, None).serve(addr.clone())?;
+    assert_eq!(handle.local_addr(), addr);
+
+    access_server(&mut JsonKvsClient::connect(addr.clone())?, "json")?;
+    access_server(&mut BinaryKvsClient::connect(addr.clone())?, "binary")?;
+    let mut failover =
+        FailoverKvsClient::connect_with_config(vec![addr.clone()], FailoverConfig::default())?;
+    assert_eq!(failover.get("binary-m2".to_owned())?, Some("v2".to_owned()));
+
+    // a socket that is in use is not replaced
+    let engine = KvStore::open(socket_dir.path())?;
+    let pool = SharedQueueThreadPool::new(1)?;
+    assert!(JsonKvsServer::new(engine, pool, None)
+        .serve(addr.clone())
+        .is_err());
+
+    drop(failover);
+    handle.shutdown()?;
+    assert!(!path.exists());
+    assert!(JsonKvsClient::connect(addr).is_err());
+    Ok(())
+}
+
+/// Log output that the test can read while the server writes to it
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl LogBuffer {
+    /// Waits until `count` records were written, the loggers write in the background
+    fn records(&self, count: usize) -> Vec<serde_json::Value> {
+        let start = Instant::now();
+        loop {
+            let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            let records: Vec<serde_json::Value> = text
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            if records.len() >= count || start.elapsed() > Duration::from_secs(5) {
+                return records;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+// Should write sampled requests to the access log and requests over the threshold to the slow
+// log, with the address of the client
+#[test]
+fn json_server_request_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4044".parse().unwrap();
+    let access = LogBuffer::default();
+    let slow = LogBuffer::default();
+    let log = RequestLog::default()
+        .access(LogFormat::Json.logger(access.clone()), 1.0)
+        .slow(
+            LogFormat::Json.logger(slow.clone()),
+            Duration::from_secs(3600),
+        );
+    let config = ServerConfig::default().request_log(log);
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = JsonKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+    assert!(client.remove("missing".to_owned()).is_err());
+
+    let records = access.records(3);
+    assert_eq!(records.len(), 3);
+    let fields = |record: &serde_json::Value| {
+        (
+            record["op"].as_str().unwrap().to_owned(),
+            record["key_size"].as_u64().unwrap(),
+            record["value_size"].as_u64().unwrap(),
+            record["result"].as_str().unwrap().to_owned(),
+        )
+    };
+    assert_eq!(
+        fields(&records[0]),
+        ("set".to_owned(), 3, 5, "Ok".to_owned())
+    );
+    assert_eq!(
+        fields(&records[1]),
+        ("get".to_owned(), 3, 5, "Ok".to_owned())
+    );
+    assert_eq!(
+        fields(&records[2]),
+        ("remove".to_owned(), 7, 0, "KeyNotFound".to_owned())
+    );
+    assert!(records.iter().all(|record| record["latency_us"].is_u64()));
+    assert!(records[0]["peer_addr"]
+        .as_str()
+        .unwrap()
+        .starts_with("127.0.0.1:"));
+    assert!(slow.records(0).is_empty());
+    drop(client);
+    handle.shutdown()?;
+
+    // every request is slow, none is sampled
+    let access = LogBuffer::default();
+    let slow = LogBuffer::default();
+    let log = RequestLog::default()
+        .access(LogFormat::Json.logger(access.clone()), 0.0)
+        .slow(LogFormat::Json.logger(slow.clone()), Duration::ZERO);
+    let config = ServerConfig::default().request_log(log);
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = JsonKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    assert_eq!(
+        client.multi_get(vec!["key".to_owned(), "missing".to_owned()])?,
+        vec![Some("value".to_owned()), None]
+    );
+    let records = slow.records(1);
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["msg"], "Slow request");
+    assert_eq!(
+        fields(&records[0]),
+        ("multi_get".to_owned(), 10, 5, "Ok".to_owned())
+    );
+    assert!(access.records(0).is_empty());
+    drop(client);
+    handle.shutdown()
+}
+
+#[test]
+fn json_server_admin() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let checkpoint_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4045".parse().unwrap();
+    let level = LogLevel::new(slog::Level::Info);
+    let admin = AdminConfig::default()
+        .token("admin-secret")
+        .log_level(level.clone());
+    let config = ServerConfig::default().admin(admin);
+    let engine = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = AutoKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+
+    let mut client = JsonKvsClient::connect(addr)?;
+    let _other = JsonKvsClient::connect(addr)?;
+    for token in [None, Some("wrong")] {
+        let err = client.admin(AdminCommand::Stats, token).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    let token = Some("admin-secret");
+    for i in 0..10 {
+        client.set(format!("key{}", i), "value".to_owned())?;
+        client.set(format!("key{}", i), "other".to_owned())?;
+    }
+    client.remove("key9".to_owned())?;
+    let stat = |reply: AdminReply, name: &str| match reply {
+        AdminReply::Stats(stats) => stats.into_iter().find(|(n, _)| n == name).unwrap().1,
+        reply => panic!("expected stats, got {:?}", reply),
+    };
+    let stats = client.admin(AdminCommand::Stats, token)?;
+    assert_eq!(stat(stats.clone(), "keys"), 9);
+    assert!(stat(stats.clone(), "garbage_bytes") > 0);
+    assert_eq!(stat(stats, "connections"), 2);
+    assert_eq!(
+        client.admin(AdminCommand::Compact, token)?,
+        AdminReply::Done
+    );
+    let stats = client.admin(AdminCommand::Stats, token)?;
+    assert_eq!(stat(stats.clone(), "garbage_bytes"), 0);
+    assert_eq!(stat(stats, "merges"), 1);
+    assert_eq!(client.admin(AdminCommand::Flush, token)?, AdminReply::Done);
+
+    let path = checkpoint_dir.path().join("checkpoint");
+    let checkpoint = AdminCommand::Checkpoint {
+        path: path.to_str().unwrap().to_owned(),
+    };
+    client.admin(checkpoint.clone(), token)?;
+    client.set("key0".to_owned(), "after".to_owned())?;
+    assert!(client.admin(checkpoint, token).is_err());
+
+    let command = AdminCommand::SetLogLevel {
+        level: "debug".to_owned(),
+    };
+    client.admin(command, token)?;
+    assert_eq!(level.get(), slog::Level::Debug);
+    let command = AdminCommand::SetLogLevel {
+        level: "loud".to_owned(),
+    };
+    let err = client.admin(command, token).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidValue);
+
+    match client.admin(AdminCommand::Clients, token)? {
+        AdminReply::Clients(clients) => {
+            assert_eq!(clients.len(), 2);
+            assert!(clients[0].id < clients[1].id);
+            assert!(clients[0]
+                .peer_addr
+                .as_deref()
+                .unwrap()
+                .starts_with("127.0.0.1:"));
+        }
+        reply => panic!("expected clients, got {:?}", reply),
+    }
+    drop(client);
+    handle.shutdown()?;
+
+    // the checkpoint has the writes before it and none after it
+    let checkpoint = KvStore::open(&path)?;
+    assert_eq!(checkpoint.get("key0".to_owned())?, Some("other".to_owned()));
+    assert_eq!(checkpoint.get("key9".to_owned())?, None);
+    drop(checkpoint);
+
+    // with authentication only admins run admin requests, servers without an admin config
+    // refuse them
+    let auth = AuthConfig::new(AuthMethod::Token)
+        .user(User::new("admin", "admin-secret").admin())
+        .user(User::new("app", "app-secret").read("").write(""));
+    for (config, token, kind) in [
+        (
+            ServerConfig::default().auth(auth.clone()),
+            "admin-secret",
+            Some(ErrorKind::Unsupported),
+        ),
+        (
+            ServerConfig::default()
+                .auth(auth.clone())
+                .admin(AdminConfig::default()),
+            "app-secret",
+            Some(ErrorKind::PermissionDenied),
+        ),
+        (
+            ServerConfig::default()
+                .auth(auth)
+                .admin(AdminConfig::default()),
+            "admin-secret",
+            None,
+        ),
+    ] {
+        let engine = KvStore::open(temp_dir.path())?;
+        let pool = SharedQueueThreadPool::new(4)?;
+        let handle = JsonKvsServer::with_config(engine, pool, config, None).serve(addr)?;
+        let mut client = JsonKvsClient::connect_with_token(addr, token)?;
+        let result = client.admin(AdminCommand::Flush, None);
+        assert_eq!(result.err().map(|err| err.kind()), kind);
+        drop(client);
+        handle.shutdown()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+use kvs::engines::{WriteBatch, WriteOp};
+use kvs::networking::{JsonKvsClient, JsonKvsServer};
+use kvs::replication::{ReplicatedKvsEngine, Role};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{ErrorKind, KvStore, KvsClient, KvsEngine, KvsServer, Result, SledKvsEngine};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Polls `condition` until it holds, fails the test after a few seconds
+fn wait_until<F>(mut condition: F) -> Result<()>
+where
+    F: FnMut() -> Result<bool>,
+{
+    let start = Instant::now();
+    while !condition()? {
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "condition did not hold in time"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+// Should copy the primary's data to a replica and apply the primary's writes afterwards
+#[test]
+fn replica_follows_primary() -> Result<()> {
+    let primary_dir = TempDir::new().expect("unable to create temporary working directory");
+    let replica_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4030".parse().unwrap();
+
+    let primary = ReplicatedKvsEngine::primary(KvStore::open(primary_dir.path())?);
+    primary.set("key1".to_owned(), "value1".to_owned())?;
+    primary.set("key2".to_owned(), "value2".to_owned())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = JsonKvsServer::new(primary.clone(), pool, None).serve(addr)?;
+
+    // keys that the primary does not have are removed by the snapshot
+    let replica_engine = SledKvsEngine::open(replica_dir.path())?;
+    replica_engine.set("stale".to_owned(), "value".to_owned())?;
+    let replica = ReplicatedKvsEngine::replica_of(replica_engine, addr, None);
+    wait_until(|| Ok(replica.replication().unwrap().status().lag == Some(0)))?;
+    assert_eq!(replica.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(replica.get("stale".to_owned())?, None);
+
+    primary.remove("key1".to_owned())?;
+    primary.increment("counter".to_owned(), 5)?;
+    primary.append("key2".to_owned(), "+suffix".to_owned())?;
+    let mut batch = WriteBatch::default();
+    batch.set("key3".to_owned(), "value3".to_owned());
+    batch.set("key4".to_owned(), "value4".to_owned());
+    primary.apply_batch(batch)?;
+    wait_until(|| Ok(replica.get("key4".to_owned())?.is_some()))?;
+    assert_eq!(replica.get("key1".to_owned())?, None);
+    assert_eq!(replica.get("counter".to_owned())?, Some("5".to_owned()));
+    assert_eq!(
+        replica.get("key2".to_owned())?,
+        Some("value2+suffix".to_owned())
+    );
+    assert_eq!(replica.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    let status = replica.replication().unwrap().status();
+    assert_eq!(status.role, Role::Replica);
+    assert_eq!(status.primary, Some(addr));
+    assert!(status.connected);
+    assert_eq!(
+        status.position,
+        Some(primary.replication().unwrap().status().position.unwrap())
+    );
+
+    let err = replica
+        .set("key".to_owned(), "value".to_owned())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ReadOnly);
+
+    handle.shutdown()
+}
+
+// Should serve reads on a replica, and writes once it is promoted
+#[test]
+fn promote_replica_over_network() -> Result<()> {
+    let primary_dir = TempDir::new().expect("unable to create temporary working directory");
+    let replica_dir = TempDir::new().expect("unable to create temporary working directory");
+    let primary_addr: SocketAddr = "127.0.0.1:4031".parse().unwrap();
+    let replica_addr: SocketAddr = "127.0.0.1:4032".parse().unwrap();
+
+    let primary = ReplicatedKvsEngine::primary(KvStore::open(primary_dir.path())?);
+    let pool = SharedQueueThreadPool::new(4)?;
+    let primary_handle = JsonKvsServer::new(primary, pool, None).serve(primary_addr)?;
+    let replica =
+        ReplicatedKvsEngine::replica_of(KvStore::open(replica_dir.path())?, primary_addr, None);
+    let pool = SharedQueueThreadPool::new(4)?;
+    let replica_handle = JsonKvsServer::new(replica, pool, None).serve(replica_addr)?;
+
+    let mut primary_client = JsonKvsClient::connect(primary_addr)?;
+    let mut replica_client = JsonKvsClient::connect(replica_addr)?;
+    primary_client.set("key".to_owned(), "value".to_owned())?;
+    wait_until(|| Ok(replica_client.get("key".to_owned())?.is_some()))?;
+
+    let err = replica_client
+        .set("key".to_owned(), "other".to_owned())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ReadOnly);
+    let status = replica_client.replication_status()?;
+    assert_eq!(status.role, Role::Replica);
+    assert!(status.lag.is_some());
+
+    // the old primary is gone, the replica takes over
+    primary_handle.shutdown()?;
+    replica_client.promote()?;
+    assert_eq!(replica_client.replication_status()?.role, Role::Primary);
+    replica_client.set("key".to_owned(), "other".to_owned())?;
+    assert_eq!(
+        replica_client.get("key".to_owned())?,
+        Some("other".to_owned())
+    );
+
+    replica_handle.shutdown()
+}
+
+// Should push the committed operations on the watched keys, in the order that they were committed
+#[test]
+fn watch_key_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4040".parse().unwrap();
+
+    let engine = ReplicatedKvsEngine::primary(KvStore::open(temp_dir.path())?);
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = JsonKvsServer::new(engine, pool, None).serve(addr)?;
+
+    let mut watcher = JsonKvsClient::connect(addr)?.watch("app/".to_owned())?;
+    let mut kvs_client = JsonKvsClient::connect(addr)?;
+    kvs_client.set("app/key1".to_owned(), "value1".to_owned())?;
+    kvs_client.set("other".to_owned(), "value".to_owned())?;
+    kvs_client.increment("app/counter".to_owned(), 3)?;
+    kvs_client.multi_set(vec![
+        ("app/key2".to_owned(), "value2".to_owned()),
+        ("other".to_owned(), "value".to_owned()),
+    ])?;
+    kvs_client.remove("app/key1".to_owned())?;
+
+    let expected = vec![
+        WriteOp::Set {
+            key: "app/key1".to_owned(),
+            value: "value1".to_owned(),
+        },
+        WriteOp::Set {
+            key: "app/counter".to_owned(),
+            value: "3".to_owned(),
+        },
+        WriteOp::Set {
+            key: "app/key2".to_owned(),
+            value: "value2".to_owned(),
+        },
+        WriteOp::Remove {
+            key: "app/key1".to_owned(),
+        },
+    ];
+    let ops = watcher
+        .by_ref()
+        .take(expected.len())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(ops, expected);
+
+    // the watch ends when the server shuts down
+    handle.shutdown()?;
+    assert!(watcher.next().is_none());
+    Ok(())
+}
+
+// Should serve watchers on their own threads, so that they do not hold the threads of the pool
+#[test]
+fn watchers_do_not_hold_pool_threads() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4048".parse().unwrap();
+
+    let engine = ReplicatedKvsEngine::primary(KvStore::open(temp_dir.path())?);
+    let pool = SharedQueueThreadPool::new(1)?;
+    let handle = JsonKvsServer::new(engine, pool, None).serve(addr)?;
+
+    let mut watcher1 = JsonKvsClient::connect(addr)?.watch("key".to_owned())?;
+    let mut watcher2 = JsonKvsClient::connect(addr)?.watch("key".to_owned())?;
+    let mut kvs_client = JsonKvsClient::connect(addr)?;
+    assert_eq!(kvs_client.get("key".to_owned())?, None);
+    kvs_client.set("key".to_owned(), "value".to_owned())?;
+
+    let expected = WriteOp::Set {
+        key: "key".to_owned(),
+        value: "value".to_owned(),
+    };
+    assert_eq!(watcher1.next().transpose()?, Some(expected.clone()));
+    assert_eq!(watcher2.next().transpose()?, Some(expected));
+
+    handle.shutdown()?;
+    assert!(watcher1.next().is_none());
+    Ok(())
+}
+
+// Should refuse to watch keys on a server whose engine has no change feed
+#[test]
+fn watch_unreplicated_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4041".parse().unwrap();
+
+    let pool = SharedQueueThreadPool::new(4)?;
+    let handle = JsonKvsServer::new(KvStore::open(temp_dir.path())?, pool, None).serve(addr)?;
+
+    assert!(JsonKvsClient::connect(addr)?.watch(String::new()).is_err());
+
+    handle.shutdown()
+}
@@ -0,0 +1,128 @@
+use kvs::engines::{SledKvsEngine, SledKvsEngineConfig, WriteBatch};
+use kvs::{ErrorKind, KvsEngine, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tempfile::TempDir;
+
+// Should persist data with a custom configuration
+#[test]
+fn open_with_config() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = SledKvsEngineConfig::default()
+        .cache_capacity(1024 * 1024)
+        .compression(true)
+        .flush_every_ms(None);
+    let engine = SledKvsEngine::open_with_config(temp_dir.path(), config.clone())?;
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(engine.increment("counter".to_owned(), 3)?, 3);
+    engine.flush()?;
+
+    // Open from disk again and check persistent data
+    drop(engine);
+    let engine = SledKvsEngine::open_with_config(temp_dir.path(), config)?;
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(engine.get("counter".to_owned())?, Some("3".to_owned()));
+    drop(engine);
+
+    // Compression can not be changed after the database was created
+    let err = SledKvsEngine::open(temp_dir.path()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    Ok(())
+}
+
+// Should report invalid values instead of panicking
+#[test]
+fn invalid_utf8_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let db = sled::Config::default().path(temp_dir.path()).open()?;
+    db.insert("key1", vec![0xff, 0xfe])?;
+    db.insert("key2", "value")?;
+
+    let engine = SledKvsEngine::new(db);
+    assert_eq!(
+        engine.get("key1".to_owned()).unwrap_err().kind(),
+        ErrorKind::InvalidValue
+    );
+    assert_eq!(
+        engine.increment("key1".to_owned(), 1).unwrap_err().kind(),
+        ErrorKind::InvalidValue
+    );
+    assert_eq!(
+        engine.increment("key2".to_owned(), 1).unwrap_err().kind(),
+        ErrorKind::InvalidValue
+    );
+    assert_eq!(engine.get("key2".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// Should apply every operation of a batch or none of them
+#[test]
+fn apply_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = SledKvsEngine::open(temp_dir.path())?;
+    engine.set("key1".to_owned(), "value1".to_owned())?;
+
+    let mut batch = WriteBatch::default();
+    batch.set("key2".to_owned(), "value2".to_owned());
+    batch.remove("key1".to_owned());
+    batch.remove("missing".to_owned());
+    let err = engine.apply_batch(batch).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::KeyNotFound);
+    assert_eq!(engine.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(engine.get("key2".to_owned())?, None);
+
+    let mut batch = WriteBatch::default();
+    batch.set("key2".to_owned(), "value2".to_owned());
+    batch.remove("key1".to_owned());
+    engine.apply_batch(batch)?;
+    assert_eq!(engine.get("key1".to_owned())?, None);
+    assert_eq!(engine.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+// Should copy the data at a single point in time while writes keep coming, and refuse to
+// overwrite an existing directory
+#[test]
+fn checkpoint() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engine = SledKvsEngine::open(temp_dir.path().join("db"))?;
+    // keys in between make the copy take long enough for writes to land during it
+    for i in 0..5000 {
+        engine.set(format!("m{:04}", i), "value".to_owned())?;
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let engine = engine.clone();
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || -> Result<()> {
+            // "a" is always written first, so a copy never sees "z" ahead of it
+            while !stop.load(Ordering::SeqCst) {
+                engine.increment("a".to_owned(), 1)?;
+                engine.increment("z".to_owned(), 1)?;
+            }
+            Ok(())
+        })
+    };
+
+    for i in 0..5 {
+        let path = temp_dir.path().join(format!("checkpoint-{}", i));
+        engine.checkpoint(&path)?;
+        let copy = SledKvsEngine::open(&path)?;
+        let value = |key: &str| -> Result<i64> {
+            Ok(copy
+                .get(key.to_owned())?
+                .map_or(0, |val| val.parse().unwrap()))
+        };
+        let (a, z) = (value("a")?, value("z")?);
+        assert!(a == z || a == z + 1, "a = {}, z = {}", a, z);
+    }
+    stop.store(true, Ordering::SeqCst);
+    writer.join().unwrap()?;
+
+    let err = engine
+        .checkpoint(&temp_dir.path().join("checkpoint-0"))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Io);
+    Ok(())
+}
@@ -64,7 +64,73 @@ fn rayon_thread_pool_spawn_counter() -> Result<()> {
     spawn_counter(pool)
 }
 
+#[test]
+fn work_stealing_thread_pool_spawn_counter() -> Result<()> {
+    let pool = WorkStealingThreadPool::new(4)?;
+    spawn_counter(pool)
+}
+
 #[test]
 fn shared_queue_thread_pool_panic_task() -> Result<()> {
     spawn_panic_task::<SharedQueueThreadPool>()
 }
+
+#[test]
+fn work_stealing_thread_pool_panic_task() -> Result<()> {
+    spawn_panic_task::<WorkStealingThreadPool>()
+}
+
+// jobs that jobs spawn go to the deque of their thread, the other threads steal them
+#[test]
+fn work_stealing_thread_pool_nested_spawn() -> Result<()> {
+    const TASK_NUM: usize = 20;
+    const NESTED_NUM: usize = 100;
+
+    let pool = Arc::new(WorkStealingThreadPool::new(4)?);
+    let wg = WaitGroup::new();
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..TASK_NUM {
+        let inner = Arc::clone(&pool);
+        let counter = Arc::clone(&counter);
+        let wg = wg.clone();
+        pool.spawn(move || {
+            for _ in 0..NESTED_NUM {
+                let counter = Arc::clone(&counter);
+                let wg = wg.clone();
+                inner.spawn(move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    drop(wg);
+                });
+            }
+        })
+    }
+
+    wg.wait();
+    assert_eq!(counter.load(Ordering::SeqCst), TASK_NUM * NESTED_NUM);
+    assert_eq!(pool.queued_jobs(), Some(0));
+    Ok(())
+}
+
+// dropping the pool lets its threads finish the jobs that are queued
+#[test]
+fn work_stealing_thread_pool_drop_finishes_jobs() -> Result<()> {
+    const TASK_NUM: usize = 100;
+
+    let pool = WorkStealingThreadPool::new(2)?;
+    let wg = WaitGroup::new();
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..TASK_NUM {
+        let counter = Arc::clone(&counter);
+        let wg = wg.clone();
+        pool.spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            counter.fetch_add(1, Ordering::SeqCst);
+            drop(wg);
+        })
+    }
+    drop(pool);
+
+    wg.wait();
+    assert_eq!(counter.load(Ordering::SeqCst), TASK_NUM);
+    Ok(())
+}